@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    client::{Client, ClientSnapshot},
+    error::Error,
+};
+
+/// Complete state of all accounts, which allows to resume processing, with
+/// the state of processing the input defined by the caller, if any.
+#[derive(Debug, Deserialize, Serialize)]
+struct Checkpoint<T> {
+    clients: Vec<ClientSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<T>,
+}
+
+/// Writes the state of all accounts as JSON to the given file.
+pub fn write_checkpoint<P: AsRef<Path>>(
+    path: P,
+    clients_map: &BTreeMap<u16, Client>,
+) -> Result<(), Error> {
+    write_checkpoint_with::<_, _, ()>(path, clients_map.values(), None)
+}
+
+/// Writes the state of all accounts with the given state of processing the
+/// input (e.g. the number of processed rows) as JSON to the given file.
+///
+/// The state is written to a temporary file first and then renamed, so an
+/// interruption while writing leaves the previous checkpoint intact.
+pub fn write_checkpoint_with<'a, P, I, T>(
+    path: P,
+    clients: I,
+    progress: Option<&T>,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = &'a Client>,
+    T: Serialize,
+{
+    let checkpoint = Checkpoint {
+        clients: clients.into_iter().map(|c| c.snapshot()).collect(),
+        progress,
+    };
+    let mut tmp_path = path.as_ref().as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut file, &checkpoint)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Reads the state of all accounts from the given file.
+pub fn read_checkpoint<P: AsRef<Path>>(path: P) -> Result<BTreeMap<u16, Client>, Error> {
+    read_checkpoint_with::<_, serde::de::IgnoredAny>(path).map(|(clients_map, _)| clients_map)
+}
+
+/// Reads the state of all accounts and the state of processing the input, if
+/// it was written, from the given file.
+pub fn read_checkpoint_with<P, T>(path: P) -> Result<(BTreeMap<u16, Client>, Option<T>), Error>
+where
+    P: AsRef<Path>,
+    T: DeserializeOwned,
+{
+    let file = BufReader::new(File::open(path)?);
+    let checkpoint: Checkpoint<T> = serde_json::from_reader(file)?;
+
+    let mut clients_map = BTreeMap::new();
+    for snapshot in checkpoint.clients {
+        let client = Client::from_snapshot(snapshot)?;
+        clients_map.insert(client.id(), client);
+    }
+    Ok((clients_map, checkpoint.progress))
+}
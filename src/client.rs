@@ -1,16 +1,162 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+};
 
-use rust_decimal::Decimal;
-use serde::Serialize;
+use csv::StringRecord;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 
 use crate::{
     error::Error,
+    hash,
     transaction::{Transaction, TransactionType},
 };
 
+/// Default number of decimal places of balances in the output.
+pub const OUTPUT_SCALE: u32 = 4;
+
+/// Maximal number of references followed from a referred transaction.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Follows the chain of references starting at the given transaction,
+/// returning the transaction it ends at. `next` returns the transaction the
+/// given one refers to, if any.
+///
+/// The chain is followed iteratively and fails with [`Error::ChainTooLong`]
+/// after [`MAX_CHAIN_LENGTH`] references, so crafted input (e.g. a cycle)
+/// can't make it run unboundedly.
+fn follow_chain<F>(tx: u32, mut next: F) -> Result<u32, Error>
+where
+    F: FnMut(u32) -> Option<u32>,
+{
+    let mut current = tx;
+    let mut steps = 0;
+    while let Some(referred) = next(current) {
+        if steps == MAX_CHAIN_LENGTH {
+            return Err(Error::ChainTooLong {
+                tx,
+                limit: MAX_CHAIN_LENGTH,
+            });
+        }
+        steps += 1;
+        current = referred;
+    }
+    Ok(current)
+}
+
+/// Rounds the balance to the given number of decimal places for the output.
+///
+/// Ties are rounded to the even digit (banker's rounding), so rounding errors
+/// of many balances (e.g. in sums) don't accumulate in one direction. Balances
+/// are kept exact during processing, only the output is rounded.
+pub fn round_output(balance: Decimal, scale: u32) -> Decimal {
+    balance.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven)
+}
+
+/// Rounds the available and held funds with [`round_output`] and returns them
+/// with the total computed as their sum, so the rounded balances still satisfy
+/// `total == available + held`.
+///
+/// The rounded funds differ from the exact ones by less than one unit of the
+/// scale, so their sum can't overflow unless the total is already at the
+/// bound of [`Decimal`], in which case it saturates.
+pub fn round_balances(
+    available: Decimal,
+    held: Decimal,
+    scale: u32,
+) -> (Decimal, Decimal, Decimal) {
+    let available = round_output(available, scale);
+    let held = round_output(held, scale);
+    // Sums with zero don't keep the scale of the zero operand.
+    let mut total = available.saturating_add(held);
+    total.rescale(available.scale().max(held.scale()));
+    (available, held, total)
+}
+
+/// Types of transactions which can be disputed by default.
+pub const DEFAULT_DISPUTABLE_TYPES: [TransactionType; 2] =
+    [TransactionType::Deposit, TransactionType::Withdrawal];
+
+/// Options of making transactions on client accounts.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Treat resolves of already resolved disputes as no-ops.
+    pub idempotent_resolve: bool,
+    /// Skip deposits, withdrawals, adjustments and transfers with IDs of
+    /// already applied ones.
+    pub skip_duplicate_tx_ids: bool,
+    /// Maximal scale of balances, if limited.
+    pub max_scale: Option<ScaleLimit>,
+    /// Types of transactions which can be disputed.
+    pub disputable_types: Vec<TransactionType>,
+    /// Allow disputes, resolves and chargebacks on locked accounts.
+    pub disputes_ignore_lock: bool,
+    /// Check that held funds don't exceed total funds after each transaction.
+    pub verify_invariants: bool,
+    /// Maximal number of transactions kept in the history of each client, if
+    /// limited. The oldest ones are dropped first, except those with open
+    /// disputes, and can't be disputed anymore.
+    pub max_history: Option<usize>,
+    /// Allow disputes to specify an amount, disputing only that part of the
+    /// transaction.
+    pub partial_disputes: bool,
+    /// Chargebacks which lock the account.
+    pub lock_on_chargeback: ChargebackLock,
+    /// Reject withdrawals and outgoing transfers which would leave exactly
+    /// zero available funds.
+    pub forbid_zeroing: bool,
+    /// Maximal total funds of a client, which credits (deposits, incoming
+    /// transfers and positive adjustments) can't exceed, if limited.
+    pub max_balance: Option<Decimal>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            idempotent_resolve: false,
+            skip_duplicate_tx_ids: false,
+            max_scale: None,
+            disputable_types: DEFAULT_DISPUTABLE_TYPES.to_vec(),
+            disputes_ignore_lock: false,
+            verify_invariants: false,
+            max_history: None,
+            partial_disputes: false,
+            lock_on_chargeback: ChargebackLock::Always,
+            forbid_zeroing: false,
+            max_balance: None,
+        }
+    }
+}
+
+/// Chargebacks which lock the account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargebackLock {
+    /// Every chargeback.
+    Always,
+    /// None, chargebacks only reverse funds.
+    Never,
+    /// Chargebacks of clients which already had one.
+    Repeat,
+}
+
+/// Limit of the scale (number of decimal places) of balances.
+///
+/// Balances are rounded after each transaction, which bounds the cost of
+/// arithmetic on inputs with growing scales, but makes balances inexact. Held
+/// funds are still released and charged back by exact amounts of disputed
+/// transactions, so rounding errors can accumulate in them.
+#[derive(Clone, Copy, Debug)]
+pub struct ScaleLimit {
+    /// Maximal number of decimal places.
+    pub scale: u32,
+    /// Strategy of rounding balances exceeding the scale.
+    pub rounding: RoundingStrategy,
+}
+
 /// Account balance of a client.
-#[derive(Debug, Serialize, PartialEq)]
-pub(crate) struct Client {
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Client {
     /// Client ID.
     client: u16,
     /// Available funds.
@@ -21,47 +167,298 @@ pub(crate) struct Client {
     total: Decimal,
     /// If true, client cannot make any transactions.
     locked: bool,
+    /// Timestamp of the chargeback which locked the account, if known.
+    #[serde(skip)]
+    locked_at: Option<u64>,
     /// History of transactions (deposit, withdrawal, dispute).
     #[serde(skip)]
     transactions: BTreeMap<u32, Transaction>,
+    /// Open (not resolved or charged back) disputes with timestamps of
+    /// opening them, if known.
+    #[serde(skip)]
+    open_disputes: BTreeMap<u32, Option<u64>>,
+    /// IDs of transactions in the history in the order of saving them,
+    /// tracked only when the history is limited.
+    #[serde(skip)]
+    history_order: VecDeque<u32>,
+    /// Position in the input of the latest transaction made on the account,
+    /// used to check that transactions are applied in the input order.
+    #[serde(skip)]
+    last_seq: u64,
+    /// Number of chargebacks made on the account.
+    #[serde(skip)]
+    chargebacks: u32,
+}
+
+/// State of a client account from before a transaction, which allows to
+/// revert it.
+///
+/// A transaction changes only the balances, the lock and the state of one
+/// transaction in the history (the new one or the referred one), so only
+/// those are stored.
+#[derive(Clone, Debug)]
+pub(crate) struct UndoRecord {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    locked_at: Option<u64>,
+    chargebacks: u32,
+    last_seq: u64,
+    tx_id: u32,
+    tx: Option<Transaction>,
+    open_dispute: Option<Option<u64>>,
+}
+
+/// Complete state of a client account, including the history of
+/// transactions and disputes, which allows to resume processing.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ClientSnapshot {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    #[serde(default)]
+    locked_at: Option<u64>,
+    transactions: Vec<Transaction>,
+    /// IDs of disputed transactions.
+    disputed: Vec<u32>,
+    /// IDs of transactions with resolved disputes.
+    #[serde(default)]
+    resolved: Vec<u32>,
+    /// IDs of charged back transactions.
+    #[serde(default)]
+    charged_back: Vec<u32>,
+    /// Disputed parts of amounts of partially disputed transactions.
+    #[serde(default)]
+    disputed_parts: BTreeMap<u32, Decimal>,
+    /// Number of chargebacks made on the account.
+    #[serde(default)]
+    chargebacks: u32,
+    open_disputes: BTreeMap<u32, Option<u64>>,
+    /// IDs of transactions in the history in the order of saving them, if
+    /// the history was limited.
+    #[serde(default)]
+    history_order: Vec<u32>,
+    /// Position in the input of the latest transaction made on the account.
+    #[serde(default)]
+    last_seq: u64,
 }
 
 impl Client {
     /// Create a new client.
-    pub(crate) fn new(id: u16) -> Client {
+    pub fn new(id: u16) -> Client {
         Client {
             client: id,
             available: Decimal::new(0, 0),
             held: Decimal::new(0, 0),
             total: Decimal::new(0, 0),
             locked: false,
+            locked_at: None,
             transactions: BTreeMap::new(),
+            open_disputes: BTreeMap::new(),
+            history_order: VecDeque::new(),
+            last_seq: 0,
+            chargebacks: 0,
         }
     }
 
+    /// Create a client with the given balances and lock state, and an empty
+    /// history of transactions.
+    ///
+    /// Fails if the total funds are not the sum of available and held ones.
+    pub fn with_balances(
+        id: u16,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+    ) -> Result<Client, Error> {
+        let client = Client {
+            available,
+            held,
+            total,
+            locked,
+            ..Client::new(id)
+        };
+        if client.checked_add(available, held)? != total {
+            return Err(Error::InconsistentTotal { client: id });
+        }
+        Ok(client)
+    }
+
+    /// Create a client from a row of the report (with `client`, `available`,
+    /// `held`, `total` and `locked` columns described by `headers`), with an
+    /// empty history of transactions.
+    ///
+    /// Fails if the total funds are not the sum of available and held ones.
+    pub fn from_report_row(record: &StringRecord, headers: &StringRecord) -> Result<Client, Error> {
+        let client: Client = record.deserialize(Some(headers))?;
+        Client::with_balances(
+            client.client,
+            client.available,
+            client.held,
+            client.total,
+            client.locked,
+        )
+    }
+
+    /// Returns the client with a different ID.
+    pub fn with_id(self, id: u16) -> Client {
+        Client { client: id, ..self }
+    }
+
+    /// Client ID.
+    pub fn id(&self) -> u16 {
+        self.client
+    }
+
+    /// Available funds.
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    /// Funds held due to a dispute.
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    /// Total funds (available and held).
+    pub fn total(&self) -> Decimal {
+        self.total
+    }
+
+    /// If true, client cannot make any transactions.
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Timestamp of the chargeback which locked the account, if known.
+    pub fn locked_at(&self) -> Option<u64> {
+        self.locked_at
+    }
+
+    /// Number of transactions in the history (deposits, withdrawals,
+    /// adjustments and outgoing transfers, unless dropped by `max_history`).
+    pub fn tx_count(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Number of open (not resolved or charged back) disputes.
+    pub fn open_disputes(&self) -> usize {
+        self.open_disputes.len()
+    }
+
+    /// IDs of transactions in the history.
+    pub fn tx_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.transactions.keys().copied()
+    }
+
+    /// Returns true if the balances and the lock state of both clients are
+    /// equal.
+    pub fn same_balance(&self, other: &Client) -> bool {
+        self.available == other.available
+            && self.held == other.held
+            && self.total == other.total
+            && self.locked == other.locked
+    }
+
+    /// Returns true if held funds exceed the given ratio of total funds.
+    pub fn held_exceeds_ratio(&self, ratio: Decimal) -> bool {
+        self.total
+            .checked_mul(ratio)
+            .is_some_and(|limit| self.held > limit)
+    }
+
+    /// Short hash of the report row of the client, allowing to verify that the
+    /// row wasn't modified.
+    ///
+    /// It's a 64-bit FNV-1a hash of the `client,available,held,total,locked`
+    /// fields, formatted (and rounded to the given scale) as in the report, so
+    /// it's deterministic and doesn't depend on the order of columns in the
+    /// output.
+    pub fn checksum(&self, scale: u32) -> String {
+        let (available, held, total) = round_balances(self.available, self.held, scale);
+        let canonical = format!(
+            "{},{},{},{},{}",
+            self.client, available, held, total, self.locked
+        );
+        hash::fnv1a(canonical.as_bytes())
+    }
+
     /// Ensures that the client can make a transaction.
     ///
     /// When client's account is locked (which means they're not allowed to
     /// make a transaction)
     fn can_make_tx(&self) -> Result<(), Error> {
         if self.locked {
-            return Err(Error::ClientLocked);
+            return Err(Error::ClientLocked(self.client));
         }
         Ok(())
     }
 
+    /// Adds the amount to the given balance, failing instead of overflowing.
+    fn checked_add(&self, balance: Decimal, amount: Decimal) -> Result<Decimal, Error> {
+        balance.checked_add(amount).ok_or(Error::BalanceOverflow {
+            client: self.client,
+        })
+    }
+
+    /// Substracts the amount from the given balance, failing instead of
+    /// overflowing.
+    fn checked_sub(&self, balance: Decimal, amount: Decimal) -> Result<Decimal, Error> {
+        balance.checked_sub(amount).ok_or(Error::BalanceOverflow {
+            client: self.client,
+        })
+    }
+
     /// Saves a transaction to client's history.
     fn save_tx(&mut self, tx: Transaction) {
         self.transactions.insert(tx.tx, tx);
     }
 
+    /// Credits the given amount to the client's account, unless the total
+    /// funds would exceed `max_balance`.
+    fn credit(&mut self, amount: Decimal, options: &ClientOptions) -> Result<(), Error> {
+        if let Some(cap) = options.max_balance {
+            if self.checked_add(self.total, amount)? > cap {
+                return Err(Error::BalanceCapExceeded {
+                    client: self.client,
+                    cap,
+                });
+            }
+        }
+        self.deposit(amount)
+    }
+
     /// Credits the given amount to the client's account.
     fn deposit(&mut self, amount: Decimal) -> Result<(), Error> {
         self.can_make_tx()?;
 
-        self.available += amount;
-        self.total += amount;
+        let available = self.checked_add(self.available, amount)?;
+        let total = self.checked_add(self.total, amount)?;
+
+        self.available = available;
+        self.total = total;
+
+        Ok(())
+    }
 
+    /// Fails if debiting the given amount would leave exactly zero available
+    /// funds and it's forbidden by the options.
+    fn check_zeroing(
+        &self,
+        amount: Decimal,
+        tx_id: u32,
+        options: &ClientOptions,
+    ) -> Result<(), Error> {
+        if options.forbid_zeroing && amount == self.available {
+            return Err(Error::ZeroBalanceForbidden {
+                client: self.client,
+                tx: tx_id,
+            });
+        }
         Ok(())
     }
 
@@ -69,7 +466,7 @@ impl Client {
     fn withdraw(&mut self, amount: Decimal) -> Result<(), Error> {
         self.can_make_tx()?;
 
-        let available = self.available - amount;
+        let available = self.checked_sub(self.available, amount)?;
         if available < Decimal::new(0, 0) {
             return Err(Error::NoFunds {
                 client: self.client,
@@ -78,159 +475,708 @@ impl Client {
             });
         }
 
+        let total = self.checked_sub(self.total, amount)?;
+
         self.available = available;
-        self.total -= amount;
+        self.total = total;
 
         Ok(())
     }
 
-    /// Gets the given (disputed) transaction.
+    /// Fails if the transaction doesn't belong to the client.
+    fn check_client(&self, tx: &Transaction) -> Result<(), Error> {
+        if tx.client != self.client {
+            return Err(Error::ClientMismatch {
+                expected: self.client,
+                found: tx.client,
+            });
+        }
+        Ok(())
+    }
+
+    /// Gets the given (disputed) transaction, which has to belong to the
+    /// client.
     fn get_tx(&mut self, tx_id: u32) -> Result<&mut Transaction, Error> {
+        let client = self.client;
         let tx = self
             .transactions
             .get_mut(&tx_id)
             .ok_or(Error::TransactionNotFound(tx_id))?;
+        if tx.client != client {
+            return Err(Error::ClientMismatch {
+                expected: client,
+                found: tx.client,
+            });
+        }
         Ok(tx)
     }
 
     /// Checks whether the given transaction can be referred by a dispute,
     /// resolve or chargeback type of transaction.
     ///
-    /// That is allowed only if the referred transaction is a deposit or
-    /// withdrawal.
-    fn tx_is_referrable(&mut self, tx_id: u32) -> Result<(), Error> {
+    /// That is allowed only if the referred transaction is of one of the
+    /// disputable types (by default [`DEFAULT_DISPUTABLE_TYPES`]). References
+    /// are followed with [`follow_chain`], so the check stays bounded if
+    /// referred transactions ever refer other ones.
+    fn tx_is_referrable(
+        &mut self,
+        tx_id: u32,
+        disputable_types: &[TransactionType],
+    ) -> Result<(), Error> {
+        // Transactions in the history don't refer other ones yet, so the
+        // chain ends at the referred one.
+        let tx_id = follow_chain(tx_id, |_| None)?;
         let tx = self.get_tx(tx_id)?;
-        match tx.tx_type {
-            TransactionType::Deposit | TransactionType::Withdrawal => Ok(()),
-            _ => Err(Error::InvalidTxType(tx.tx_type.clone())),
+        if disputable_types.contains(&tx.tx_type) {
+            Ok(())
+        } else {
+            Err(Error::InvalidTxType(tx.tx_type.clone()))
         }
     }
 
-    /// Claim that the other transaction was erroneus and should be reversed.
-    fn dispute(&mut self, tx_id: u32) -> Result<(), Error> {
-        self.can_make_tx()?;
-        self.tx_is_referrable(tx_id)?;
+    /// Returns the amount of the given transaction (or its disputed part)
+    /// and whether it debited the account (withdrawals and negative
+    /// adjustments) rather than credited it.
+    fn disputed_amount(&mut self, tx_id: u32) -> Result<(Decimal, bool), Error> {
+        Client::held_amount(self.get_tx(tx_id)?)
+    }
+
+    /// Returns the amount held by a dispute of the given transaction and
+    /// whether the transaction debited the account.
+    fn held_amount(tx: &Transaction) -> Result<(Decimal, bool), Error> {
+        // Saved transactions are checked to have amounts, so only a corrupted
+        // history (e.g. from a snapshot) can lack them.
+        let amount = tx.amount.ok_or_else(|| Error::ReferredWithoutAmount {
+            tx: tx.tx,
+            tx_type: tx.tx_type.clone(),
+        })?;
+        let (amount, debit) = match tx.tx_type {
+            TransactionType::Withdrawal => (amount, true),
+            TransactionType::Adjustment if amount.is_sign_negative() => (-amount, true),
+            _ => (amount, false),
+        };
+        Ok((tx.disputed_part().unwrap_or(amount), debit))
+    }
 
+    /// Claim that the other transaction (of one of the disputable types) was
+    /// erroneus and should be reversed.
+    ///
+    /// The disputed amount is held. For a deposit, it's taken from available
+    /// funds, since they were credited by it - the dispute fails if they were
+    /// already withdrawn. For a withdrawal, the debited funds are no longer
+    /// available, so they're added to the total instead.
+    /// A disputed transaction can't be disputed again until the dispute is
+    /// resolved, so its amount is held only once.
+    ///
+    /// If `part` is given, only that part of the amount is disputed and held,
+    /// and later released or charged back.
+    fn dispute(
+        &mut self,
+        tx_id: u32,
+        part: Option<Decimal>,
+        timestamp: Option<u64>,
+        disputable_types: &[TransactionType],
+    ) -> Result<(), Error> {
+        self.tx_is_referrable(tx_id, disputable_types)?;
         let tx = self.get_tx(tx_id)?;
-        tx.dispute();
-        let amount = tx.get_amount_or_err()?;
-        self.available -= amount;
-        self.held += amount;
+        if tx.is_charged_back() {
+            return Err(Error::AlreadyChargedBack(tx_id));
+        }
+        if tx.is_disputed() {
+            return Err(Error::AlreadyDisputed(tx_id));
+        }
+
+        let (amount, debit) = self.disputed_amount(tx_id)?;
+        let amount = match part {
+            Some(part) if part > amount => {
+                return Err(Error::DisputeExceedsAmount {
+                    tx: tx_id,
+                    amount: part,
+                })
+            }
+            Some(part) => part,
+            None => amount,
+        };
+        let held = self.checked_add(self.held, amount)?;
+        let (available, total) = if debit {
+            (self.available, self.checked_add(self.total, amount)?)
+        } else {
+            let available = self.checked_sub(self.available, amount)?;
+            if available < Decimal::new(0, 0) {
+                return Err(Error::NoFunds {
+                    client: self.client,
+                    available: self.available,
+                    requested: amount,
+                });
+            }
+            (available, self.total)
+        };
+
+        match part {
+            Some(part) => self.get_tx(tx_id)?.dispute_part(part),
+            None => self.get_tx(tx_id)?.dispute(),
+        }
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        self.open_disputes.insert(tx_id, timestamp);
 
         Ok(())
     }
 
-    /// Resolve a dispute, release the associated held funds.
+    /// Resolve a dispute, release the associated held funds. The disputed
+    /// transaction stands, so funds held for a deposit are available again,
+    /// while funds held for a withdrawal are removed from the total. The
+    /// transaction is released from the dispute, so it can be disputed again.
+    ///
+    /// The transaction was referrable when it was disputed, so it's not
+    /// checked again - disputable types could change since then (e.g. between
+    /// saving and restoring a checkpoint).
     fn resolve(&mut self, tx_id: u32) -> Result<(), Error> {
-        self.can_make_tx()?;
-        self.tx_is_referrable(tx_id)?;
-
         let tx = self.get_tx(tx_id)?;
-        if !tx.is_disputed() {
+        if tx.is_charged_back() {
+            return Err(Error::AlreadyChargedBack(tx_id));
+        }
+        if !tx.is_disputed() || !self.open_disputes.contains_key(&tx_id) {
             return Err(Error::TxNotDisputed(tx_id));
         }
-        let amount = self.get_tx(tx_id)?.get_amount_or_err()?;
-        self.available += amount;
-        self.held -= amount;
+        let (amount, debit) = self.disputed_amount(tx_id)?;
+        let held = self.checked_sub(self.held, amount)?;
+        let (available, total) = if debit {
+            (self.available, self.checked_sub(self.total, amount)?)
+        } else {
+            (self.checked_add(self.available, amount)?, self.total)
+        };
+
+        self.get_tx(tx_id)?.undispute();
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        self.open_disputes.remove(&tx_id);
 
         Ok(())
     }
 
+    /// Returns true if the given transaction was disputed and the dispute was
+    /// already resolved.
+    fn dispute_resolved(&self, tx_id: u32) -> bool {
+        self.transactions
+            .get(&tx_id)
+            .is_some_and(|tx| tx.is_resolved())
+            && !self.open_disputes.contains_key(&tx_id)
+    }
+
     /// Reverse a transaction and lock the client account. Final state of a
-    /// dispute.
-    fn chargeback(&mut self, tx_id: u32) -> Result<(), Error> {
+    /// dispute - the transaction can't be disputed, resolved or charged back
+    /// anymore.
+    ///
+    /// Funds held for a deposit are removed from the total, reverting the
+    /// credit. Funds held for a withdrawal are made available, reverting the
+    /// debit.
+    fn chargeback(
+        &mut self,
+        tx_id: u32,
+        timestamp: Option<u64>,
+        lock: ChargebackLock,
+    ) -> Result<(), Error> {
         let tx = self.get_tx(tx_id)?;
-        if !tx.is_disputed() {
+        if tx.is_charged_back() {
+            return Err(Error::AlreadyChargedBack(tx_id));
+        }
+        if !tx.is_disputed() || !self.open_disputes.contains_key(&tx_id) {
             return Err(Error::TxNotDisputed(tx_id));
         }
-        // NOTE: Not sure about the implementation here. In theory chargeback
-        // should always just substract the held and total amounts, but not
-        // sure if that should happen for charging back the withdrawaals as
-        // well... For now, I'm leaving it as it is, always substracting.
-        //
-        // match tx.tx_type {
-        //     // In case of deposit transactions, we need to simply substract the
-        //     // disputed amount from held and total amount, since we are reverting
-        //     // the credit.
-        //     TransactionType::Deposit => {
-        //         let amount = tx.get_amount_or_err()?;
-        //         self.held -= amount;
-        //         self.total -= amount;
-        //     }
-        //     // In case of withdrawal transactions, we need to add the disputed
-        //     // amount to helf and total amount, since we are reverting the debit
-        //     // (reverting the previous substractionnn) and we need to compensate
-        //     // by giving the disputed amount back.
-        //     TransactionType::Withdrawal => {
-        //         let amount = tx.get_amount_or_err()?;
-        //         self.held += amount;
-        //         self.total += amount;
-        //     }
-        //     _ => {
-        //         return Err(Error::InvalidTxType(tx.tx_type.clone()));
-        //     }
-        // }
-        let amount = tx.get_amount_or_err()?;
-        self.held -= amount;
-        self.total -= amount;
-        self.locked = true;
+        let (amount, debit) = self.disputed_amount(tx_id)?;
+        let held = self.checked_sub(self.held, amount)?;
+        let (available, total) = if debit {
+            (self.checked_add(self.available, amount)?, self.total)
+        } else {
+            (self.available, self.checked_sub(self.total, amount)?)
+        };
+
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        self.get_tx(tx_id)?.charge_back();
+        let locks = match lock {
+            ChargebackLock::Always => true,
+            ChargebackLock::Never => false,
+            ChargebackLock::Repeat => self.chargebacks > 0,
+        };
+        if locks {
+            self.locked = true;
+            self.locked_at = timestamp;
+        }
+        self.chargebacks += 1;
+        self.open_disputes.remove(&tx_id);
 
         Ok(())
     }
 
+    /// Saves the complete state of the account.
+    pub fn snapshot(&self) -> ClientSnapshot {
+        ClientSnapshot {
+            client: self.client,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            locked_at: self.locked_at,
+            transactions: self.transactions.values().cloned().collect(),
+            disputed: self
+                .transactions
+                .values()
+                .filter(|tx| tx.is_disputed())
+                .map(|tx| tx.tx)
+                .collect(),
+            resolved: self
+                .transactions
+                .values()
+                .filter(|tx| tx.is_resolved())
+                .map(|tx| tx.tx)
+                .collect(),
+            charged_back: self
+                .transactions
+                .values()
+                .filter(|tx| tx.is_charged_back())
+                .map(|tx| tx.tx)
+                .collect(),
+            disputed_parts: self
+                .transactions
+                .values()
+                .filter_map(|tx| tx.disputed_part().map(|part| (tx.tx, part)))
+                .collect(),
+            chargebacks: self.chargebacks,
+            open_disputes: self.open_disputes.clone(),
+            history_order: self.history_order.iter().copied().collect(),
+            last_seq: self.last_seq,
+        }
+    }
+
+    /// Restores the complete state of the account.
+    ///
+    /// Fails if the total funds are not the sum of available and held ones.
+    pub fn from_snapshot(snapshot: ClientSnapshot) -> Result<Client, Error> {
+        let mut client = Client::with_balances(
+            snapshot.client,
+            snapshot.available,
+            snapshot.held,
+            snapshot.total,
+            snapshot.locked,
+        )?;
+        client.locked_at = snapshot.locked_at;
+        client.chargebacks = snapshot.chargebacks;
+        client.open_disputes = snapshot.open_disputes;
+        for tx in snapshot.transactions {
+            client.save_tx(tx);
+        }
+        client.history_order = snapshot.history_order.into();
+        client.last_seq = snapshot.last_seq;
+        for tx_id in snapshot.resolved {
+            client.get_tx(tx_id)?.undispute();
+        }
+        for tx_id in snapshot.disputed {
+            client.get_tx(tx_id)?.dispute();
+        }
+        for (tx_id, part) in snapshot.disputed_parts {
+            client.get_tx(tx_id)?.dispute_part(part);
+        }
+        for tx_id in snapshot.charged_back {
+            client.get_tx(tx_id)?.charge_back();
+        }
+        Ok(client)
+    }
+
+    /// Saves the current state of the account, which is going to be changed
+    /// by a transaction with the given ID.
+    pub(crate) fn undo_record(&self, tx_id: u32) -> UndoRecord {
+        UndoRecord {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            locked_at: self.locked_at,
+            chargebacks: self.chargebacks,
+            last_seq: self.last_seq,
+            tx_id,
+            tx: self.transactions.get(&tx_id).cloned(),
+            open_dispute: self.open_disputes.get(&tx_id).copied(),
+        }
+    }
+
+    /// Restores the state of the account saved before a transaction.
+    pub(crate) fn undo(&mut self, record: UndoRecord) {
+        self.available = record.available;
+        self.held = record.held;
+        self.total = record.total;
+        self.locked = record.locked;
+        self.locked_at = record.locked_at;
+        self.chargebacks = record.chargebacks;
+        self.last_seq = record.last_seq;
+        match record.tx {
+            Some(tx) => self.transactions.insert(record.tx_id, tx),
+            None => self.transactions.remove(&record.tx_id),
+        };
+        match record.open_dispute {
+            Some(timestamp) => self.open_disputes.insert(record.tx_id, timestamp),
+            None => self.open_disputes.remove(&record.tx_id),
+        };
+    }
+
     /// Makes a transaction on the given client account.
-    pub(crate) fn make_tx(&mut self, tx: Transaction) -> Result<(), Error> {
-        self.can_make_tx()?;
+    ///
+    /// Transactions on a locked account are rejected, except disputes,
+    /// resolves and chargebacks if `disputes_ignore_lock` is set. Those can
+    /// refer only to transactions made before the lock, so they continue
+    /// lifecycles which started before it.
+    ///
+    /// Transactions of a client have to be made in the order of the input,
+    /// which is checked (in debug builds) for ones with known positions.
+    pub(crate) fn make_tx(
+        &mut self,
+        tx: Transaction,
+        options: &ClientOptions,
+    ) -> Result<Effect, Error> {
+        self.check_client(&tx)?;
+        if tx.seq != 0 {
+            debug_assert!(
+                tx.seq > self.last_seq,
+                "transaction {} of client {} applied out of order",
+                tx.seq,
+                self.client
+            );
+            self.last_seq = tx.seq;
+        }
+        if !(options.disputes_ignore_lock && tx.tx_type.refers_tx()) {
+            self.can_make_tx()?;
+        }
+        let (tx_id, saved) = (tx.tx, !tx.tx_type.refers_tx());
 
         match tx.tx_type {
-            TransactionType::Deposit => match tx.amount {
-                Some(a) => {
-                    self.deposit(a)?;
-                    self.save_tx(tx);
+            TransactionType::Deposit => {
+                let amount = tx.get_positive_amount_or_err()?;
+                self.credit(amount, options)?;
+                self.save_tx(tx);
+            }
+            TransactionType::Withdrawal => {
+                let amount = tx.get_positive_amount_or_err()?;
+                self.check_zeroing(amount, tx.tx, options)?;
+                self.withdraw(amount)?;
+                self.save_tx(tx);
+            }
+            TransactionType::Adjustment => {
+                let amount = tx.get_amount_or_err()?;
+                if amount.is_sign_negative() {
+                    self.withdraw(-amount)?;
+                } else {
+                    self.credit(amount, options)?;
                 }
-                None => return Err(Error::WithoutAmount),
-            },
-            TransactionType::Withdrawal => match tx.amount {
-                Some(a) => {
-                    self.withdraw(a)?;
-                    self.save_tx(tx);
+                // Saved only to reject disputes referring to it.
+                self.save_tx(tx);
+            }
+            TransactionType::Transfer => {
+                // Only the source account is debited here, the target one is
+                // credited by `apply_transaction`.
+                tx.get_target_or_err()?;
+                let amount = tx.get_positive_amount_or_err()?;
+                self.check_zeroing(amount, tx.tx, options)?;
+                self.withdraw(amount)?;
+                // Saved only to reject disputes referring to it.
+                self.save_tx(tx);
+            }
+            TransactionType::Dispute => {
+                let part = match tx.amount {
+                    Some(_) if options.partial_disputes => Some(tx.get_positive_amount_or_err()?),
+                    _ => {
+                        tx.ensure_no_amount()?;
+                        None
+                    }
+                };
+                self.dispute(tx.tx, part, tx.timestamp, &options.disputable_types)?;
+            }
+            TransactionType::Resolve => {
+                tx.ensure_no_amount()?;
+                if options.idempotent_resolve && self.dispute_resolved(tx.tx) {
+                    return Ok(Effect::Unchanged);
                 }
-                None => return Err(Error::WithoutAmount),
-            },
-            TransactionType::Dispute => match tx.amount {
-                Some(_) => return Err(Error::WithAmount),
-                None => self.dispute(tx.tx)?,
-            },
-            TransactionType::Resolve => match tx.amount {
-                Some(_) => return Err(Error::WithAmount),
-                None => self.resolve(tx.tx)?,
-            },
-            TransactionType::Chargeback => match tx.amount {
-                Some(_) => return Err(Error::WithAmount),
-                None => self.chargeback(tx.tx)?,
-            },
+                self.resolve(tx.tx)?;
+            }
+            TransactionType::Chargeback => {
+                tx.ensure_no_amount()?;
+                self.chargeback(tx.tx, tx.timestamp, options.lock_on_chargeback)?;
+            }
+        }
+
+        if let Some(limit) = options.max_scale {
+            self.limit_scale(limit);
+        }
+        if options.verify_invariants {
+            self.verify_invariants()?;
+        }
+        if let Some(max_history) = options.max_history {
+            if saved {
+                self.history_order.push_back(tx_id);
+            }
+            self.prune_history(max_history);
+        }
+
+        Ok(Effect::Changed)
+    }
+
+    /// Drops the oldest transactions from the history, so it contains at most
+    /// `max_history` of them. Transactions with open disputes are kept, since
+    /// they are still going to be resolved or charged back, so the history
+    /// exceeds the limit only if there are more open disputes. Disputes of
+    /// dropped transactions fail with [`Error::TransactionNotFound`].
+    ///
+    /// Transactions saved while the history wasn't limited (e.g. restored
+    /// from a snapshot of such a run) are considered the oldest ones.
+    fn prune_history(&mut self, max_history: usize) {
+        let mut excess = self.transactions.len().saturating_sub(max_history);
+        if excess == 0 {
+            return;
+        }
+        if self.history_order.len() < self.transactions.len() {
+            let ordered: BTreeSet<u32> = self.history_order.iter().copied().collect();
+            let unordered: Vec<u32> = self
+                .transactions
+                .keys()
+                .copied()
+                .filter(|tx_id| !ordered.contains(tx_id))
+                .collect();
+            for tx_id in unordered.into_iter().rev() {
+                self.history_order.push_front(tx_id);
+            }
+        }
+        let transactions = &mut self.transactions;
+        let open_disputes = &self.open_disputes;
+        self.history_order.retain(|tx_id| {
+            if excess == 0 || open_disputes.contains_key(tx_id) {
+                return true;
+            }
+            // IDs of transactions which were already removed (e.g. by undo)
+            // are just forgotten.
+            if transactions.remove(tx_id).is_some() {
+                excess -= 1;
+            }
+            false
+        });
+    }
+
+    /// Credits funds transferred from another client.
+    fn receive_transfer(&mut self, amount: Decimal, options: &ClientOptions) -> Result<(), Error> {
+        self.credit(amount, options)?;
+        if let Some(limit) = options.max_scale {
+            self.limit_scale(limit);
+        }
+        Ok(())
+    }
+
+    /// Ensures that held funds don't exceed total funds - client can't have
+    /// more funds held than their account contains.
+    ///
+    /// Transactions keep it, since disputes of deposits whose funds were
+    /// already withdrawn are rejected, but it doesn't hold for inconsistent
+    /// balances the account was created with (e.g. restored from a report).
+    fn verify_invariants(&self) -> Result<(), Error> {
+        if self.held > self.total {
+            return Err(Error::InvariantViolated {
+                client: self.client,
+                held: self.held,
+                total: self.total,
+            });
         }
+        Ok(())
+    }
 
+    /// Checks that held funds are the sum of amounts held by disputed
+    /// transactions, detecting drift caused by errors in dispute arithmetic.
+    ///
+    /// It doesn't hold for accounts created with held funds (e.g. restored from
+    /// a report), or with balances rounded by [`ScaleLimit`].
+    pub fn audit(&self) -> Result<(), Error> {
+        let mut expected = Decimal::new(0, 0);
+        for tx in self.transactions.values().filter(|tx| tx.is_disputed()) {
+            let (amount, _) = Client::held_amount(tx)?;
+            expected = self.checked_add(expected, amount)?;
+        }
+        if expected != self.held {
+            return Err(Error::AuditMismatch {
+                client: self.client,
+                expected,
+                actual: self.held,
+            });
+        }
         Ok(())
     }
+
+    /// Rounds balances to the given scale. Total funds are computed from the
+    /// rounded available and held ones, so they stay consistent.
+    fn limit_scale(&mut self, limit: ScaleLimit) {
+        if self.available.scale() <= limit.scale
+            && self.held.scale() <= limit.scale
+            && self.total.scale() <= limit.scale
+        {
+            return;
+        }
+        let available = self
+            .available
+            .round_dp_with_strategy(limit.scale, limit.rounding);
+        let held = self
+            .held
+            .round_dp_with_strategy(limit.scale, limit.rounding);
+        // If the sum of rounded balances overflows, leave them unrounded.
+        if let Some(total) = available.checked_add(held) {
+            self.available = available;
+            self.held = held;
+            self.total = total;
+        }
+    }
+
+    /// Resolves disputes which were opened more than `expiry` seconds before
+    /// `now`. Returns IDs of the resolved transactions.
+    ///
+    /// Disputes on a locked account are left untouched.
+    pub fn expire_disputes(&mut self, now: u64, expiry: u64) -> Result<Vec<u32>, Error> {
+        let expired = self.expired_disputes(now, expiry);
+        for tx_id in expired.iter() {
+            self.resolve(*tx_id)?;
+        }
+
+        Ok(expired)
+    }
+
+    /// Returns IDs of transactions whose disputes were opened more than
+    /// `expiry` seconds before `now`, without resolving them.
+    ///
+    /// Disputes on a locked account never expire.
+    pub fn expired_disputes(&self, now: u64, expiry: u64) -> Vec<u32> {
+        if self.locked {
+            return Vec::new();
+        }
+
+        self.open_disputes
+            .iter()
+            .filter_map(|(tx_id, opened)| match opened {
+                Some(opened) if now.saturating_sub(*opened) > expiry => Some(*tx_id),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Serializes the report row of the client, with balances rounded with
+/// [`round_balances`] to [`OUTPUT_SCALE`] decimal places.
+impl Serialize for Client {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (available, held, total) = round_balances(self.available, self.held, OUTPUT_SCALE);
+        let mut row = serializer.serialize_struct("Client", 5)?;
+        row.serialize_field("client", &self.client)?;
+        row.serialize_field("available", &available)?;
+        row.serialize_field("held", &held)?;
+        row.serialize_field("total", &total)?;
+        row.serialize_field("locked", &self.locked)?;
+        row.end()
+    }
+}
+
+/// One-line summary of the account, with exact balances and the number of
+/// transactions in the history.
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {}: avail={} held={} total={} locked={} ({} txs)",
+            self.client,
+            self.available,
+            self.held,
+            self.total,
+            self.locked,
+            self.transactions.len()
+        )
+    }
+}
+
+/// Effect of an applied transaction on the account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Effect {
+    /// The account changed.
+    Changed,
+    /// The transaction was accepted as a no-op (e.g. a resolve of an already
+    /// resolved dispute with `idempotent_resolve`).
+    Unchanged,
+}
+
+/// Routes the transaction to the client it belongs to, creating that client if
+/// it doesn't exist yet, and makes the transaction on their account.
+///
+/// A transfer is also routed to its target client, whose account is credited
+/// after debiting the source one. If the target can't be credited, the source
+/// account is reverted, so either both accounts change or none of them.
+pub(crate) fn apply_transaction(
+    clients_map: &mut BTreeMap<u16, Client>,
+    tx: Transaction,
+    options: &ClientOptions,
+) -> Result<Effect, Error> {
+    let target = match tx.tx_type {
+        TransactionType::Transfer => {
+            let target = tx.get_target_or_err()?;
+            if let Some(target) = clients_map.get(&target) {
+                target.can_make_tx()?;
+            }
+            Some((target, tx.get_positive_amount_or_err()?))
+        }
+        _ => None,
+    };
+
+    clients_map
+        .entry(tx.client)
+        .or_insert(Client::new(tx.client));
+
+    let client = clients_map
+        .get_mut(&tx.client)
+        .ok_or(Error::ClientNotFound(tx.client))?;
+
+    let (target, amount) = match target {
+        Some(target) => target,
+        None => return client.make_tx(tx, options),
+    };
+    let (source, undo) = (tx.client, client.undo_record(tx.tx));
+    client.make_tx(tx, options)?;
+
+    let credited = clients_map
+        .entry(target)
+        .or_insert(Client::new(target))
+        .receive_transfer(amount, options);
+    if credited.is_err() {
+        if let Some(client) = clients_map.get_mut(&source) {
+            client.undo(undo);
+        }
+    }
+    credited.map(|()| Effect::Changed)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use csv::WriterBuilder;
+    use csv::{ReaderBuilder, WriterBuilder};
 
     #[test]
     fn serialize_client() {
-        let clients = vec![
+        let clients = [
             Client {
                 client: 1,
                 available: Decimal::new(15, 1),
                 held: Decimal::new(0, 0),
                 total: Decimal::new(15, 1),
                 locked: false,
+                locked_at: None,
                 transactions: BTreeMap::new(),
+                open_disputes: BTreeMap::new(),
+                history_order: VecDeque::new(),
+                last_seq: 0,
+                chargebacks: 0,
             },
             Client {
                 client: 2,
@@ -238,7 +1184,12 @@ mod tests {
                 held: Decimal::new(0, 0),
                 total: Decimal::new(2, 0),
                 locked: false,
+                locked_at: None,
                 transactions: BTreeMap::new(),
+                open_disputes: BTreeMap::new(),
+                history_order: VecDeque::new(),
+                last_seq: 0,
+                chargebacks: 0,
             },
         ];
 
@@ -259,45 +1210,765 @@ client,available,held,total,locked
     }
 
     #[test]
-    fn test_can_make_tx() {
-        let mut c = Client::new(1);
-
-        c.can_make_tx()
-            .expect("Expected client account to not be locked");
+    fn test_serialize_rounded() {
+        let mut c1 = Client::new(1);
+        c1.deposit(Decimal::new(100005, 5))
+            .expect("Failed to deposit funds");
+        let mut c2 = Client::new(2);
+        for _ in 0..3 {
+            c2.deposit(Decimal::new(100005, 5))
+                .expect("Failed to deposit funds");
+        }
 
-        c.locked = true;
+        let mut wtr = WriterBuilder::new().from_writer(vec![]);
+        for client in [&c1, &c2] {
+            wtr.serialize(client).expect("Failed to serialize client");
+        }
 
-        c.can_make_tx()
-            .expect_err("Expected client account to be locked");
+        // 1.00005 is rounded to the even 1.0000, 3.00015 to 3.0002.
+        let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "\
+client,available,held,total,locked
+1,1.0000,0,1.0000,false
+2,3.0002,0,3.0002,false
+"
+        );
+        // Balances stay exact.
+        assert_eq!(c2.available, Decimal::new(300015, 5));
     }
 
     #[test]
-    fn test_save_tx() {
+    fn test_round_output() {
         let mut c = Client::new(1);
+        for _ in 0..123_456 {
+            c.deposit(Decimal::new(1, 8))
+                .expect("Failed to deposit funds");
+        }
 
-        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0)));
-        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::new(5, 1)));
+        // Balances are exact, only the output is rounded.
+        assert_eq!(c.total, Decimal::new(123_456, 8));
+        assert_eq!(round_output(c.total, 8), Decimal::new(123_456, 8));
+        assert_eq!(round_output(c.total, OUTPUT_SCALE), Decimal::new(12, 4));
+        assert_eq!(round_output(c.total, 0), Decimal::new(0, 0));
+    }
 
-        c.save_tx(tx1.clone());
-        c.save_tx(tx2.clone());
+    #[test]
+    fn test_round_balances() {
+        let mut c = Client::new(1);
+        for tx_id in 1..=2 {
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(5, 5))),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to deposit funds");
+        }
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to dispute the deposit");
 
+        // Rounded separately, the total (0.0001) would differ from the sum of
+        // available and held funds (0.0000 each).
+        assert_eq!(round_output(c.total, OUTPUT_SCALE), Decimal::new(1, 4));
         assert_eq!(
-            *c.transactions.get(&1).expect("Failed to get a transaction"),
-            tx1
-        );
-        assert_eq!(
-            *c.transactions.get(&2).expect("Failed to get a transaction"),
-            tx2
+            round_balances(c.available, c.held, OUTPUT_SCALE),
+            (Decimal::new(0, 4), Decimal::new(0, 4), Decimal::new(0, 4))
         );
     }
 
     #[test]
-    fn test_deposit() {
-        let mut c = Client::new(1);
+    fn test_with_balances() {
+        let c = Client::with_balances(
+            1,
+            Decimal::new(15, 1),
+            Decimal::new(5, 1),
+            Decimal::new(2, 0),
+            true,
+        )
+        .expect("Failed to create a client");
+        assert_eq!(c.id(), 1);
+        assert_eq!(c.available(), Decimal::new(15, 1));
+        assert_eq!(c.held(), Decimal::new(5, 1));
+        assert_eq!(c.total(), Decimal::new(2, 0));
+        assert!(c.locked());
+        assert!(c.transactions.is_empty());
+
+        let err = Client::with_balances(
+            2,
+            Decimal::new(15, 1),
+            Decimal::new(5, 1),
+            Decimal::new(3, 0),
+            false,
+        )
+        .expect_err("Expected inconsistent total to be rejected");
+        assert_eq!(err, Error::InconsistentTotal { client: 2 });
+    }
 
-        // Deposit 2.5
-        c.deposit(Decimal::new(25, 1)).expect("Failed to deposit");
-        assert_eq!(c.available, Decimal::new(25, 1));
+    #[test]
+    fn test_from_report_row() {
+        let mut c = Client::new(1);
+        c.deposit(Decimal::new(25, 1))
+            .expect("Failed to deposit funds");
+        c.save_tx(Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Decimal::new(25, 1)),
+        ));
+        c.dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Failed to dispute a transaction");
+
+        let mut wtr = WriterBuilder::new().from_writer(vec![]);
+        wtr.serialize(&c).expect("Failed to serialize client");
+        let data = wtr.into_inner().unwrap();
+
+        let mut rdr = ReaderBuilder::new().from_reader(data.as_slice());
+        let headers = rdr.headers().expect("Failed to read headers").clone();
+        let record = rdr
+            .records()
+            .next()
+            .expect("Expected a report row")
+            .expect("Failed to read a report row");
+        let c2 = Client::from_report_row(&record, &headers).expect("Failed to read client");
+
+        assert!(c2.same_balance(&c));
+        assert_eq!(c2.id(), 1);
+        assert_eq!(c2.held(), Decimal::new(25, 1));
+        assert_eq!(c2.open_disputes(), 0);
+        assert!(c2.transactions.is_empty());
+
+        let record = StringRecord::from(vec!["1", "1.0", "0.5", "2.0", "false"]);
+        let err = Client::from_report_row(&record, &headers)
+            .expect_err("Expected inconsistent total to be rejected");
+        assert_eq!(err, Error::InconsistentTotal { client: 1 });
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let options = ClientOptions::default();
+        let mut c = Client::new(1);
+        for (tx_id, amount) in [(1, 5), (2, 3), (3, 1)] {
+            c.make_tx(
+                Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    tx_id,
+                    Some(Decimal::new(amount, 0)),
+                ),
+                &options,
+            )
+            .expect("Failed to make a transaction");
+        }
+        c.dispute(1, None, Some(10), &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Failed to dispute transaction");
+        c.dispute(2, None, None, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Failed to dispute transaction");
+        c.chargeback(2, None, ChargebackLock::Always)
+            .expect("Failed to charge back transaction");
+        assert!(c.locked);
+
+        let json = serde_json::to_string(&c.snapshot()).expect("Failed to serialize snapshot");
+        let snapshot: ClientSnapshot =
+            serde_json::from_str(&json).expect("Failed to deserialize snapshot");
+        let mut restored = Client::from_snapshot(snapshot).expect("Failed to restore client");
+
+        assert_eq!(restored, c);
+        assert!(restored.locked());
+        assert!(restored.get_tx(1).expect("Failed to get tx").is_disputed());
+        assert_eq!(restored.open_disputes.get(&1), Some(&Some(10)));
+
+        // The restored account is still locked.
+        let err = restored
+            .make_tx(
+                Transaction::new(TransactionType::Withdrawal, 1, 4, Some(Decimal::new(1, 0))),
+                &options,
+            )
+            .expect_err("Expected withdrawal from a locked account to fail");
+        assert_eq!(err, Error::ClientLocked(1));
+        assert_eq!(restored.available, Decimal::new(1, 0));
+        assert_eq!(restored.held, Decimal::new(5, 0));
+        assert_eq!(restored.total, Decimal::new(6, 0));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut c = Client::new(1);
+        c.deposit(Decimal::new(15, 1))
+            .expect("Failed to deposit funds");
+        assert_eq!(c.checksum(OUTPUT_SCALE), "0816c87bfd25f2e7");
+
+        // The checksum depends on the formatting of values, not only on
+        // values themselves.
+        let mut c2 = Client::new(1);
+        c2.deposit(Decimal::new(150, 2))
+            .expect("Failed to deposit funds");
+        assert_ne!(c2.checksum(OUTPUT_SCALE), c.checksum(OUTPUT_SCALE));
+
+        c.locked = true;
+        assert_ne!(c.checksum(OUTPUT_SCALE), "0816c87bfd25f2e7");
+    }
+
+    #[test]
+    fn test_can_make_tx() {
+        let mut c = Client::new(1);
+
+        c.can_make_tx()
+            .expect("Expected client account to not be locked");
+
+        c.locked = true;
+
+        c.can_make_tx()
+            .expect_err("Expected client account to be locked");
+    }
+
+    #[test]
+    fn test_locked_account() {
+        let mut c = Client::new(1);
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(Decimal::new(5, 0))),
+            (TransactionType::Deposit, 2, Some(Decimal::new(3, 0))),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Dispute, 2, None),
+            (TransactionType::Chargeback, 1, None),
+        ] {
+            c.make_tx(
+                Transaction::new(tx_type, 1, tx_id, amount),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to make a transaction");
+        }
+        assert!(c.locked);
+
+        // Transactions of all types are rejected, including another
+        // chargeback of the charged back transaction.
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 3, Some(Decimal::new(1, 0))),
+            (TransactionType::Withdrawal, 4, Some(Decimal::new(1, 0))),
+            (TransactionType::Adjustment, 5, Some(Decimal::new(1, 0))),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Resolve, 2, None),
+            (TransactionType::Chargeback, 1, None),
+            (TransactionType::Chargeback, 2, None),
+        ] {
+            let err = c
+                .make_tx(
+                    Transaction::new(tx_type, 1, tx_id, amount),
+                    &ClientOptions::default(),
+                )
+                .expect_err("Expected a transaction on a locked account to fail");
+            assert_eq!(err, Error::ClientLocked(1));
+        }
+        assert_eq!(c.available, Decimal::new(0, 0));
+        assert_eq!(c.held, Decimal::new(3, 0));
+        assert_eq!(c.total, Decimal::new(3, 0));
+
+        // Even if disputes ignore the lock, a transaction can't be charged
+        // back twice.
+        let options = ClientOptions {
+            disputes_ignore_lock: true,
+            ..Default::default()
+        };
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Chargeback, 1, 1, None),
+                &options,
+            )
+            .expect_err("Expected a repeated chargeback to fail");
+        assert_eq!(err, Error::AlreadyChargedBack(1));
+        assert_eq!(c.total, Decimal::new(3, 0));
+    }
+
+    #[test]
+    fn test_after_chargeback() {
+        let mut c = Client::new(1);
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(Decimal::new(5, 0))),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Chargeback, 1, None),
+        ] {
+            c.make_tx(
+                Transaction::new(tx_type, 1, tx_id, amount),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to make a transaction");
+        }
+        let tx = c.transactions.get(&1).expect("Failed to get a transaction");
+        assert!(tx.is_charged_back());
+        assert!(!tx.is_disputed());
+
+        let options = ClientOptions {
+            disputes_ignore_lock: true,
+            ..Default::default()
+        };
+        for tx_type in [TransactionType::Resolve, TransactionType::Dispute] {
+            let err = c
+                .make_tx(Transaction::new(tx_type, 1, 1, None), &options)
+                .expect_err("Expected a transaction referring a charged back one to fail");
+            assert_eq!(err, Error::AlreadyChargedBack(1));
+        }
+        assert_eq!(c.available, Decimal::new(0, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(0, 0));
+
+        // The final state survives a snapshot.
+        let mut c = Client::from_snapshot(c.snapshot()).expect("Failed to restore a client");
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Resolve, 1, 1, None),
+                &options,
+            )
+            .expect_err("Expected resolve of a charged back transaction to fail");
+        assert_eq!(err, Error::AlreadyChargedBack(1));
+    }
+
+    #[test]
+    fn test_max_balance() {
+        let options = ClientOptions {
+            max_balance: Some(Decimal::new(10, 0)),
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+        let deposit =
+            |tx_id, amount| Transaction::new(TransactionType::Deposit, 1, tx_id, Some(amount));
+
+        // Below the cap.
+        c.make_tx(deposit(1, Decimal::new(6, 0)), &options)
+            .expect("Failed to deposit below the cap");
+        // Above the cap.
+        let err = c
+            .make_tx(deposit(2, Decimal::new(41, 1)), &options)
+            .expect_err("Expected a deposit exceeding the cap to fail");
+        assert_eq!(
+            err,
+            Error::BalanceCapExceeded {
+                client: 1,
+                cap: Decimal::new(10, 0)
+            }
+        );
+        assert!(err.is_ignorable());
+        assert_eq!(c.available, Decimal::new(6, 0));
+        assert_eq!(c.total, Decimal::new(6, 0));
+        assert!(!c.transactions.contains_key(&2));
+        // At the cap.
+        c.make_tx(deposit(3, Decimal::new(4, 0)), &options)
+            .expect("Failed to deposit up to the cap");
+        assert_eq!(c.total, Decimal::new(10, 0));
+
+        let err = c
+            .make_tx(deposit(4, Decimal::new(1, 4)), &options)
+            .expect_err("Expected a deposit exceeding the cap to fail");
+        assert!(matches!(err, Error::BalanceCapExceeded { .. }));
+        assert_eq!(c.total, Decimal::new(10, 0));
+    }
+
+    #[test]
+    fn test_max_balance_adjustment() {
+        let options = ClientOptions {
+            max_balance: Some(Decimal::new(10, 0)),
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+        let adjustment =
+            |tx_id, amount| Transaction::new(TransactionType::Adjustment, 1, tx_id, Some(amount));
+
+        c.make_tx(adjustment(1, Decimal::new(5, 0)), &options)
+            .expect("Failed to credit below the cap");
+        let err = c
+            .make_tx(adjustment(2, Decimal::new(100, 0)), &options)
+            .expect_err("Expected a credit exceeding the cap to fail");
+        assert!(matches!(err, Error::BalanceCapExceeded { client: 1, .. }));
+        assert_eq!(c.total, Decimal::new(5, 0));
+
+        // Debits are never capped.
+        c.make_tx(adjustment(3, Decimal::new(-1, 0)), &options)
+            .expect("Failed to debit");
+        assert_eq!(c.total, Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn test_max_balance_transfer() {
+        let options = ClientOptions {
+            max_balance: Some(Decimal::new(10, 0)),
+            ..Default::default()
+        };
+        let mut clients_map = with_deposits(&[(1, 1, 10), (2, 2, 10)]);
+
+        // The source account is reverted if the target would exceed the cap.
+        let err = apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Decimal::new(10, 0)))
+                .with_target(2),
+            &options,
+        )
+        .expect_err("Expected transfer exceeding the cap of the target to fail");
+        assert_eq!(
+            err,
+            Error::BalanceCapExceeded {
+                client: 2,
+                cap: Decimal::new(10, 0)
+            }
+        );
+        let c1 = clients_map.get(&1).expect("Failed to get a client");
+        assert_eq!(c1.total, Decimal::new(10, 0));
+        assert_eq!(c1.tx_ids().collect::<Vec<_>>(), vec![1]);
+        let c2 = clients_map.get(&2).expect("Failed to get a client");
+        assert_eq!(c2.total, Decimal::new(10, 0));
+    }
+
+    #[test]
+    fn test_forbid_zeroing() {
+        let forbid_zeroing = ClientOptions {
+            forbid_zeroing: true,
+            ..Default::default()
+        };
+        for (options, zeroed) in [(ClientOptions::default(), true), (forbid_zeroing, false)] {
+            let mut c = Client::new(1);
+            for (tx_id, amount) in [(1, Decimal::new(5, 0)), (2, Decimal::new(2, 0))] {
+                c.make_tx(
+                    Transaction::new(TransactionType::Deposit, 1, tx_id, Some(amount)),
+                    &options,
+                )
+                .expect("Failed to make a deposit");
+            }
+            c.make_tx(
+                Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Decimal::new(4, 0))),
+                &options,
+            )
+            .expect("Failed to withdraw part of the balance");
+
+            let result = c.make_tx(
+                Transaction::new(TransactionType::Withdrawal, 1, 4, Some(Decimal::new(3, 0))),
+                &options,
+            );
+            if zeroed {
+                result.expect("Failed to withdraw the whole balance");
+                assert_eq!(c.available, Decimal::new(0, 0));
+            } else {
+                let err = result.expect_err("Expected zeroing the balance to fail");
+                assert_eq!(err, Error::ZeroBalanceForbidden { client: 1, tx: 4 });
+                assert!(err.is_ignorable());
+                assert_eq!(c.available, Decimal::new(3, 0));
+                assert_eq!(c.total, Decimal::new(3, 0));
+
+                // The same applies to outgoing transfers.
+                let err = c
+                    .make_tx(
+                        Transaction::new(TransactionType::Transfer, 1, 5, Some(Decimal::new(3, 0)))
+                            .with_target(2),
+                        &options,
+                    )
+                    .expect_err("Expected zeroing the balance with a transfer to fail");
+                assert_eq!(err, Error::ZeroBalanceForbidden { client: 1, tx: 5 });
+                assert_eq!(c.available, Decimal::new(3, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_audit() {
+        let options = ClientOptions {
+            partial_disputes: true,
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(Decimal::new(10, 0))),
+            (TransactionType::Deposit, 2, Some(Decimal::new(5, 0))),
+            (TransactionType::Withdrawal, 3, Some(Decimal::new(3, 0))),
+            (TransactionType::Dispute, 1, Some(Decimal::new(4, 0))),
+            (TransactionType::Dispute, 3, None),
+            (TransactionType::Dispute, 2, None),
+            (TransactionType::Resolve, 2, None),
+        ] {
+            c.make_tx(Transaction::new(tx_type, 1, tx_id, amount), &options)
+                .expect("Failed to make a transaction");
+            c.audit().expect("Failed to audit the account");
+        }
+        assert_eq!(c.held, Decimal::new(7, 0));
+
+        c.held += Decimal::new(1, 0);
+        let err = c
+            .audit()
+            .expect_err("Expected the audit of corrupted held funds to fail");
+        assert_eq!(
+            err,
+            Error::AuditMismatch {
+                client: 1,
+                expected: Decimal::new(7, 0),
+                actual: Decimal::new(8, 0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let mut c = Client::new(1);
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Decimal::new(10, 1)),
+            (TransactionType::Deposit, 2, Decimal::new(20, 1)),
+            (TransactionType::Withdrawal, 3, Decimal::new(15, 1)),
+        ] {
+            c.make_tx(
+                Transaction::new(tx_type, 1, tx_id, Some(amount)),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to make a transaction");
+        }
+        assert_eq!(
+            c.to_string(),
+            "client 1: avail=1.5 held=0 total=1.5 locked=false (3 txs)"
+        );
+    }
+
+    #[test]
+    fn test_without_amount() {
+        let mut c = Client::new(1);
+
+        // A deposit without amount is malformed.
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Deposit, 1, 1, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected a deposit without amount to fail");
+        assert_eq!(
+            err,
+            Error::WithoutAmount {
+                tx: 1,
+                tx_type: TransactionType::Deposit
+            }
+        );
+        assert!(!err.is_ignorable());
+
+        // A transaction referring to a saved one without amount (e.g. from
+        // a corrupted snapshot) is skipped.
+        c.save_tx(Transaction::new(TransactionType::Deposit, 1, 1, None));
+        let referred_without_amount = Error::ReferredWithoutAmount {
+            tx: 1,
+            tx_type: TransactionType::Deposit,
+        };
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected a dispute of a deposit without amount to fail");
+        assert_eq!(err, referred_without_amount);
+        assert!(err.is_ignorable());
+
+        c.get_tx(1).expect("Failed to get a transaction").dispute();
+        c.open_disputes.insert(1, None);
+        for tx_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+            let err = c
+                .make_tx(
+                    Transaction::new(tx_type, 1, 1, None),
+                    &ClientOptions::default(),
+                )
+                .expect_err("Expected a transaction referring a deposit without amount to fail");
+            assert_eq!(err, referred_without_amount);
+            assert!(err.is_ignorable());
+        }
+        assert_eq!(c.total, Decimal::new(0, 0));
+        assert!(!c.locked);
+    }
+
+    #[test]
+    fn test_lock_on_chargeback() {
+        // Whether the account is locked after the first and the second
+        // chargeback.
+        for (lock, locked) in [
+            (ChargebackLock::Always, [true, true]),
+            (ChargebackLock::Never, [false, false]),
+            (ChargebackLock::Repeat, [false, true]),
+        ] {
+            let options = ClientOptions {
+                lock_on_chargeback: lock,
+                disputes_ignore_lock: true,
+                ..Default::default()
+            };
+            let mut c = Client::new(1);
+            for tx_id in [1, 2] {
+                c.make_tx(
+                    Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(5, 0))),
+                    &options,
+                )
+                .expect("Failed to make a deposit");
+            }
+            for (tx_id, locked) in [1, 2].into_iter().zip(locked) {
+                for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                    c.make_tx(Transaction::new(tx_type, 1, tx_id, None), &options)
+                        .expect("Failed to make a transaction");
+                }
+                assert_eq!(c.locked, locked, "{:?}", lock);
+                assert_eq!(c.chargebacks, tx_id);
+
+                // The count of chargebacks survives a snapshot.
+                c = Client::from_snapshot(c.snapshot()).expect("Failed to restore a client");
+            }
+            assert_eq!(c.available, Decimal::new(0, 0));
+            assert_eq!(c.total, Decimal::new(0, 0));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "transaction 2 of client 1 applied out of order")]
+    fn test_out_of_order() {
+        let mut c = Client::new(1);
+        for (tx_id, seq) in [(1, 1), (2, 3), (3, 2)] {
+            let _ = c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(1, 0)))
+                    .with_seq(seq),
+                &ClientOptions::default(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let options = ClientOptions {
+            partial_disputes: true,
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+        for tx in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(10, 0))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(5, 0))),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Decimal::new(3, 0))),
+        ] {
+            c.make_tx(tx, &options)
+                .expect("Failed to make a transaction");
+        }
+
+        let partial_dispute =
+            |tx_id, amount| Transaction::new(TransactionType::Dispute, 1, tx_id, Some(amount));
+
+        // Without the option, disputes can't specify an amount.
+        let err = c
+            .make_tx(
+                partial_dispute(1, Decimal::new(4, 0)),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected a dispute with amount to fail");
+        assert!(matches!(err, Error::WithAmount { tx: 1, .. }));
+
+        let err = c
+            .make_tx(partial_dispute(1, Decimal::new(11, 0)), &options)
+            .expect_err("Expected a dispute exceeding the amount to fail");
+        assert_eq!(
+            err,
+            Error::DisputeExceedsAmount {
+                tx: 1,
+                amount: Decimal::new(11, 0)
+            }
+        );
+        assert!(err.is_ignorable());
+
+        c.make_tx(partial_dispute(1, Decimal::new(4, 0)), &options)
+            .expect("Failed to dispute part of a deposit");
+        assert_eq!(c.available, Decimal::new(8, 0));
+        assert_eq!(c.held, Decimal::new(4, 0));
+        assert_eq!(c.total, Decimal::new(12, 0));
+
+        // The disputed part survives a snapshot.
+        let mut c = Client::from_snapshot(c.snapshot()).expect("Failed to restore a client");
+        assert_eq!(c.transactions[&1].disputed_part(), Some(Decimal::new(4, 0)));
+
+        // Only the disputed part is released.
+        c.make_tx(
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            &options,
+        )
+        .expect("Failed to resolve a partial dispute");
+        assert_eq!(c.available, Decimal::new(12, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(12, 0));
+        assert_eq!(c.transactions[&1].disputed_part(), None);
+
+        // A partial dispute of a withdrawal is charged back by its part.
+        c.make_tx(partial_dispute(3, Decimal::new(1, 0)), &options)
+            .expect("Failed to dispute part of a withdrawal");
+        c.make_tx(
+            Transaction::new(TransactionType::Chargeback, 1, 3, None),
+            &options,
+        )
+        .expect("Failed to charge back a partial dispute");
+        assert_eq!(c.available, Decimal::new(13, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(13, 0));
+        assert!(c.locked);
+    }
+
+    #[test]
+    fn test_save_tx() {
+        let mut c = Client::new(1);
+
+        let tx1 = Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0)));
+        let tx2 = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::new(5, 1)));
+
+        c.save_tx(tx1.clone());
+        c.save_tx(tx2.clone());
+
+        assert_eq!(
+            *c.transactions.get(&1).expect("Failed to get a transaction"),
+            tx1
+        );
+        assert_eq!(
+            *c.transactions.get(&2).expect("Failed to get a transaction"),
+            tx2
+        );
+    }
+
+    #[test]
+    fn test_client_mismatch() {
+        let mut c = Client::new(1);
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Deposit, 2, 1, Some(Decimal::new(1, 0))),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected a transaction of another client to fail");
+        assert!(matches!(
+            err,
+            Error::ClientMismatch {
+                expected: 1,
+                found: 2
+            }
+        ));
+        assert_eq!(c.total, Decimal::new(0, 0));
+
+        // Referred transaction of another client.
+        c.save_tx(Transaction::new(
+            TransactionType::Deposit,
+            2,
+            2,
+            Some(Decimal::new(1, 0)),
+        ));
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 2, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected dispute of a transaction of another client to fail");
+        assert!(matches!(
+            err,
+            Error::ClientMismatch {
+                expected: 1,
+                found: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn test_deposit() {
+        let mut c = Client::new(1);
+
+        // Deposit 2.5
+        c.deposit(Decimal::new(25, 1)).expect("Failed to deposit");
+        assert_eq!(c.available, Decimal::new(25, 1));
         assert_eq!(c.held, Decimal::new(0, 0));
         assert_eq!(c.total, Decimal::new(25, 1));
 
@@ -376,35 +2047,56 @@ client,available,held,total,locked
         c.save_tx(Transaction::new(TransactionType::Resolve, 1, 4, None));
         c.save_tx(Transaction::new(TransactionType::Chargeback, 1, 5, None));
 
-        c.tx_is_referrable(1).expect("Expected tx to be referrable");
-        c.tx_is_referrable(2).expect("Expected tx to be referrable");
+        c.tx_is_referrable(1, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Expected tx to be referrable");
+        c.tx_is_referrable(2, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Expected tx to be referrable");
 
-        c.tx_is_referrable(3)
+        c.tx_is_referrable(3, &DEFAULT_DISPUTABLE_TYPES)
             .expect_err("Expected tx to be not referrable");
-        c.tx_is_referrable(4)
+        c.tx_is_referrable(4, &DEFAULT_DISPUTABLE_TYPES)
             .expect_err("Expected tx to be not referrable");
-        c.tx_is_referrable(5)
+        c.tx_is_referrable(5, &DEFAULT_DISPUTABLE_TYPES)
             .expect_err("Expected tx to be not referrable");
     }
 
+    #[test]
+    fn test_follow_chain() {
+        // Each transaction refers to the preceding one.
+        let next = |tx: u32| tx.checked_sub(1);
+        assert_eq!(follow_chain(MAX_CHAIN_LENGTH as u32, next), Ok(0));
+        assert_eq!(
+            follow_chain(MAX_CHAIN_LENGTH as u32 + 1, next),
+            Err(Error::ChainTooLong {
+                tx: MAX_CHAIN_LENGTH as u32 + 1,
+                limit: MAX_CHAIN_LENGTH,
+            })
+        );
+
+        // Cycles are cut by the limit too.
+        let err = follow_chain(1, |tx| Some(tx % 2 + 1))
+            .expect_err("Expected a cycle of references to fail");
+        assert!(matches!(err, Error::ChainTooLong { tx: 1, .. }));
+        assert!(err.is_ignorable());
+    }
+
     #[test]
     fn test_dispute_resolve() {
         // Dispute and resolve the only first deposit.
         {
             let mut c = Client::new(1);
 
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                1,
-                1,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
 
             c.resolve(1)
                 .expect_err("Expected resolving a transaction not under dispute to fail");
 
-            c.dispute(1).expect("Failed to dispute transaction");
+            c.dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+                .expect("Failed to dispute transaction");
 
             assert_eq!(c.available, Decimal::new(0, 0));
             assert_eq!(c.held, Decimal::new(25, 1));
@@ -420,26 +2112,23 @@ client,available,held,total,locked
         {
             let mut c = Client::new(2);
 
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                2,
-                1,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 2, 1, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                2,
-                2,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 2, 2, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
 
             assert_eq!(c.available, Decimal::new(5, 0));
             assert_eq!(c.held, Decimal::new(0, 0));
             assert_eq!(c.total, Decimal::new(5, 0));
 
-            c.dispute(2).expect("Failed to dispute transaction");
+            c.dispute(2, None, None, &DEFAULT_DISPUTABLE_TYPES)
+                .expect("Failed to dispute transaction");
 
             assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(25, 1));
@@ -455,63 +2144,179 @@ client,available,held,total,locked
         {
             let mut c = Client::new(3);
 
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                3,
-                1,
-                Some(Decimal::new(5, 0)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 3, 1, Some(Decimal::new(5, 0))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
-            c.make_tx(Transaction::new(
-                TransactionType::Withdrawal,
-                3,
-                2,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Withdrawal, 3, 2, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
 
             assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(0, 0));
             assert_eq!(c.total, Decimal::new(25, 1));
 
-            c.dispute(2).expect("Failed to dispute transaction");
+            c.dispute(2, None, None, &DEFAULT_DISPUTABLE_TYPES)
+                .expect("Failed to dispute transaction");
 
-            assert_eq!(c.available, Decimal::new(0, 0));
+            // The debited funds are held, available ones are untouched.
+            assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(25, 1));
-            assert_eq!(c.total, Decimal::new(25, 1));
+            assert_eq!(c.total, Decimal::new(5, 0));
 
             c.resolve(2).expect("Failed to resolve transaction");
 
+            // The withdrawal stands.
             assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(0, 0));
             assert_eq!(c.total, Decimal::new(25, 1));
         }
     }
 
+    #[test]
+    fn test_dispute_twice() {
+        let mut c = Client::new(1);
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
+
+        c.dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Failed to dispute transaction");
+        let err = c
+            .dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+            .expect_err("Expected disputing a disputed transaction to fail");
+        assert_eq!(err, Error::AlreadyDisputed(1));
+
+        assert_eq!(c.available, Decimal::new(0, 0));
+        assert_eq!(c.held, Decimal::new(5, 0));
+        assert_eq!(c.total, Decimal::new(5, 0));
+    }
+
+    #[test]
+    fn test_dispute_after_resolve() {
+        let mut c = Client::new(1);
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
+
+        c.dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Failed to dispute transaction");
+        c.resolve(1).expect("Failed to resolve transaction");
+        assert!(!c.get_tx(1).expect("Failed to get tx").is_disputed());
+        c.resolve(1)
+            .expect_err("Expected resolving a resolved transaction to fail");
+
+        // The resolved state survives a checkpoint.
+        let mut c = Client::from_snapshot(c.snapshot()).expect("Failed to restore client");
+        assert!(c.dispute_resolved(1));
+
+        c.dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+            .expect("Failed to dispute a resolved transaction");
+        assert_eq!(c.available, Decimal::new(0, 0));
+        assert_eq!(c.held, Decimal::new(5, 0));
+        assert_eq!(c.total, Decimal::new(5, 0));
+
+        c.chargeback(1, None, ChargebackLock::Always)
+            .expect("Failed to charge back transaction");
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(0, 0));
+        assert!(c.locked);
+    }
+
+    #[test]
+    fn test_idempotent_resolve() {
+        let strict = ClientOptions::default();
+        let idempotent = ClientOptions {
+            idempotent_resolve: true,
+            ..Default::default()
+        };
+
+        let mut c = Client::new(1);
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(25, 1))),
+            &strict,
+        )
+        .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(1, 0))),
+            &strict,
+        )
+        .expect("Failed to make a transaction");
+
+        // Never disputed transaction can't be resolved in either mode.
+        for options in [&strict, &idempotent] {
+            let err = c
+                .make_tx(
+                    Transaction::new(TransactionType::Resolve, 1, 2, None),
+                    options,
+                )
+                .expect_err("Expected resolving a never disputed transaction to fail");
+            assert_eq!(err, Error::TxNotDisputed(2));
+        }
+
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            &strict,
+        )
+        .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            &strict,
+        )
+        .expect("Failed to make a transaction");
+
+        // Already resolved dispute can't be resolved again in the strict mode.
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Resolve, 1, 1, None),
+                &strict,
+            )
+            .expect_err("Expected resolving an already resolved dispute to fail");
+        assert_eq!(err, Error::TxNotDisputed(1));
+
+        // With idempotent resolves, it's a no-op.
+        c.make_tx(
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            &idempotent,
+        )
+        .expect("Failed to make a transaction");
+
+        assert_eq!(c.available, Decimal::new(35, 1));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(35, 1));
+    }
+
     #[test]
     fn test_dispute_chargeback() {
         // Dispute and chargeback the only first deposit.
         {
             let mut c = Client::new(1);
 
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                1,
-                1,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
 
-            c.chargeback(1)
+            c.chargeback(1, None, ChargebackLock::Always)
                 .expect_err("Expected chargeback of a transaction not under dispute to fail");
 
-            c.dispute(1).expect("Failed to dispute transaction");
+            c.dispute(1, None, None, &DEFAULT_DISPUTABLE_TYPES)
+                .expect("Failed to dispute transaction");
 
             assert_eq!(c.available, Decimal::new(0, 0));
             assert_eq!(c.held, Decimal::new(25, 1));
             assert_eq!(c.total, Decimal::new(25, 1));
 
-            c.chargeback(1).expect("Failed to resolve transaction");
+            c.chargeback(1, None, ChargebackLock::Always)
+                .expect("Failed to resolve transaction");
 
             assert_eq!(c.available, Decimal::new(0, 0));
             assert_eq!(c.held, Decimal::new(0, 0));
@@ -521,32 +2326,30 @@ client,available,held,total,locked
         {
             let mut c = Client::new(2);
 
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                2,
-                1,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 2, 1, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                2,
-                2,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 2, 2, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
 
             assert_eq!(c.available, Decimal::new(5, 0));
             assert_eq!(c.held, Decimal::new(0, 0));
             assert_eq!(c.total, Decimal::new(5, 0));
 
-            c.dispute(2).expect("Failed to dispute transaction");
+            c.dispute(2, None, None, &DEFAULT_DISPUTABLE_TYPES)
+                .expect("Failed to dispute transaction");
 
             assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(25, 1));
             assert_eq!(c.total, Decimal::new(5, 0));
 
-            c.chargeback(2).expect("Failed to resolve transaction");
+            c.chargeback(2, None, ChargebackLock::Always)
+                .expect("Failed to resolve transaction");
 
             assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(0, 0));
@@ -556,40 +2359,36 @@ client,available,held,total,locked
         {
             let mut c = Client::new(3);
 
-            c.make_tx(Transaction::new(
-                TransactionType::Deposit,
-                3,
-                1,
-                Some(Decimal::new(5, 0)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 3, 1, Some(Decimal::new(5, 0))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
-            c.make_tx(Transaction::new(
-                TransactionType::Withdrawal,
-                3,
-                2,
-                Some(Decimal::new(25, 1)),
-            ))
+            c.make_tx(
+                Transaction::new(TransactionType::Withdrawal, 3, 2, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
 
             assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(0, 0));
             assert_eq!(c.total, Decimal::new(25, 1));
 
-            c.dispute(2).expect("Failed to dispute transaction");
+            c.dispute(2, None, None, &DEFAULT_DISPUTABLE_TYPES)
+                .expect("Failed to dispute transaction");
 
-            assert_eq!(c.available, Decimal::new(0, 0));
+            // The debited funds are held, available ones are untouched.
+            assert_eq!(c.available, Decimal::new(25, 1));
             assert_eq!(c.held, Decimal::new(25, 1));
-            assert_eq!(c.total, Decimal::new(25, 1));
-
-            c.chargeback(2).expect("Failed to resolve transaction");
+            assert_eq!(c.total, Decimal::new(5, 0));
 
-            // assert_eq!(c.available, Decimal::new(0, 0));
-            // assert_eq!(c.held, Decimal::new(5, 0));
-            // assert_eq!(c.total, Decimal::new(5, 0));
+            c.chargeback(2, None, ChargebackLock::Always)
+                .expect("Failed to resolve transaction");
 
-            assert_eq!(c.available, Decimal::new(0, 0));
+            // The withdrawal is reverted, the funds are available again.
+            assert_eq!(c.available, Decimal::new(5, 0));
             assert_eq!(c.held, Decimal::new(0, 0));
-            assert_eq!(c.total, Decimal::new(0, 0));
+            assert_eq!(c.total, Decimal::new(5, 0));
         }
     }
 
@@ -598,87 +2397,891 @@ client,available,held,total,locked
         let mut c = Client::new(1);
 
         // Make some deposits.
-        c.make_tx(Transaction::new(
-            TransactionType::Deposit,
-            1,
-            1,
-            Some(Decimal::new(26, 1)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(26, 1))),
+            &ClientOptions::default(),
+        )
         .expect("Failed to make a transaction");
-        c.make_tx(Transaction::new(
-            TransactionType::Deposit,
-            1,
-            2,
-            Some(Decimal::new(53, 1)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(53, 1))),
+            &ClientOptions::default(),
+        )
         .expect("Failed to make a transaction");
-        c.make_tx(Transaction::new(
-            TransactionType::Deposit,
-            1,
-            3,
-            Some(Decimal::new(41, 1)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 3, Some(Decimal::new(41, 1))),
+            &ClientOptions::default(),
+        )
         .expect("Failed to make a transaction");
 
         // Try to make a faulty deposit without amount.
-        c.make_tx(Transaction::new(TransactionType::Deposit, 1, 4, None))
-            .expect_err("Expected deposit without amount to fail");
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 4, None),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected deposit without amount to fail");
 
         // Make a withdrawal.
-        c.make_tx(Transaction::new(
-            TransactionType::Withdrawal,
-            1,
-            5,
-            Some(Decimal::new(13, 1)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Withdrawal, 1, 5, Some(Decimal::new(13, 1))),
+            &ClientOptions::default(),
+        )
         .expect("Failed to make a transaction");
         // Try to make faulty withdrawals.
-        c.make_tx(Transaction::new(TransactionType::Withdrawal, 1, 6, None))
-            .expect_err("Expected withdrawal without amount to fail");
-        c.make_tx(Transaction::new(
-            TransactionType::Withdrawal,
-            1,
-            7,
-            Some(Decimal::new(9001, 0)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Withdrawal, 1, 6, None),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected withdrawal without amount to fail");
+        c.make_tx(
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                7,
+                Some(Decimal::new(9001, 0)),
+            ),
+            &ClientOptions::default(),
+        )
         .expect_err("Expected withdrawal to fail due to insufficient funds");
 
         // Try to make a faulty dispute.
-        c.make_tx(Transaction::new(
-            TransactionType::Dispute,
-            1,
-            1,
-            Some(Decimal::new(1, 0)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 1, Some(Decimal::new(1, 0))),
+            &ClientOptions::default(),
+        )
         .expect_err("Expected dispute with provided amount to fail");
         // Make correct disputes.
-        c.make_tx(Transaction::new(TransactionType::Dispute, 1, 1, None))
-            .expect("Failed to make a transaction");
-        c.make_tx(Transaction::new(TransactionType::Dispute, 1, 2, None))
-            .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
 
         // Try to make a faulty resolve transaction.
-        c.make_tx(Transaction::new(
-            TransactionType::Resolve,
-            1,
-            1,
-            Some(Decimal::new(26, 1)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Resolve, 1, 1, Some(Decimal::new(26, 1))),
+            &ClientOptions::default(),
+        )
         .expect_err("Expected resolve transaction with provided amounnt to fail");
         // Make a correct resolve transaction.
-        c.make_tx(Transaction::new(TransactionType::Resolve, 1, 1, None))
-            .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
 
         // Try to make a faulty chargeback transaction.
-        c.make_tx(Transaction::new(
-            TransactionType::Chargeback,
-            1,
-            2,
-            Some(Decimal::new(26, 1)),
-        ))
+        c.make_tx(
+            Transaction::new(TransactionType::Chargeback, 1, 2, Some(Decimal::new(26, 1))),
+            &ClientOptions::default(),
+        )
         .expect_err("Expected chargeback with provided amount to fail");
         // Make a correct chargeback transaction.
-        c.make_tx(Transaction::new(TransactionType::Chargeback, 1, 2, None))
+        c.make_tx(
+            Transaction::new(TransactionType::Chargeback, 1, 2, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
+    }
+
+    #[test]
+    fn test_adjustment() {
+        let options = ClientOptions::default();
+        let mut c = Client::new(1);
+
+        c.make_tx(
+            Transaction::new(TransactionType::Adjustment, 1, 1, Some(Decimal::new(25, 1))),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+        assert_eq!(c.available, Decimal::new(25, 1));
+        assert_eq!(c.total, Decimal::new(25, 1));
+
+        c.make_tx(
+            Transaction::new(TransactionType::Adjustment, 1, 2, Some(Decimal::new(-1, 0))),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+        assert_eq!(c.available, Decimal::new(15, 1));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(15, 1));
+
+        // Debit exceeding the available funds.
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Adjustment, 1, 3, Some(Decimal::new(-2, 0))),
+                &options,
+            )
+            .expect_err("Expected client account to have insufficient funds");
+        assert!(matches!(err, Error::NoFunds { .. }));
+
+        // Adjustments can't be disputed.
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                &options,
+            )
+            .expect_err("Expected dispute of an adjustment to fail");
+        assert!(matches!(
+            err,
+            Error::InvalidTxType(TransactionType::Adjustment)
+        ));
+        assert!(!err.is_ignorable());
+        assert_eq!(c.available, Decimal::new(15, 1));
+        assert_eq!(c.held, Decimal::new(0, 0));
+
+        // Adjustments respect the lock.
+        c.locked = true;
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Adjustment, 1, 4, Some(Decimal::new(1, 0))),
+                &options,
+            )
+            .expect_err("Expected adjustment of a locked account to fail");
+        assert_eq!(err, Error::ClientLocked(1));
+    }
+
+    #[test]
+    fn test_max_scale() {
+        let options = ClientOptions {
+            max_scale: Some(ScaleLimit {
+                scale: 4,
+                rounding: RoundingStrategy::MidpointNearestEven,
+            }),
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+
+        // Amounts with growing scales.
+        for i in 1..=100u32 {
+            c.make_tx(
+                Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    i,
+                    Some(Decimal::new(1, i % 28)),
+                ),
+                &options,
+            )
+            .expect("Failed to make a transaction");
+            assert!(c.available.scale() <= 4);
+            assert!(c.total.scale() <= 4);
+        }
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+        assert!(c.available.scale() <= 4);
+        assert!(c.held.scale() <= 4);
+        assert_eq!(c.held, Decimal::new(1, 2));
+        assert_eq!(c.available + c.held, c.total);
+
+        // Without the limit, the scale grows to the greatest one.
+        let mut c = Client::new(1);
+        for i in 1..=100u32 {
+            c.make_tx(
+                Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    i,
+                    Some(Decimal::new(1, i % 28)),
+                ),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to make a transaction");
+        }
+        assert_eq!(c.total.scale(), 27);
+    }
+
+    #[test]
+    fn test_apply_transaction() {
+        let mut clients_map = BTreeMap::new();
+
+        apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Deposit, 2, 1, Some(Decimal::new(3, 0))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to apply a transaction");
+        apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(15, 1))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to apply a transaction");
+        apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Withdrawal, 2, 3, Some(Decimal::new(1, 0))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to apply a transaction");
+        apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to apply a transaction");
+
+        // Withdrawal exceeding the available funds.
+        let err = apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Withdrawal, 1, 4, Some(Decimal::new(1, 0))),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected client account to have insufficient funds");
+        assert!(err.is_ignorable());
+
+        assert_eq!(clients_map.len(), 2);
+
+        let c1 = clients_map.get(&1).expect("Failed to get a client");
+        assert_eq!(c1.available, Decimal::new(0, 0));
+        assert_eq!(c1.held, Decimal::new(15, 1));
+        assert_eq!(c1.total, Decimal::new(15, 1));
+        assert_eq!(c1.transactions.len(), 1);
+
+        let c2 = clients_map.get(&2).expect("Failed to get a client");
+        assert_eq!(c2.available, Decimal::new(2, 0));
+        assert_eq!(c2.held, Decimal::new(0, 0));
+        assert_eq!(c2.total, Decimal::new(2, 0));
+        assert_eq!(c2.transactions.len(), 2);
+    }
+
+    /// Returns clients with funds deposited with the given transactions.
+    fn with_deposits(deposits: &[(u16, u32, i64)]) -> BTreeMap<u16, Client> {
+        let mut clients_map = BTreeMap::new();
+        for &(client, tx, amount) in deposits {
+            apply_transaction(
+                &mut clients_map,
+                Transaction::new(
+                    TransactionType::Deposit,
+                    client,
+                    tx,
+                    Some(Decimal::new(amount, 0)),
+                ),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to apply a transaction");
+        }
+        clients_map
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut clients_map = with_deposits(&[(1, 1, 5)]);
+
+        // The target account is created.
+        apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Transfer, 1, 2, Some(Decimal::new(3, 0)))
+                .with_target(2),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to transfer funds");
+
+        let c1 = clients_map.get(&1).expect("Failed to get a client");
+        assert_eq!(c1.available, Decimal::new(2, 0));
+        assert_eq!(c1.total, Decimal::new(2, 0));
+        let c2 = clients_map.get(&2).expect("Failed to get a client");
+        assert_eq!(c2.available, Decimal::new(3, 0));
+        assert_eq!(c2.total, Decimal::new(3, 0));
+
+        // Transfers can't be disputed.
+        let err = apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected dispute of a transfer to fail");
+        assert!(matches!(
+            err,
+            Error::InvalidTxType(TransactionType::Transfer)
+        ));
+
+        for tx in [
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Decimal::new(1, 0))),
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Decimal::new(1, 0)))
+                .with_target(1),
+        ] {
+            let err = apply_transaction(&mut clients_map, tx, &ClientOptions::default())
+                .expect_err("Expected transfer without a valid target to fail");
+            assert_eq!(err, Error::InvalidTarget { tx: 3 });
+        }
+    }
+
+    #[test]
+    fn test_transfer_no_funds() {
+        let mut clients_map = with_deposits(&[(1, 1, 5), (2, 2, 1)]);
+
+        let err = apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Decimal::new(6, 0)))
+                .with_target(2),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected transfer exceeding available funds to fail");
+        assert!(matches!(err, Error::NoFunds { .. }));
+
+        let c1 = clients_map.get(&1).expect("Failed to get a client");
+        assert_eq!(c1.total, Decimal::new(5, 0));
+        let c2 = clients_map.get(&2).expect("Failed to get a client");
+        assert_eq!(c2.total, Decimal::new(1, 0));
+    }
+
+    #[test]
+    fn test_transfer_locked_target() {
+        let mut clients_map = with_deposits(&[(1, 1, 5), (2, 2, 1), (2, 3, 2)]);
+        for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            apply_transaction(
+                &mut clients_map,
+                Transaction::new(tx_type, 2, 3, None),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to apply a transaction");
+        }
+
+        let err = apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Transfer, 1, 4, Some(Decimal::new(3, 0)))
+                .with_target(2),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected transfer to a locked account to fail");
+        assert_eq!(err, Error::ClientLocked(2));
+
+        let c1 = clients_map.get(&1).expect("Failed to get a client");
+        assert_eq!(c1.available, Decimal::new(5, 0));
+        assert_eq!(c1.total, Decimal::new(5, 0));
+        assert_eq!(c1.tx_ids().collect::<Vec<_>>(), vec![1]);
+        let c2 = clients_map.get(&2).expect("Failed to get a client");
+        assert_eq!(c2.total, Decimal::new(1, 0));
+    }
+
+    #[test]
+    fn test_transfer_overflow() {
+        let mut clients_map = with_deposits(&[(1, 1, 5), (2, 2, i64::MAX)]);
+        apply_transaction(
+            &mut clients_map,
+            Transaction::new(
+                TransactionType::Deposit,
+                2,
+                3,
+                Some(Decimal::MAX - Decimal::new(i64::MAX, 0)),
+            ),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to apply a transaction");
+
+        // The source account is reverted if the target can't be credited.
+        let err = apply_transaction(
+            &mut clients_map,
+            Transaction::new(TransactionType::Transfer, 1, 4, Some(Decimal::new(3, 0)))
+                .with_target(2),
+            &ClientOptions::default(),
+        )
+        .expect_err("Expected transfer overflowing the target balance to fail");
+        assert_eq!(err, Error::BalanceOverflow { client: 2 });
+
+        let c1 = clients_map.get(&1).expect("Failed to get a client");
+        assert_eq!(c1.available, Decimal::new(5, 0));
+        assert_eq!(c1.total, Decimal::new(5, 0));
+        assert_eq!(c1.tx_ids().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_amount_errors() {
+        let mut c = Client::new(1);
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Deposit, 1, 3, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected deposit without amount to fail");
+        assert_eq!(err.to_string(), "deposit tx 3 has to specify amount");
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Withdrawal, 1, 4, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected withdrawal without amount to fail");
+        assert_eq!(err.to_string(), "withdrawal tx 4 has to specify amount");
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 5, Some(Decimal::new(10, 1))),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected dispute with provided amount to fail");
+        assert_eq!(
+            err.to_string(),
+            "dispute tx 5 must not specify amount (got 1.0)"
+        );
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Chargeback, 1, 6, Some(Decimal::new(25, 1))),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected chargeback with provided amount to fail");
+        assert_eq!(
+            err.to_string(),
+            "chargeback tx 6 must not specify amount (got 2.5)"
+        );
+    }
+
+    #[test]
+    fn test_non_positive_amount() {
+        let mut c = Client::new(1);
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
+
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 2, Decimal::new(-10, 1)),
+            (TransactionType::Deposit, 3, Decimal::new(0, 0)),
+            (TransactionType::Withdrawal, 4, Decimal::new(-10, 1)),
+            (TransactionType::Withdrawal, 5, Decimal::new(0, 0)),
+        ] {
+            let err = c
+                .make_tx(
+                    Transaction::new(tx_type, 1, tx_id, Some(amount)),
+                    &ClientOptions::default(),
+                )
+                .expect_err("Expected a non-positive amount to fail");
+            assert!(err == Error::NonPositiveAmount { tx: tx_id, amount });
+        }
+
+        assert_eq!(c.available, Decimal::new(5, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(5, 0));
+        assert_eq!(c.tx_ids().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        let mut c = Client::new(1);
+        let amount = Decimal::new(i64::MAX, 0);
+
+        // Start close to the limit, so only a few deposits are needed.
+        c.deposit(Decimal::MAX - amount * Decimal::new(100, 0))
+            .expect("Failed to deposit");
+
+        let mut tx_id = 0;
+        let err = loop {
+            let before = c.total;
+            tx_id += 1;
+            match c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, tx_id, Some(amount)),
+                &ClientOptions::default(),
+            ) {
+                Ok(_) => assert!(tx_id <= 100, "Expected deposits to overflow"),
+                Err(e) => {
+                    // The balance from before the failed deposit is preserved.
+                    assert_eq!(c.available, before);
+                    assert_eq!(c.total, before);
+                    break e;
+                }
+            }
+        };
+
+        assert_eq!(tx_id, 101);
+        assert_eq!(err, Error::BalanceOverflow { client: 1 });
+        assert!(err.is_ignorable());
+    }
+
+    #[test]
+    fn test_balance_overflow_max() {
+        let mut c = Client::new(1);
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(Decimal::MAX)),
+            (TransactionType::Withdrawal, 2, Some(Decimal::MAX)),
+            (TransactionType::Deposit, 3, Some(Decimal::MAX)),
+        ] {
+            c.make_tx(
+                Transaction::new(tx_type, 1, tx_id, amount),
+                &ClientOptions::default(),
+            )
             .expect("Failed to make a transaction");
+        }
+
+        // Neither a deposit of any amount nor a dispute of the withdrawal
+        // (adding its amount to the total) fit in the balance.
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 4, Some(Decimal::new(1, 0))),
+            (TransactionType::Dispute, 2, None),
+        ] {
+            let err = c
+                .make_tx(
+                    Transaction::new(tx_type, 1, tx_id, amount),
+                    &ClientOptions::default(),
+                )
+                .expect_err("Expected the balance to overflow");
+            assert_eq!(err, Error::BalanceOverflow { client: 1 });
+        }
+        assert_eq!(c.available, Decimal::MAX);
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::MAX);
+        assert!(!c.transactions[&2].is_disputed());
+    }
+
+    #[test]
+    fn test_disputable_types() {
+        let options = ClientOptions {
+            disputable_types: vec![TransactionType::Deposit],
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::new(2, 0))),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 2, None),
+                &options,
+            )
+            .expect_err("Expected dispute of a withdrawal to fail");
+        assert!(matches!(
+            err,
+            Error::InvalidTxType(TransactionType::Withdrawal)
+        ));
+        assert!(!err.is_ignorable());
+        assert_eq!(c.available, Decimal::new(3, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 3, Some(Decimal::new(4, 0))),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            &options,
+        )
+        .expect("Failed to dispute a deposit");
+        assert_eq!(c.available, Decimal::new(2, 0));
+        assert_eq!(c.held, Decimal::new(5, 0));
+        assert_eq!(c.total, Decimal::new(7, 0));
+    }
+
+    #[test]
+    fn test_dispute_withdrawn_deposit() {
+        let mut c = Client::new(1);
+        for tx in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(1, 0))),
+            Transaction::new(TransactionType::Withdrawal, 1, 3, Some(Decimal::new(4, 0))),
+        ] {
+            c.make_tx(tx, &ClientOptions::default())
+                .expect("Failed to make a transaction");
+        }
+
+        // Holding the deposit would leave -3 available.
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected dispute of a withdrawn deposit to fail");
+        assert_eq!(
+            err,
+            Error::NoFunds {
+                client: 1,
+                available: Decimal::new(2, 0),
+                requested: Decimal::new(5, 0)
+            }
+        );
+        assert!(err.is_ignorable());
+        assert_eq!(c.available, Decimal::new(2, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(2, 0));
+        assert!(!c.transactions[&1].is_disputed());
+
+        // The deposit with funds still available can be disputed.
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to dispute a deposit");
+        assert_eq!(c.available, Decimal::new(1, 0));
+        assert_eq!(c.held, Decimal::new(1, 0));
+
+        // Dispute of a withdrawal doesn't take available funds.
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 3, None),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to dispute a withdrawal");
+        assert_eq!(c.available, Decimal::new(1, 0));
+        assert_eq!(c.held, Decimal::new(5, 0));
+        assert_eq!(c.total, Decimal::new(6, 0));
+    }
+
+    #[test]
+    fn test_disputes_ignore_lock() {
+        let mut c = Client::new(1);
+        for (tx_id, amount) in [(1, 5), (2, 3)] {
+            c.make_tx(
+                Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    tx_id,
+                    Some(Decimal::new(amount, 0)),
+                ),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to make a transaction");
+        }
+        for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            c.make_tx(
+                Transaction::new(tx_type, 1, 1, None),
+                &ClientOptions::default(),
+            )
+            .expect("Failed to make a transaction");
+        }
+        assert!(c.locked);
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 2, None),
+                &ClientOptions::default(),
+            )
+            .expect_err("Expected dispute on a locked account to fail");
+        assert_eq!(err, Error::ClientLocked(1));
+
+        let options = ClientOptions {
+            disputes_ignore_lock: true,
+            ..Default::default()
+        };
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+            &options,
+        )
+        .expect("Failed to dispute a transaction on a locked account");
+        assert_eq!(c.available, Decimal::new(0, 0));
+        assert_eq!(c.held, Decimal::new(3, 0));
+        assert_eq!(c.total, Decimal::new(3, 0));
+
+        c.make_tx(
+            Transaction::new(TransactionType::Chargeback, 1, 2, None),
+            &options,
+        )
+        .expect("Failed to charge back a transaction on a locked account");
+        assert_eq!(c.available, Decimal::new(0, 0));
+        assert_eq!(c.held, Decimal::new(0, 0));
+        assert_eq!(c.total, Decimal::new(0, 0));
+        assert!(c.locked);
+
+        // Other transactions are still rejected.
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Deposit, 1, 3, Some(Decimal::new(1, 0))),
+                &options,
+            )
+            .expect_err("Expected deposit on a locked account to fail");
+        assert_eq!(err, Error::ClientLocked(1));
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        let options = ClientOptions {
+            verify_invariants: true,
+            ..Default::default()
+        };
+
+        // Balances restored e.g. from a report with more funds held than the
+        // account contains.
+        let mut c = Client::with_balances(
+            1,
+            Decimal::new(-5, 0),
+            Decimal::new(5, 0),
+            Decimal::new(0, 0),
+            false,
+        )
+        .expect("Failed to create a client");
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0))),
+                &options,
+            )
+            .expect_err("Expected the invariant check to fail");
+        assert_eq!(
+            err,
+            Error::InvariantViolated {
+                client: 1,
+                held: Decimal::new(5, 0),
+                total: Decimal::new(1, 0)
+            }
+        );
+        assert!(!err.is_ignorable());
+
+        // Without the check, the deposit is applied.
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(1, 0))),
+            &ClientOptions::default(),
+        )
+        .expect("Failed to make a transaction");
+        assert_eq!(c.held, Decimal::new(5, 0));
+        assert_eq!(c.total, Decimal::new(2, 0));
+    }
+
+    #[test]
+    fn test_max_history() {
+        let options = ClientOptions {
+            max_history: Some(10),
+            ..Default::default()
+        };
+        let mut c = Client::new(1);
+
+        for tx_id in 1..=1000 {
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(1, 0))),
+                &options,
+            )
+            .expect("Failed to make a transaction");
+            assert!(c.transactions.len() <= 10);
+        }
+        assert_eq!(
+            c.tx_ids().collect::<Vec<_>>(),
+            (991..=1000).collect::<Vec<_>>()
+        );
+        assert_eq!(c.history_order.len(), 10);
+        assert_eq!(c.total, Decimal::new(1000, 0));
+
+        let err = c
+            .make_tx(
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                &options,
+            )
+            .expect_err("Expected dispute of a pruned transaction to fail");
+        assert_eq!(err, Error::TransactionNotFound(1));
+
+        // Disputed transactions are kept until the dispute is closed.
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 991, None),
+            &options,
+        )
+        .expect("Failed to dispute a transaction");
+        for tx_id in 1001..=1100 {
+            c.make_tx(
+                Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(1, 0))),
+                &options,
+            )
+            .expect("Failed to make a transaction");
+        }
+        assert_eq!(c.transactions.len(), 10);
+        assert!(c.transactions.contains_key(&991));
+        c.make_tx(
+            Transaction::new(TransactionType::Resolve, 1, 991, None),
+            &options,
+        )
+        .expect("Failed to resolve a dispute");
+        c.make_tx(
+            Transaction::new(TransactionType::Deposit, 1, 1101, Some(Decimal::new(1, 0))),
+            &options,
+        )
+        .expect("Failed to make a transaction");
+        assert_eq!(c.transactions.len(), 10);
+        assert!(!c.transactions.contains_key(&991));
+    }
+
+    #[test]
+    fn test_max_history_restored() {
+        let options = ClientOptions {
+            max_history: Some(2),
+            ..Default::default()
+        };
+        let deposit =
+            |tx_id| Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(1, 0)));
+
+        // Transactions saved without the limit are dropped first.
+        let mut c = Client::new(1);
+        c.make_tx(deposit(1), &ClientOptions::default())
+            .expect("Failed to make a transaction");
+        let mut c = Client::from_snapshot(c.snapshot()).expect("Failed to restore a client");
+        c.make_tx(deposit(2), &options)
+            .expect("Failed to make a transaction");
+        c.make_tx(deposit(3), &options)
+            .expect("Failed to make a transaction");
+        assert_eq!(c.tx_ids().collect::<Vec<_>>(), vec![2, 3]);
+
+        // The order of the limited history is restored.
+        let mut c = Client::from_snapshot(c.snapshot()).expect("Failed to restore a client");
+        c.make_tx(deposit(4), &options)
+            .expect("Failed to make a transaction");
+        assert_eq!(c.tx_ids().collect::<Vec<_>>(), vec![3, 4]);
+        c.make_tx(
+            Transaction::new(TransactionType::Dispute, 1, 4, None),
+            &options,
+        )
+        .expect("Failed to dispute a transaction made after restoring");
+    }
+
+    /// Generates a transaction of client 1, referring to one of a few
+    /// transaction IDs, so disputes, resolves and chargebacks hit both
+    /// existing and missing transactions.
+    fn arb_transaction() -> impl proptest::strategy::Strategy<Value = Transaction> {
+        use proptest::prelude::*;
+
+        (
+            prop_oneof![
+                Just(TransactionType::Deposit),
+                Just(TransactionType::Withdrawal),
+                Just(TransactionType::Dispute),
+                Just(TransactionType::Resolve),
+                Just(TransactionType::Chargeback),
+                Just(TransactionType::Adjustment),
+            ],
+            1u32..8,
+            -1_000i64..100_000,
+            0u32..4,
+        )
+            .prop_map(|(tx_type, tx_id, amount, scale)| {
+                let amount = if tx_type.refers_tx() {
+                    None
+                } else {
+                    Some(Decimal::new(amount, scale))
+                };
+                Transaction::new(tx_type, 1, tx_id, amount)
+            })
+    }
+
+    proptest::proptest! {
+        /// Total funds are the sum of available and held ones after every
+        /// transaction, whether it succeeds or not, and neither available nor
+        /// held funds are ever negative.
+        #[test]
+        fn test_balance_invariant(
+            txs in proptest::collection::vec(arb_transaction(), 0..64),
+            disputes_ignore_lock: bool,
+        ) {
+            let options = ClientOptions {
+                disputes_ignore_lock,
+                ..Default::default()
+            };
+            let mut c = Client::new(1);
+            for tx in txs {
+                let _ = c.make_tx(tx, &options);
+                proptest::prop_assert_eq!(c.available + c.held, c.total);
+                proptest::prop_assert!(c.available >= Decimal::new(0, 0));
+                proptest::prop_assert!(c.held >= Decimal::new(0, 0));
+            }
+        }
     }
 }
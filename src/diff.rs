@@ -0,0 +1,145 @@
+/// Number of unchanged lines shown around the changes.
+const CONTEXT: usize = 3;
+
+/// Line of a diff.
+#[derive(Debug, PartialEq)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Computes the difference between lines which differ, using the longest
+/// common subsequence.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    // lcs[i][j] is the length of the longest common subsequence of old[i..]
+    // and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|l| DiffLine::Removed(l)));
+    lines.extend(new[j..].iter().map(|l| DiffLine::Added(l)));
+
+    lines
+}
+
+/// Returns a unified-diff-style report of differences between the expected
+/// and actual text, or `None` if both are equal.
+///
+/// Common leading and trailing lines are skipped before diffing, so the cost
+/// depends mostly on the size of the changed region. All changes are reported
+/// in a single hunk.
+pub(crate) fn unified_diff(
+    expected: &str,
+    actual: &str,
+    expected_name: &str,
+    actual_name: &str,
+) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+
+    let start = prefix.saturating_sub(CONTEXT);
+    let old_end = (old.len() - suffix + CONTEXT).min(old.len());
+    let new_end = (new.len() - suffix + CONTEXT).min(new.len());
+
+    let mut report = format!("--- {}\n+++ {}\n", expected_name, actual_name);
+    report.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n",
+        start + 1,
+        old_end - start,
+        start + 1,
+        new_end - start
+    ));
+    for line in old[start..prefix].iter() {
+        report.push_str(&format!(" {}\n", line));
+    }
+    for line in diff_lines(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    ) {
+        match line {
+            DiffLine::Same(l) => report.push_str(&format!(" {}\n", l)),
+            DiffLine::Removed(l) => report.push_str(&format!("-{}\n", l)),
+            DiffLine::Added(l) => report.push_str(&format!("+{}\n", l)),
+        }
+    }
+    for line in old[old.len() - suffix..old_end].iter() {
+        report.push_str(&format!(" {}\n", line));
+    }
+
+    Some(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "expected", "actual"), None);
+
+        let expected = "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,2.0,0,2.0,false
+";
+        let actual = "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,0.0,0,0.0,false
+3,1.0,0,1.0,false
+";
+        assert_eq!(
+            unified_diff(expected, actual, "expected.csv", "actual").unwrap(),
+            "\
+--- expected.csv
++++ actual
+@@ -1,3 +1,4 @@
+ client,available,held,total,locked
+ 1,1.5,0,1.5,false
+-2,2.0,0,2.0,false
++2,0.0,0,0.0,false
++3,1.0,0,1.0,false
+"
+        );
+    }
+}
@@ -0,0 +1,953 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Read,
+    sync::{PoisonError, RwLock},
+};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    client::{self, Client, ClientOptions, Effect, UndoRecord},
+    error::Error,
+    reader::{ReaderOptions, TransactionReader},
+    transaction::{Transaction, TransactionType},
+};
+
+/// Entry of the undo log, describing how to revert an applied transaction.
+#[derive(Debug)]
+struct UndoEntry {
+    client: u16,
+    /// Whether the client was created by the transaction.
+    created: bool,
+    /// ID of the transaction, if it was registered as owned by the client.
+    owned: Option<u32>,
+    record: UndoRecord,
+    /// Target client of a transfer, whether it was created by the transfer
+    /// and its state from before it.
+    target: Option<(u16, bool, UndoRecord)>,
+    /// Clients and IDs of transactions skipped as duplicates after this one.
+    skipped_duplicates: Vec<(u16, u32)>,
+}
+
+/// Operation which changed an account, with balances of the account after it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TraceEntry {
+    pub client: u16,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// Transaction processing engine, keeping accounts of all clients.
+#[derive(Debug, Default)]
+pub struct Engine {
+    clients: BTreeMap<u16, Client>,
+    /// Log of applied transactions which can be reverted, if enabled.
+    undo_log: Option<Vec<UndoEntry>>,
+    /// Operations which changed accounts since the last
+    /// [`Engine::take_trace`], if enabled.
+    trace: Option<Vec<TraceEntry>>,
+    options: ClientOptions,
+    /// Clients owning IDs of applied deposits, withdrawals, adjustments and
+    /// transfers, tracked only when duplicate IDs are skipped.
+    tx_owners: BTreeMap<u32, u16>,
+    /// Clients and IDs of deposits, withdrawals, adjustments and transfers
+    /// skipped due to duplicate IDs.
+    skipped_duplicates: BTreeSet<(u16, u32)>,
+}
+
+impl Engine {
+    /// Create a new engine without any clients.
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// Create a new engine making transactions with the given options.
+    pub fn with_options(options: ClientOptions) -> Engine {
+        Engine {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the engine keeping a log of applied transactions, so they can
+    /// be reverted with [`Engine::undo_last`].
+    pub fn with_undo_log(self) -> Engine {
+        Engine {
+            undo_log: Some(Vec::new()),
+            ..self
+        }
+    }
+
+    /// Returns the engine recording operations which changed accounts, so
+    /// they can be retrieved with [`Engine::take_trace`]. Skipped transactions
+    /// and no-ops are not recorded.
+    pub fn with_trace(self) -> Engine {
+        Engine {
+            trace: Some(Vec::new()),
+            ..self
+        }
+    }
+
+    /// Returns operations recorded since the previous call, in the order of
+    /// applying them. A transfer is recorded for both the source and the
+    /// target client.
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Records the operation on the account of the given client.
+    fn trace(&mut self, client: u16, tx_type: &TransactionType, tx: u32, amount: Option<Decimal>) {
+        let (Some(trace), Some(c)) = (self.trace.as_mut(), self.clients.get(&client)) else {
+            return;
+        };
+        trace.push(TraceEntry {
+            client,
+            tx_type: tx_type.clone(),
+            tx,
+            amount,
+            available: c.available(),
+            held: c.held(),
+            total: c.total(),
+            locked: c.locked(),
+        });
+    }
+
+    /// Adds the given clients, e.g. restored from a checkpoint.
+    pub fn restore(&mut self, clients: BTreeMap<u16, Client>) {
+        if self.options.skip_duplicate_tx_ids {
+            for (id, client) in clients.iter() {
+                for tx_id in client.tx_ids() {
+                    self.tx_owners.insert(tx_id, *id);
+                }
+            }
+        }
+        self.clients.extend(clients);
+    }
+
+    /// Fails if the transaction has the ID of an already applied deposit,
+    /// withdrawal, adjustment or transfer, or refers to an ID which was
+    /// skipped as a duplicate for its client.
+    ///
+    /// The first applied transaction with the given ID is retained, so
+    /// disputes, resolves and chargebacks always refer to it.
+    fn check_duplicate(&mut self, tx: &Transaction) -> Result<(), Error> {
+        match tx.tx_type {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Adjustment
+            | TransactionType::Transfer => {
+                if self.tx_owners.contains_key(&tx.tx) {
+                    if self.skipped_duplicates.insert((tx.client, tx.tx)) {
+                        // Forget it when reverting the preceding transaction.
+                        if let Some(undo) = self.undo_log.as_mut().and_then(|log| log.last_mut()) {
+                            undo.skipped_duplicates.push((tx.client, tx.tx));
+                        }
+                    }
+                    return Err(Error::DuplicateTxId(tx.tx));
+                }
+            }
+            _ => {
+                // The client of the retained transaction can refer to it.
+                if self.tx_owners.get(&tx.tx) != Some(&tx.client)
+                    && self.skipped_duplicates.contains(&(tx.client, tx.tx))
+                {
+                    return Err(Error::SkippedDuplicateTx(tx.tx));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the transaction on the account of the client it belongs to.
+    pub fn process_transaction(&mut self, tx: Transaction) -> Result<(), Error> {
+        if self.options.skip_duplicate_tx_ids {
+            self.check_duplicate(&tx)?;
+        }
+        // ID of the transaction, if it's going to be owned by its client.
+        let owned = match tx.tx_type {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Adjustment
+            | TransactionType::Transfer
+                if self.options.skip_duplicate_tx_ids =>
+            {
+                Some((tx.tx, tx.client))
+            }
+            _ => None,
+        };
+
+        let undo = self.undo_log.as_ref().map(|_| {
+            let (created, record) = self.undo_account(tx.client, tx.tx);
+            let target = match (&tx.tx_type, tx.target) {
+                (TransactionType::Transfer, Some(target)) => {
+                    let (created, record) = self.undo_account(target, tx.tx);
+                    Some((target, created, record))
+                }
+                _ => None,
+            };
+            UndoEntry {
+                client: tx.client,
+                created,
+                owned: owned.map(|(tx_id, _)| tx_id),
+                record,
+                target,
+                skipped_duplicates: Vec::new(),
+            }
+        });
+
+        let traced = self
+            .trace
+            .as_ref()
+            .map(|_| (tx.client, tx.tx_type.clone(), tx.tx, tx.amount, tx.target));
+
+        let effect = client::apply_transaction(&mut self.clients, tx, &self.options)?;
+
+        if let (Some((client, tx_type, tx_id, amount, target)), Effect::Changed) = (traced, effect)
+        {
+            self.trace(client, &tx_type, tx_id, amount);
+            if let (TransactionType::Transfer, Some(target)) = (&tx_type, target) {
+                self.trace(target, &tx_type, tx_id, amount);
+            }
+        }
+
+        if let Some((tx_id, client)) = owned {
+            self.tx_owners.insert(tx_id, client);
+        }
+
+        if let (Some(undo_log), Some(undo)) = (self.undo_log.as_mut(), undo) {
+            undo_log.push(undo);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a CSV series of transactions from the source with the given
+    /// options and applies them, returning the outcome of each row (including
+    /// ones which can't be read) with the number of its line, so the caller
+    /// decides which errors to tolerate (e.g. with a [`SkipPolicy`]). Rows
+    /// after a failed one are still processed.
+    ///
+    /// An invalid header is returned as the outcome of the first line.
+    ///
+    /// [`SkipPolicy`]: crate::SkipPolicy
+    pub fn process_reader<R: Read>(
+        &mut self,
+        source: R,
+        options: ReaderOptions,
+    ) -> Vec<(u64, Result<(), Error>)> {
+        let reader = match TransactionReader::new(source, options) {
+            Ok(reader) => reader,
+            Err(e) => return vec![(1, Err(e))],
+        };
+        let mut line = 1;
+        reader
+            .map(|row| match row {
+                Ok((row_line, tx)) => {
+                    line = row_line;
+                    (line, self.process_transaction(tx))
+                }
+                Err(e) => {
+                    line = e.line().unwrap_or(line + 1);
+                    (line, Err(e))
+                }
+            })
+            .collect()
+    }
+
+    /// Reverts the most recently applied transaction.
+    pub fn undo_last(&mut self) -> Result<(), Error> {
+        let undo = self
+            .undo_log
+            .as_mut()
+            .and_then(|undo_log| undo_log.pop())
+            .ok_or(Error::NothingToUndo)?;
+
+        if let Some(tx_id) = undo.owned {
+            self.tx_owners.remove(&tx_id);
+        }
+        for skipped in undo.skipped_duplicates.iter() {
+            self.skipped_duplicates.remove(skipped);
+        }
+
+        if let Some((target, created, record)) = undo.target {
+            self.undo_account_change(target, created, record)?;
+        }
+        self.undo_account_change(undo.client, undo.created, undo.record)
+    }
+
+    /// Returns whether the account of the given client doesn't exist yet and
+    /// its state from before the given transaction.
+    fn undo_account(&self, client: u16, tx_id: u32) -> (bool, UndoRecord) {
+        match self.clients.get(&client) {
+            Some(c) => (false, c.undo_record(tx_id)),
+            None => (true, Client::new(client).undo_record(tx_id)),
+        }
+    }
+
+    /// Reverts the account of the given client to the recorded state, or
+    /// removes it if it was created by the reverted transaction.
+    fn undo_account_change(
+        &mut self,
+        client: u16,
+        created: bool,
+        record: UndoRecord,
+    ) -> Result<(), Error> {
+        if created {
+            self.clients.remove(&client);
+        } else {
+            let client = self
+                .clients
+                .get_mut(&client)
+                .ok_or(Error::ClientNotFound(client))?;
+            client.undo(record);
+        }
+
+        Ok(())
+    }
+
+    /// Gets the given client.
+    pub fn client(&self, id: u16) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    /// Returns accounts of all clients, ordered by their IDs.
+    pub fn clients(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    /// Sum of available funds of all clients.
+    ///
+    /// Fails with [`Error::BalanceOverflow`] (with the client whose funds
+    /// made it overflow) if the sum doesn't fit in a [`Decimal`].
+    pub fn total_available(&self) -> Result<Decimal, Error> {
+        self.sum_balances(Client::available)
+    }
+
+    /// Sum of held funds of all clients.
+    ///
+    /// Fails with [`Error::BalanceOverflow`] (with the client whose funds
+    /// made it overflow) if the sum doesn't fit in a [`Decimal`].
+    pub fn total_held(&self) -> Result<Decimal, Error> {
+        self.sum_balances(Client::held)
+    }
+
+    /// Sums the given balance of all clients.
+    fn sum_balances(&self, balance: fn(&Client) -> Decimal) -> Result<Decimal, Error> {
+        self.clients
+            .values()
+            .try_fold(Decimal::ZERO, |sum, client| {
+                sum.checked_add(balance(client))
+                    .ok_or(Error::BalanceOverflow {
+                        client: client.id(),
+                    })
+            })
+    }
+
+    /// Resolves disputes which were opened more than `expiry` seconds before
+    /// `now` (see [`Client::expired_disputes`]), returning IDs of clients and
+    /// transactions whose disputes were resolved.
+    ///
+    /// The resolves are applied like any other transaction, so they're
+    /// recorded in the trace and in the undo log.
+    pub fn expire_disputes(&mut self, now: u64, expiry: u64) -> Result<Vec<(u16, u32)>, Error> {
+        let expired: Vec<(u16, u32)> = self
+            .clients
+            .values()
+            .flat_map(|client| {
+                client
+                    .expired_disputes(now, expiry)
+                    .into_iter()
+                    .map(|tx_id| (client.id(), tx_id))
+            })
+            .collect();
+        for (client, tx_id) in expired.iter() {
+            self.process_transaction(Transaction::new(
+                TransactionType::Resolve,
+                *client,
+                *tx_id,
+                None,
+            ))?;
+        }
+        Ok(expired)
+    }
+
+    /// Number of locked accounts.
+    pub fn locked_count(&self) -> usize {
+        self.clients.values().filter(|c| c.locked()).count()
+    }
+
+    /// Number of clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Consumes the engine, returning accounts of all clients.
+    pub fn into_clients(self) -> BTreeMap<u16, Client> {
+        self.clients
+    }
+}
+
+/// Engine which can be shared between threads.
+///
+/// Transactions are applied under a write lock, so only one transaction is
+/// applied at a time and no reader can see it half-applied. Clients are read
+/// under a read lock, so multiple readers can query the balances concurrently,
+/// but they wait for a transaction being currently applied.
+///
+/// If a thread panics while holding the lock, the engine is still usable -
+/// a transaction is applied only after all its checks succeed, so a panic
+/// cannot leave an account in an inconsistent state.
+// Not used by the CLI, meant for long-running services.
+#[derive(Debug, Default)]
+pub struct SharedEngine {
+    engine: RwLock<Engine>,
+}
+
+impl SharedEngine {
+    /// Create a new shared engine without any clients.
+    pub fn new() -> SharedEngine {
+        SharedEngine::default()
+    }
+
+    /// Applies the transaction, taking a write lock.
+    pub fn process_transaction(&self, tx: Transaction) -> Result<(), Error> {
+        self.engine
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .process_transaction(tx)
+    }
+
+    /// Returns a snapshot of the given client, taking a read lock.
+    pub fn client(&self, id: u16) -> Option<Client> {
+        self.engine
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .client(id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn test_aggregates() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.client_count(), 0);
+        assert_eq!(engine.total_held(), Ok(Decimal::new(0, 0)));
+
+        for (tx_type, client, tx, amount) in [
+            (TransactionType::Deposit, 1, 1, Some(Decimal::new(50, 1))),
+            (TransactionType::Deposit, 2, 2, Some(Decimal::new(30, 1))),
+            (TransactionType::Deposit, 3, 3, Some(Decimal::new(20, 1))),
+            (TransactionType::Withdrawal, 1, 4, Some(Decimal::new(15, 1))),
+            (TransactionType::Dispute, 2, 2, None),
+            (TransactionType::Dispute, 3, 3, None),
+            (TransactionType::Chargeback, 3, 3, None),
+        ] {
+            engine
+                .process_transaction(Transaction::new(tx_type, client, tx, amount))
+                .expect("Failed to apply a transaction");
+        }
+
+        assert_eq!(engine.client_count(), 3);
+        assert_eq!(engine.locked_count(), 1);
+        assert_eq!(engine.total_available(), Ok(Decimal::new(35, 1)));
+        assert_eq!(engine.total_held(), Ok(Decimal::new(30, 1)));
+
+        // Balances of each client are valid, but their sum overflows.
+        for (client, tx) in [(4, 5), (5, 6)] {
+            engine
+                .process_transaction(Transaction::new(
+                    TransactionType::Deposit,
+                    client,
+                    tx,
+                    Some(Decimal::MAX / Decimal::new(2, 0)),
+                ))
+                .expect("Failed to apply a transaction");
+        }
+        assert_eq!(
+            engine.total_available(),
+            Err(Error::BalanceOverflow { client: 5 })
+        );
+        assert_eq!(engine.total_held(), Ok(Decimal::new(30, 1)));
+    }
+
+    #[test]
+    fn test_process_reader() {
+        let data = "\
+type,       client, tx, amount
+deposit,         1,  1,    5.0
+withdrawal,      1,  2,    9.0
+deposit,    client,  3,    1.0
+deposit,         1,  4,    abc
+dispute,         1,  9,
+deposit,         1,  5
+dispute,         1,  1,
+";
+        let mut engine = Engine::new();
+        let results = engine.process_reader(data.as_bytes(), ReaderOptions::default());
+        let lines: Vec<u64> = results.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [2, 3, 4, 5, 6, 7, 8]);
+
+        let (_, results): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+        assert_eq!(results[0], Ok(()));
+        assert!(matches!(results[1], Err(Error::NoFunds { client: 1, .. })));
+        assert!(matches!(
+            results[2],
+            Err(Error::InvalidInteger {
+                field: "client",
+                ..
+            })
+        ));
+        assert!(matches!(results[3], Err(Error::CSV(_))));
+        assert_eq!(results[4], Err(Error::TransactionNotFound(9)));
+        assert!(matches!(
+            results[5],
+            Err(Error::FieldCountMismatch { found: 3, .. })
+        ));
+        assert_eq!(results[6], Ok(()));
+
+        let client = engine.client(1).expect("Failed to get a client");
+        assert_eq!(client.held(), Decimal::new(50, 1));
+
+        let results =
+            engine.process_reader("client,tx\n1,1\n".as_bytes(), ReaderOptions::default());
+        assert!(matches!(
+            results.as_slice(),
+            [(1, Err(Error::InvalidHeader { .. }))]
+        ));
+
+        // Rows are read with the given options.
+        let results = engine.process_reader(
+            "type;client;tx;amount\ndeposit;2;10;1_000.5\n".as_bytes(),
+            ReaderOptions {
+                delimiter: b';',
+                lenient_amounts: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(results, [(2, Ok(()))]);
+        let client = engine.client(2).expect("Failed to get a client");
+        assert_eq!(client.available(), Decimal::new(10005, 1));
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut engine = Engine::with_options(ClientOptions {
+            idempotent_resolve: true,
+            ..Default::default()
+        })
+        .with_trace();
+        for tx in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(2, 0))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::new(5, 0))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            Transaction::new(TransactionType::Resolve, 1, 1, None),
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Decimal::new(5, 1)))
+                .with_target(2),
+        ] {
+            // Skipped transactions and no-ops are not recorded.
+            let _ = engine.process_transaction(tx);
+        }
+
+        let entry = |client, tx_type, tx, amount, available, held| TraceEntry {
+            client,
+            tx_type,
+            tx,
+            amount,
+            available,
+            held,
+            total: available + held,
+            locked: false,
+        };
+        assert_eq!(
+            engine.take_trace(),
+            vec![
+                entry(
+                    1,
+                    TransactionType::Deposit,
+                    1,
+                    Some(Decimal::new(2, 0)),
+                    Decimal::new(2, 0),
+                    Decimal::new(0, 0),
+                ),
+                entry(
+                    1,
+                    TransactionType::Dispute,
+                    1,
+                    None,
+                    Decimal::new(0, 0),
+                    Decimal::new(2, 0),
+                ),
+                entry(
+                    1,
+                    TransactionType::Resolve,
+                    1,
+                    None,
+                    Decimal::new(2, 0),
+                    Decimal::new(0, 0),
+                ),
+                entry(
+                    1,
+                    TransactionType::Transfer,
+                    3,
+                    Some(Decimal::new(5, 1)),
+                    Decimal::new(15, 1),
+                    Decimal::new(0, 0),
+                ),
+                entry(
+                    2,
+                    TransactionType::Transfer,
+                    3,
+                    Some(Decimal::new(5, 1)),
+                    Decimal::new(5, 1),
+                    Decimal::new(0, 0),
+                ),
+            ]
+        );
+        assert!(engine.take_trace().is_empty());
+
+        // Disputes auto-resolved after expiry are recorded too.
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                4,
+                Some(Decimal::new(1, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        let mut dispute = Transaction::new(TransactionType::Dispute, 1, 4, None);
+        dispute.timestamp = Some(100);
+        engine
+            .process_transaction(dispute)
+            .expect("Failed to apply a transaction");
+        assert_eq!(engine.take_trace().len(), 2);
+        assert_eq!(engine.expire_disputes(200, 60), Ok(vec![(1, 4)]));
+        assert_eq!(
+            engine.take_trace(),
+            vec![entry(
+                1,
+                TransactionType::Resolve,
+                4,
+                None,
+                Decimal::new(25, 1),
+                Decimal::new(0, 0),
+            )]
+        );
+
+        // Nothing is recorded unless enabled.
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::new(2, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        assert!(engine.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_undo_transfer() {
+        let mut engine = Engine::new().with_undo_log();
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::new(5, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        engine
+            .process_transaction(
+                Transaction::new(TransactionType::Transfer, 1, 2, Some(Decimal::new(2, 0)))
+                    .with_target(2),
+            )
+            .expect("Failed to apply a transaction");
+        assert_eq!(engine.client_count(), 2);
+        assert_eq!(engine.total_available(), Ok(Decimal::new(5, 0)));
+
+        engine.undo_last().expect("Failed to undo a transaction");
+        assert_eq!(engine.client_count(), 1);
+        let c = engine.client(1).expect("Failed to get a client");
+        assert_eq!(c.available(), Decimal::new(5, 0));
+    }
+
+    #[test]
+    fn test_undo_last() {
+        let mut engine = Engine::new().with_undo_log();
+
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::new(5, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        engine
+            .process_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .expect("Failed to apply a transaction");
+
+        let c = engine.client(1).expect("Failed to get a client");
+        assert_eq!(c.available(), Decimal::new(0, 0));
+        assert_eq!(c.held(), Decimal::new(5, 0));
+
+        // Undo the dispute.
+        engine.undo_last().expect("Failed to undo a transaction");
+
+        let c = engine.client(1).expect("Failed to get a client");
+        assert_eq!(c.available(), Decimal::new(5, 0));
+        assert_eq!(c.held(), Decimal::new(0, 0));
+        assert_eq!(c.total(), Decimal::new(5, 0));
+        // The transaction is not disputed anymore.
+        engine
+            .process_transaction(Transaction::new(TransactionType::Resolve, 1, 1, None))
+            .expect_err("Expected resolving a transaction not under dispute to fail");
+
+        // Dispute and chargeback, then undo the chargeback.
+        engine
+            .process_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .expect("Failed to apply a transaction");
+        engine
+            .process_transaction(Transaction::new(TransactionType::Chargeback, 1, 1, None))
+            .expect("Failed to apply a transaction");
+        assert!(engine.client(1).expect("Failed to get a client").locked());
+
+        engine.undo_last().expect("Failed to undo a transaction");
+
+        let c = engine.client(1).expect("Failed to get a client");
+        assert!(!c.locked());
+        assert_eq!(c.available(), Decimal::new(0, 0));
+        assert_eq!(c.held(), Decimal::new(5, 0));
+        assert_eq!(c.total(), Decimal::new(5, 0));
+
+        // Undo the remaining dispute and the deposit, which created the client.
+        engine.undo_last().expect("Failed to undo a transaction");
+        engine.undo_last().expect("Failed to undo a transaction");
+        assert!(engine.client(1).is_none());
+
+        engine
+            .undo_last()
+            .expect_err("Expected undo without applied transactions to fail");
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut engine = Engine::new().with_undo_log();
+        let deposit = |tx_id: u32| {
+            Transaction::new(TransactionType::Deposit, 1, tx_id, Some(Decimal::new(5, 0)))
+                .with_seq(tx_id as u64)
+        };
+        for tx_id in [1, 2] {
+            engine
+                .process_transaction(deposit(tx_id))
+                .expect("Failed to apply a transaction");
+        }
+
+        // Step back and forward again.
+        engine.undo_last().expect("Failed to undo a transaction");
+        engine
+            .process_transaction(deposit(2))
+            .expect("Failed to apply a transaction");
+        let c = engine.client(1).expect("Failed to get a client");
+        assert_eq!(c.total(), Decimal::new(10, 0));
+    }
+
+    #[test]
+    fn test_undo_skipped_duplicate() {
+        let mut engine = Engine::with_options(ClientOptions {
+            skip_duplicate_tx_ids: true,
+            ..Default::default()
+        })
+        .with_undo_log();
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::new(2, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                2,
+                1,
+                Some(Decimal::new(7, 0)),
+            ))
+            .expect_err("Expected a duplicate deposit to be skipped");
+
+        // Reverting the deposit forgets its skipped duplicate.
+        engine.undo_last().expect("Failed to undo a transaction");
+        assert!(engine.skipped_duplicates.is_empty());
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                3,
+                1,
+                Some(Decimal::new(1, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        let err = engine
+            .process_transaction(Transaction::new(TransactionType::Dispute, 2, 1, None))
+            .expect_err("Expected a dispute of another client's transaction to fail");
+        assert_eq!(err, Error::TransactionNotFound(1));
+    }
+
+    #[test]
+    fn test_skip_duplicate_tx_ids() {
+        let mut engine = Engine::with_options(ClientOptions {
+            skip_duplicate_tx_ids: true,
+            ..Default::default()
+        });
+
+        engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::new(2, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        // Duplicates of the same and of another client are skipped.
+        let err = engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::new(5, 0)),
+            ))
+            .expect_err("Expected a duplicate deposit to be skipped");
+        assert_eq!(err, Error::DuplicateTxId(1));
+        let err = engine
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                2,
+                1,
+                Some(Decimal::new(7, 0)),
+            ))
+            .expect_err("Expected a duplicate deposit to be skipped");
+        assert_eq!(err, Error::DuplicateTxId(1));
+        assert!(err.is_ignorable());
+
+        // Dispute refers to the first deposit.
+        engine
+            .process_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .expect("Failed to apply a transaction");
+        let c = engine.client(1).expect("Failed to get a client");
+        assert_eq!(c.available(), Decimal::new(0, 0));
+        assert_eq!(c.held(), Decimal::new(2, 0));
+        assert_eq!(c.total(), Decimal::new(2, 0));
+
+        // The client whose deposit was skipped can't refer to it.
+        let err = engine
+            .process_transaction(Transaction::new(TransactionType::Dispute, 2, 1, None))
+            .expect_err("Expected a dispute of a skipped duplicate to fail");
+        assert_eq!(err, Error::SkippedDuplicateTx(1));
+        assert!(engine.client(2).is_none());
+
+        // Without the option, the duplicate is applied.
+        let mut engine = Engine::new();
+        for amount in [2, 5] {
+            engine
+                .process_transaction(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::new(amount, 0)),
+                ))
+                .expect("Failed to apply a transaction");
+        }
+        assert_eq!(
+            engine.client(1).expect("Failed to get a client").total(),
+            Decimal::new(7, 0)
+        );
+    }
+
+    #[test]
+    fn test_shared_engine() {
+        const DEPOSITS: u32 = 1000;
+
+        let engine = Arc::new(SharedEngine::new());
+        // Start reading together with writing.
+        let barrier = Arc::new(Barrier::new(5));
+
+        let writer = {
+            let engine = Arc::clone(&engine);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for tx_id in 0..DEPOSITS {
+                    engine
+                        .process_transaction(Transaction::new(
+                            TransactionType::Deposit,
+                            1,
+                            tx_id,
+                            Some(Decimal::new(1, 0)),
+                        ))
+                        .expect("Failed to apply a transaction");
+                    // Dispute every other deposit to move funds between
+                    // available and held.
+                    if tx_id % 2 == 0 {
+                        engine
+                            .process_transaction(Transaction::new(
+                                TransactionType::Dispute,
+                                1,
+                                tx_id,
+                                None,
+                            ))
+                            .expect("Failed to apply a transaction");
+                    }
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let engine = Arc::clone(&engine);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    let mut last_total = Decimal::new(0, 0);
+                    for _ in 0..DEPOSITS {
+                        if let Some(c) = engine.client(1) {
+                            // Snapshot is always consistent.
+                            assert_eq!(c.available() + c.held(), c.total());
+                            assert!(c.total() >= last_total);
+                            last_total = c.total();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().expect("Writer thread panicked");
+        for reader in readers {
+            reader.join().expect("Reader thread panicked");
+        }
+
+        let c = engine.client(1).expect("Failed to get a client");
+        assert_eq!(c.available(), Decimal::from(DEPOSITS / 2));
+        assert_eq!(c.held(), Decimal::from(DEPOSITS / 2));
+        assert_eq!(c.total(), Decimal::from(DEPOSITS));
+    }
+}
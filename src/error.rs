@@ -4,10 +4,22 @@ use thiserror::Error;
 use crate::transaction::TransactionType;
 
 #[derive(Debug, Error)]
-pub(crate) enum Error {
+pub enum Error {
+    #[allow(clippy::upper_case_acronyms)]
     #[error(transparent)]
     CSV(#[from] csv::Error),
 
+    #[allow(clippy::upper_case_acronyms)]
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    Postgres(#[from] postgres::Error),
+
     #[error("client `{0}` not found")]
     ClientNotFound(u16),
 
@@ -18,21 +30,572 @@ pub(crate) enum Error {
         requested: Decimal,
     },
 
-    #[error("deposit/withdrawal transaction has to specify amount")]
-    WithoutAmount,
+    #[error("tx {tx} would leave client `{client}` with zero available funds")]
+    ZeroBalanceForbidden { client: u16, tx: u32 },
+
+    #[error("total funds of client `{client}` would exceed the cap ({cap})")]
+    BalanceCapExceeded { client: u16, cap: Decimal },
+
+    #[error("balance of client `{client}` would overflow")]
+    BalanceOverflow { client: u16 },
+
+    #[error("total funds of client `{client}` are not the sum of available and held ones")]
+    InconsistentTotal { client: u16 },
+
+    #[error("held funds ({held}) of client `{client}` exceed total funds ({total})")]
+    InvariantViolated {
+        client: u16,
+        held: Decimal,
+        total: Decimal,
+    },
+
+    #[error("{tx_type} tx {tx} has to specify amount")]
+    WithoutAmount { tx: u32, tx_type: TransactionType },
+
+    #[error("referred {tx_type} tx {tx} has no amount")]
+    ReferredWithoutAmount { tx: u32, tx_type: TransactionType },
+
+    #[error("{tx_type} tx {tx} must not specify amount (got {amount})")]
+    WithAmount {
+        tx: u32,
+        tx_type: TransactionType,
+        amount: Decimal,
+    },
+
+    #[error("transfer tx {tx} has to specify a target client other than its own")]
+    InvalidTarget { tx: u32 },
+
+    #[error("transfers can't be processed in multiple threads (tx {0})")]
+    UnsupportedTransfer(u32),
 
-    #[error("dispute/resolve transaction must not specify amount")]
-    WithAmount,
+    #[error("tx {tx} has to specify a positive amount (got {amount})")]
+    NonPositiveAmount { tx: u32, amount: Decimal },
 
-    #[error("client's account locked")]
-    ClientLocked,
+    #[error("invalid delimiter `{0}`, expected a single ASCII character")]
+    InvalidDelimiter(String),
+
+    #[error("invalid comment character `{0}`, expected a single ASCII character")]
+    InvalidCommentChar(String),
+
+    #[error(
+        "invalid table name `{0}`, expected an identifier, optionally qualified with a schema"
+    )]
+    InvalidTableName(String),
+
+    #[error("invalid amount `{0}`")]
+    InvalidAmount(String),
+
+    #[error("expected currency `{expected}`, found `{found}`")]
+    CurrencyMismatch { expected: String, found: String },
+
+    #[error("account of client `{0}` is locked")]
+    ClientLocked(u16),
 
     #[error("transaction not found")]
     TransactionNotFound(u32),
 
-    #[error("invalid transaction type `{0:?}`, only deposit/withdrawal can be referred")]
+    #[error("transactions of type `{0}` cannot be disputed")]
     InvalidTxType(TransactionType),
 
     #[error("transaction is not dissputed, cannot resolve/chargeback")]
     TxNotDisputed(u32),
+
+    #[error("transaction `{0}` is already disputed")]
+    AlreadyDisputed(u32),
+
+    #[error("transaction `{0}` is already charged back")]
+    AlreadyChargedBack(u32),
+
+    #[error("disputed amount {amount} exceeds the amount of transaction `{tx}`")]
+    DisputeExceedsAmount { tx: u32, amount: Decimal },
+
+    #[error("transaction of client `{found}` made on account of client `{expected}`")]
+    ClientMismatch { expected: u16, found: u16 },
+
+    #[error("transaction ID `{0}` is a duplicate")]
+    DuplicateTxId(u32),
+
+    #[error("transaction `{0}` was skipped as a duplicate, cannot be referred")]
+    SkippedDuplicateTx(u32),
+
+    #[error("chargeback of transaction `{0}` is not preceded by a dispute")]
+    ChargebackWithoutDispute(u32),
+
+    #[error("transaction `{0}` referred by a dispute lifecycle row is not in the restored state")]
+    TxNotRestored(u32),
+
+    #[error("transaction ID `{0}` is reserved")]
+    ReservedTxId(u32),
+
+    #[error("chain of references starting at tx {tx} is longer than {limit}")]
+    ChainTooLong { tx: u32, limit: usize },
+
+    #[error("invalid {field} `{value}`, expected a non-negative integer in range (line {line})")]
+    InvalidInteger {
+        field: &'static str,
+        line: u64,
+        value: String,
+    },
+
+    #[error("invalid header {found:?}, expected columns `type`, `client`, `tx` and `amount` (and optionally `timestamp`, `target` and `currency`)")]
+    InvalidHeader { found: Vec<String> },
+
+    #[error("row has {found} fields instead of {expected} (line {line})")]
+    FieldCountMismatch {
+        line: u64,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("transactions are not sorted by client (line {line})")]
+    NotSortedByClient { line: u64 },
+
+    #[error("{source} (row {row})")]
+    AtRow { row: u64, source: Box<Error> },
+
+    #[error("{path}: {source}")]
+    InFile { path: String, source: Box<Error> },
+
+    #[error("more than {0} transactions skipped, input is likely malformed")]
+    TooManySkips(u64),
+
+    #[error("{0} invalid rows found")]
+    InvalidRows(u64),
+
+    #[error("transaction {0} to reconstruct balances as of not found")]
+    AsOfTxNotFound(u32),
+
+    #[error(
+        "held funds ({actual}) of client `{client}` don't match disputed amounts ({expected})"
+    )]
+    AuditMismatch {
+        client: u16,
+        expected: Decimal,
+        actual: Decimal,
+    },
+
+    #[error("{0} clients failed the audit")]
+    AuditFailed(usize),
+
+    #[error("checkpoint doesn't record the progress of processing the input")]
+    NoCheckpointProgress,
+
+    #[error("input differs from the one of the checkpoint in the first {rows} rows")]
+    CheckpointInputMismatch { rows: u64 },
+
+    #[error("{0} clients cannot be renumbered to IDs starting from 1")]
+    TooManyClients(usize),
+
+    #[error("worker thread failed")]
+    WorkerFailed,
+
+    #[error("no transaction to undo")]
+    NothingToUndo,
+
+    #[cfg(unix)]
+    #[error("cannot connect to socket `{path}`, is the consumer listening? ({source})")]
+    SocketUnavailable {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("cannot create output file `{path}` ({source})")]
+    OutputUnavailable {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("output differs from `{expected}`:\n{diff}")]
+    OutputMismatch { expected: String, diff: String },
+}
+
+/// Errors are equal if they're of the same variant with equal fields. Errors of
+/// other crates (which don't implement `PartialEq`) are compared by their
+/// messages.
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::CSV(a), Error::CSV(b)) => a.to_string() == b.to_string(),
+            (Error::IO(a), Error::IO(b)) => a.to_string() == b.to_string(),
+            (Error::Json(a), Error::Json(b)) => a.to_string() == b.to_string(),
+            #[cfg(feature = "postgres")]
+            (Error::Postgres(a), Error::Postgres(b)) => a.to_string() == b.to_string(),
+            (Error::ClientNotFound(a), Error::ClientNotFound(b)) => a == b,
+            (
+                Error::BalanceCapExceeded { client, cap },
+                Error::BalanceCapExceeded {
+                    client: other_client,
+                    cap: other_cap,
+                },
+            ) => client == other_client && cap == other_cap,
+            (
+                Error::ZeroBalanceForbidden { client, tx },
+                Error::ZeroBalanceForbidden {
+                    client: other_client,
+                    tx: other_tx,
+                },
+            ) => client == other_client && tx == other_tx,
+            (
+                Error::NoFunds {
+                    client,
+                    available,
+                    requested,
+                },
+                Error::NoFunds {
+                    client: other_client,
+                    available: other_available,
+                    requested: other_requested,
+                },
+            ) => {
+                client == other_client
+                    && available == other_available
+                    && requested == other_requested
+            }
+            (
+                Error::BalanceOverflow { client },
+                Error::BalanceOverflow {
+                    client: other_client,
+                },
+            ) => client == other_client,
+            (
+                Error::InconsistentTotal { client },
+                Error::InconsistentTotal {
+                    client: other_client,
+                },
+            ) => client == other_client,
+            (
+                Error::InvariantViolated {
+                    client,
+                    held,
+                    total,
+                },
+                Error::InvariantViolated {
+                    client: other_client,
+                    held: other_held,
+                    total: other_total,
+                },
+            ) => client == other_client && held == other_held && total == other_total,
+            (
+                Error::WithoutAmount { tx, tx_type },
+                Error::WithoutAmount {
+                    tx: other_tx,
+                    tx_type: other_tx_type,
+                },
+            ) => tx == other_tx && tx_type == other_tx_type,
+            (
+                Error::ReferredWithoutAmount { tx, tx_type },
+                Error::ReferredWithoutAmount {
+                    tx: other_tx,
+                    tx_type: other_tx_type,
+                },
+            ) => tx == other_tx && tx_type == other_tx_type,
+            (
+                Error::WithAmount {
+                    tx,
+                    tx_type,
+                    amount,
+                },
+                Error::WithAmount {
+                    tx: other_tx,
+                    tx_type: other_tx_type,
+                    amount: other_amount,
+                },
+            ) => tx == other_tx && tx_type == other_tx_type && amount == other_amount,
+            (Error::InvalidTarget { tx }, Error::InvalidTarget { tx: other_tx }) => tx == other_tx,
+            (Error::UnsupportedTransfer(a), Error::UnsupportedTransfer(b)) => a == b,
+            (
+                Error::NonPositiveAmount { tx, amount },
+                Error::NonPositiveAmount {
+                    tx: other_tx,
+                    amount: other_amount,
+                },
+            ) => tx == other_tx && amount == other_amount,
+            (Error::InvalidDelimiter(a), Error::InvalidDelimiter(b)) => a == b,
+            (Error::InvalidCommentChar(a), Error::InvalidCommentChar(b)) => a == b,
+            (Error::InvalidTableName(a), Error::InvalidTableName(b)) => a == b,
+            (Error::InvalidAmount(a), Error::InvalidAmount(b)) => a == b,
+            (
+                Error::CurrencyMismatch { expected, found },
+                Error::CurrencyMismatch {
+                    expected: other_expected,
+                    found: other_found,
+                },
+            ) => expected == other_expected && found == other_found,
+            (Error::ClientLocked(a), Error::ClientLocked(b)) => a == b,
+            (Error::TransactionNotFound(a), Error::TransactionNotFound(b)) => a == b,
+            (Error::InvalidTxType(a), Error::InvalidTxType(b)) => a == b,
+            (Error::TxNotDisputed(a), Error::TxNotDisputed(b)) => a == b,
+            (Error::AlreadyDisputed(a), Error::AlreadyDisputed(b)) => a == b,
+            (Error::AlreadyChargedBack(a), Error::AlreadyChargedBack(b)) => a == b,
+            (
+                Error::DisputeExceedsAmount { tx, amount },
+                Error::DisputeExceedsAmount {
+                    tx: other_tx,
+                    amount: other_amount,
+                },
+            ) => tx == other_tx && amount == other_amount,
+            (
+                Error::ClientMismatch { expected, found },
+                Error::ClientMismatch {
+                    expected: other_expected,
+                    found: other_found,
+                },
+            ) => expected == other_expected && found == other_found,
+            (Error::DuplicateTxId(a), Error::DuplicateTxId(b)) => a == b,
+            (Error::SkippedDuplicateTx(a), Error::SkippedDuplicateTx(b)) => a == b,
+            (Error::ChargebackWithoutDispute(a), Error::ChargebackWithoutDispute(b)) => a == b,
+            (Error::TxNotRestored(a), Error::TxNotRestored(b)) => a == b,
+            (Error::ReservedTxId(a), Error::ReservedTxId(b)) => a == b,
+            (
+                Error::ChainTooLong { tx, limit },
+                Error::ChainTooLong {
+                    tx: other_tx,
+                    limit: other_limit,
+                },
+            ) => tx == other_tx && limit == other_limit,
+            (
+                Error::InvalidInteger { field, line, value },
+                Error::InvalidInteger {
+                    field: other_field,
+                    line: other_line,
+                    value: other_value,
+                },
+            ) => field == other_field && line == other_line && value == other_value,
+            (Error::InvalidHeader { found }, Error::InvalidHeader { found: other_found }) => {
+                found == other_found
+            }
+            (
+                Error::FieldCountMismatch {
+                    line,
+                    expected,
+                    found,
+                },
+                Error::FieldCountMismatch {
+                    line: other_line,
+                    expected: other_expected,
+                    found: other_found,
+                },
+            ) => line == other_line && expected == other_expected && found == other_found,
+            (Error::NotSortedByClient { line }, Error::NotSortedByClient { line: other_line }) => {
+                line == other_line
+            }
+            (
+                Error::AtRow { row, source },
+                Error::AtRow {
+                    row: other_row,
+                    source: other_source,
+                },
+            ) => row == other_row && source == other_source,
+            (
+                Error::InFile { path, source },
+                Error::InFile {
+                    path: other_path,
+                    source: other_source,
+                },
+            ) => path == other_path && source == other_source,
+            (Error::TooManySkips(a), Error::TooManySkips(b)) => a == b,
+            (Error::InvalidRows(a), Error::InvalidRows(b)) => a == b,
+            (Error::AsOfTxNotFound(a), Error::AsOfTxNotFound(b)) => a == b,
+            (
+                Error::AuditMismatch {
+                    client,
+                    expected,
+                    actual,
+                },
+                Error::AuditMismatch {
+                    client: other_client,
+                    expected: other_expected,
+                    actual: other_actual,
+                },
+            ) => client == other_client && expected == other_expected && actual == other_actual,
+            (Error::AuditFailed(a), Error::AuditFailed(b)) => a == b,
+            (Error::NoCheckpointProgress, Error::NoCheckpointProgress) => true,
+            (
+                Error::CheckpointInputMismatch { rows },
+                Error::CheckpointInputMismatch { rows: other_rows },
+            ) => rows == other_rows,
+            (Error::TooManyClients(a), Error::TooManyClients(b)) => a == b,
+            (Error::WorkerFailed, Error::WorkerFailed) => true,
+            (Error::NothingToUndo, Error::NothingToUndo) => true,
+            #[cfg(unix)]
+            (
+                Error::SocketUnavailable { path, source },
+                Error::SocketUnavailable {
+                    path: other_path,
+                    source: other_source,
+                },
+            ) => path == other_path && source.to_string() == other_source.to_string(),
+            (
+                Error::OutputUnavailable { path, source },
+                Error::OutputUnavailable {
+                    path: other_path,
+                    source: other_source,
+                },
+            ) => path == other_path && source.to_string() == other_source.to_string(),
+            (
+                Error::OutputMismatch { expected, diff },
+                Error::OutputMismatch {
+                    expected: other_expected,
+                    diff: other_diff,
+                },
+            ) => expected == other_expected && diff == other_diff,
+            _ => false,
+        }
+    }
+}
+
+impl Error {
+    /// Returns true if the error affects only the given transaction, which can
+    /// be skipped, and processing of the next transactions can proceed.
+    pub fn is_ignorable(&self) -> bool {
+        if let Error::InFile { source, .. } = self {
+            return source.is_ignorable();
+        }
+        matches!(
+            self,
+            Error::NoFunds { .. }
+                | Error::ZeroBalanceForbidden { .. }
+                | Error::BalanceCapExceeded { .. }
+                | Error::BalanceOverflow { .. }
+                | Error::NonPositiveAmount { .. }
+                | Error::ClientLocked(_)
+                | Error::TransactionNotFound(_)
+                | Error::TxNotDisputed(_)
+                | Error::AlreadyDisputed(_)
+                | Error::AlreadyChargedBack(_)
+                | Error::DisputeExceedsAmount { .. }
+                | Error::ClientMismatch { .. }
+                | Error::DuplicateTxId(_)
+                | Error::SkippedDuplicateTx(_)
+                | Error::ChargebackWithoutDispute(_)
+                | Error::ReservedTxId(_)
+                | Error::ChainTooLong { .. }
+                | Error::ReferredWithoutAmount { .. }
+        )
+    }
+
+    /// Number of the line of the input which caused the error, if known.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Error::CSV(e) => e.position().map(|position| position.line()),
+            Error::InvalidInteger { line, .. } | Error::FieldCountMismatch { line, .. } => {
+                Some(*line)
+            }
+            Error::AtRow { row, .. } => Some(*row),
+            Error::InFile { source, .. } => source.line(),
+            _ => None,
+        }
+    }
+
+    /// The error without the path of the file it occurred in (see
+    /// [`Error::InFile`]).
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::InFile { source, .. } => source.inner(),
+            e => e,
+        }
+    }
+
+    /// Short name of the kind of the error, used e.g. for counting skipped
+    /// transactions.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::CSV(_) => "csv",
+            Error::IO(_) => "io",
+            Error::Json(_) => "json",
+            #[cfg(feature = "postgres")]
+            Error::Postgres(_) => "postgres",
+            Error::ClientNotFound(_) => "client_not_found",
+            Error::NoFunds { .. } => "no_funds",
+            Error::ZeroBalanceForbidden { .. } => "zero_balance_forbidden",
+            Error::BalanceCapExceeded { .. } => "balance_cap_exceeded",
+            Error::BalanceOverflow { .. } => "balance_overflow",
+            Error::InconsistentTotal { .. } => "inconsistent_total",
+            Error::InvariantViolated { .. } => "invariant_violated",
+            Error::WithoutAmount { .. } => "without_amount",
+            Error::ReferredWithoutAmount { .. } => "referred_without_amount",
+            Error::WithAmount { .. } => "with_amount",
+            Error::InvalidTarget { .. } => "invalid_target",
+            Error::UnsupportedTransfer(_) => "unsupported_transfer",
+            Error::NonPositiveAmount { .. } => "non_positive_amount",
+            Error::InvalidDelimiter(_) => "invalid_delimiter",
+            Error::InvalidCommentChar(_) => "invalid_comment_char",
+            Error::InvalidTableName(_) => "invalid_table_name",
+            Error::InvalidAmount(_) => "invalid_amount",
+            Error::CurrencyMismatch { .. } => "currency_mismatch",
+            Error::ClientLocked(_) => "client_locked",
+            Error::TransactionNotFound(_) => "transaction_not_found",
+            Error::InvalidTxType(_) => "invalid_tx_type",
+            Error::TxNotDisputed(_) => "tx_not_disputed",
+            Error::AlreadyDisputed(_) => "already_disputed",
+            Error::AlreadyChargedBack(_) => "already_charged_back",
+            Error::DisputeExceedsAmount { .. } => "dispute_exceeds_amount",
+            Error::ClientMismatch { .. } => "client_mismatch",
+            Error::DuplicateTxId(_) => "duplicate_tx_id",
+            Error::SkippedDuplicateTx(_) => "skipped_duplicate_tx",
+            Error::ChargebackWithoutDispute(_) => "chargeback_without_dispute",
+            Error::TxNotRestored(_) => "tx_not_restored",
+            Error::ReservedTxId(_) => "reserved_tx_id",
+            Error::ChainTooLong { .. } => "chain_too_long",
+            Error::InvalidInteger { .. } => "invalid_integer",
+            Error::InvalidHeader { .. } => "invalid_header",
+            Error::FieldCountMismatch { .. } => "field_count_mismatch",
+            Error::NotSortedByClient { .. } => "not_sorted_by_client",
+            Error::AtRow { .. } => "at_row",
+            Error::InFile { source, .. } => source.kind(),
+            Error::TooManySkips(_) => "too_many_skips",
+            Error::InvalidRows(_) => "invalid_rows",
+            Error::AsOfTxNotFound(_) => "as_of_tx_not_found",
+            Error::AuditMismatch { .. } => "audit_mismatch",
+            Error::AuditFailed(_) => "audit_failed",
+            Error::NoCheckpointProgress => "no_checkpoint_progress",
+            Error::CheckpointInputMismatch { .. } => "checkpoint_input_mismatch",
+            Error::TooManyClients(_) => "too_many_clients",
+            Error::WorkerFailed => "worker_failed",
+            Error::NothingToUndo => "nothing_to_undo",
+            #[cfg(unix)]
+            Error::SocketUnavailable { .. } => "socket_unavailable",
+            Error::OutputUnavailable { .. } => "output_unavailable",
+            Error::OutputMismatch { .. } => "output_mismatch",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eq() {
+        assert_eq!(Error::ClientLocked(1), Error::ClientLocked(1));
+        assert_ne!(Error::ClientLocked(1), Error::ClientLocked(2));
+        assert_ne!(Error::TransactionNotFound(1), Error::TxNotDisputed(1));
+        assert_eq!(
+            Error::NoFunds {
+                client: 1,
+                available: Decimal::new(1, 0),
+                requested: Decimal::new(2, 0),
+            },
+            Error::NoFunds {
+                client: 1,
+                available: Decimal::new(10, 1),
+                requested: Decimal::new(2, 0),
+            }
+        );
+        assert_eq!(
+            Error::AtRow {
+                row: 2,
+                source: Box::new(Error::AlreadyDisputed(1)),
+            },
+            Error::AtRow {
+                row: 2,
+                source: Box::new(Error::AlreadyDisputed(1)),
+            }
+        );
+
+        // Errors of other crates are compared by their messages.
+        let io_err = |msg| Error::IO(std::io::Error::other(msg));
+        assert_eq!(io_err("broken pipe"), io_err("broken pipe"));
+        assert_ne!(io_err("broken pipe"), io_err("no space left"));
+    }
 }
@@ -0,0 +1,201 @@
+use std::io::Write;
+
+use csv::Writer;
+use rust_decimal::Decimal;
+
+use tranzaktionz::{Error, TransactionType};
+
+/// Scale of generated amounts.
+const AMOUNT_SCALE: u32 = 4;
+/// Maximal generated amount, in units of the scale.
+const MAX_AMOUNT: u64 = 10_000_000;
+/// Probability that a closed dispute is charged back instead of resolved.
+const CHARGEBACK_RATE: f64 = 0.1;
+
+/// Options of generating synthetic transactions.
+#[derive(Debug)]
+pub(crate) struct GeneratorOptions {
+    /// Number of clients.
+    pub(crate) clients: u16,
+    /// Number of rows.
+    pub(crate) rows: u32,
+    /// Seed of the pseudo-random number generator.
+    pub(crate) seed: u64,
+    /// Probability that a row is a dispute, resolve or chargeback.
+    pub(crate) dispute_rate: f64,
+    /// Probability that a row is a dispute referring to a non-existent
+    /// transaction.
+    pub(crate) error_rate: f64,
+}
+
+/// Pseudo-random number generator (SplitMix64). It's not suitable for anything
+/// but generating test data, but it's deterministic across platforms and
+/// versions, so the same seed always gives the same transactions.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in the range `[0, n)`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns true with the given probability.
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// State of a client account, tracked to generate only valid transactions.
+#[derive(Clone, Default)]
+struct Account {
+    available: Decimal,
+    /// Deposits which weren't disputed yet.
+    deposits: Vec<(u32, Decimal)>,
+    /// Open disputes.
+    disputed: Vec<(u32, Decimal)>,
+}
+
+/// Writes a synthetic CSV series of transactions to the writer.
+///
+/// Unless `error_rate` is non-zero, every transaction can be applied: deposits
+/// are disputed at most once and only while their funds are available,
+/// withdrawals don't exceed available funds, and resolves and chargebacks
+/// refer to open disputes. Accounts locked by chargebacks don't get any more
+/// transactions, and the last unlocked account is never charged back.
+pub(crate) fn generate<W: Write>(options: &GeneratorOptions, writer: W) -> Result<(), Error> {
+    let mut wtr = Writer::from_writer(writer);
+    wtr.write_record(["type", "client", "tx", "amount"])?;
+
+    let mut rng = Rng::new(options.seed);
+    let mut accounts = vec![Account::default(); options.clients as usize];
+    // IDs of clients with unlocked accounts.
+    let mut unlocked: Vec<u16> = (1..=options.clients).collect();
+
+    for tx in 1..=options.rows {
+        if unlocked.is_empty() {
+            break;
+        }
+        let idx = rng.below(unlocked.len() as u64) as usize;
+        let client = unlocked[idx];
+        let account = &mut accounts[client as usize - 1];
+
+        // Deposits whose funds weren't withdrawn, so they can be held.
+        let disputable: Vec<usize> = account
+            .deposits
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, amount))| *amount <= account.available)
+            .map(|(i, _)| i)
+            .collect();
+
+        let (tx_type, tx_id, amount) = if rng.chance(options.error_rate) {
+            // The transaction IDs are sequential, so it was never used.
+            (TransactionType::Dispute, u32::MAX - tx, None)
+        } else if rng.chance(options.dispute_rate)
+            && !(disputable.is_empty() && account.disputed.is_empty())
+        {
+            if !account.disputed.is_empty() && (disputable.is_empty() || rng.chance(0.5)) {
+                let (tx_id, amount) = account.disputed.swap_remove(0);
+                if unlocked.len() > 1 && rng.chance(CHARGEBACK_RATE) {
+                    unlocked.swap_remove(idx);
+                    (TransactionType::Chargeback, tx_id, None)
+                } else {
+                    account.available += amount;
+                    (TransactionType::Resolve, tx_id, None)
+                }
+            } else {
+                let i = disputable[rng.below(disputable.len() as u64) as usize];
+                let (tx_id, amount) = account.deposits.swap_remove(i);
+                account.available -= amount;
+                account.disputed.push((tx_id, amount));
+                (TransactionType::Dispute, tx_id, None)
+            }
+        } else {
+            let amount = Decimal::new(rng.below(MAX_AMOUNT) as i64 + 1, AMOUNT_SCALE);
+            if account.available >= amount && rng.chance(0.5) {
+                account.available -= amount;
+                (TransactionType::Withdrawal, tx, Some(amount))
+            } else {
+                account.available += amount;
+                account.deposits.push((tx, amount));
+                (TransactionType::Deposit, tx, Some(amount))
+            }
+        };
+
+        wtr.write_record([
+            tx_type.to_string(),
+            client.to_string(),
+            tx_id.to_string(),
+            amount.map_or_else(String::new, |amount| amount.to_string()),
+        ])?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tranzaktionz::{Engine, Error, ReaderOptions};
+
+    fn options(error_rate: f64) -> GeneratorOptions {
+        GeneratorOptions {
+            clients: 20,
+            rows: 10_000,
+            seed: 42,
+            dispute_rate: 0.3,
+            error_rate,
+        }
+    }
+
+    /// Returns numbers of applied and skipped transactions.
+    fn apply(data: &[u8]) -> (u64, u64) {
+        let mut engine = Engine::new();
+        let (mut applied, mut skipped) = (0, 0);
+        for (_, result) in engine.process_reader(data, ReaderOptions::default()) {
+            match result {
+                Ok(()) => applied += 1,
+                Err(Error::CSV(e)) => panic!("Failed to read a transaction: {}", e),
+                Err(_) => skipped += 1,
+            }
+        }
+        (applied, skipped)
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut data = Vec::new();
+        generate(&options(0.0), &mut data).expect("Failed to generate transactions");
+        assert_eq!(apply(&data), (10_000, 0));
+
+        // The same seed gives the same transactions.
+        let mut data2 = Vec::new();
+        generate(&options(0.0), &mut data2).expect("Failed to generate transactions");
+        assert_eq!(data, data2);
+    }
+
+    #[test]
+    fn test_generate_with_errors() {
+        let mut data = Vec::new();
+        generate(&options(0.1), &mut data).expect("Failed to generate transactions");
+        let (applied, skipped) = apply(&data);
+        assert_eq!(applied + skipped, 10_000);
+        assert!(skipped > 0);
+    }
+}
@@ -0,0 +1,21 @@
+/// Offset basis of the 64-bit FNV-1a hash, i.e. the hash of no data.
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Computes the 64-bit FNV-1a hash of the data, formatted as 16 hex digits.
+///
+/// It's not a cryptographic hash - it detects accidental modifications, not
+/// deliberate ones.
+pub fn fnv1a(data: &[u8]) -> String {
+    format!("{:016x}", fnv1a_update(FNV_OFFSET_BASIS, data))
+}
+
+/// Continues the 64-bit FNV-1a hash of preceding data (starting with
+/// [`FNV_OFFSET_BASIS`]) with the given data, which allows to hash data
+/// coming in parts.
+pub fn fnv1a_update(hash: u64, data: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    data.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
@@ -0,0 +1,318 @@
+use std::io::Read;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    client::Client,
+    engine::Engine,
+    error::Error,
+    metrics::Skips,
+    reader::{ReaderOptions, TransactionReader},
+    transaction::Transaction,
+};
+
+/// Policy deciding which failing transactions are skipped, instead of
+/// stopping the processing.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SkipPolicy {
+    /// Skip transactions failing with errors which can be ignored (see
+    /// [`Error::is_ignorable`]).
+    #[default]
+    Ignorable,
+    /// Skip no transactions, stop on the first error.
+    Never,
+    /// Skip transactions failing with errors for which the function returns
+    /// `true`.
+    Custom(fn(&Error) -> bool),
+}
+
+impl SkipPolicy {
+    /// Whether a transaction failing with the given error is skipped.
+    pub fn skips(&self, e: &Error) -> bool {
+        match self {
+            SkipPolicy::Ignorable => e.is_ignorable(),
+            SkipPolicy::Never => false,
+            SkipPolicy::Custom(skips) => skips(e),
+        }
+    }
+}
+
+/// State of the account of a client.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ClientView {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl From<&Client> for ClientView {
+    fn from(client: &Client) -> ClientView {
+        ClientView {
+            client: client.id(),
+            available: client.available(),
+            held: client.held(),
+            total: client.total(),
+            locked: client.locked(),
+        }
+    }
+}
+
+/// Ledger of client accounts, applying transactions with an [`Engine`] and
+/// skipping ones which fail according to its [`SkipPolicy`].
+#[derive(Debug, Default)]
+pub struct Ledger {
+    engine: Engine,
+    policy: SkipPolicy,
+    /// Numbers of skipped transactions.
+    skips: Skips,
+    /// Error of the transaction skipped by the latest [`Ledger::apply`].
+    skipped: Option<Error>,
+}
+
+impl Ledger {
+    /// Create a new ledger without any clients, skipping transactions which
+    /// fail with errors that can be ignored.
+    pub fn new() -> Ledger {
+        Ledger::default()
+    }
+
+    /// Create a new ledger applying transactions with the given engine (e.g.
+    /// with options or restored accounts).
+    pub fn with_engine(engine: Engine) -> Ledger {
+        Ledger {
+            engine,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the ledger skipping transactions according to the given
+    /// policy.
+    pub fn with_skip_policy(self, policy: SkipPolicy) -> Ledger {
+        Ledger { policy, ..self }
+    }
+
+    /// Applies the transaction, creating the account of its client if
+    /// needed.
+    ///
+    /// Transactions failing with errors which are skipped by the policy
+    /// leave accounts untouched and are counted, with the error available
+    /// from [`Ledger::take_skipped`]. Other errors are returned.
+    pub fn apply(&mut self, tx: Transaction) -> Result<(), Error> {
+        self.skipped = None;
+        match self.engine.process_transaction(tx) {
+            Err(e) if self.policy.skips(&e) => {
+                self.skip(e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Counts the transaction skipped with the given error.
+    fn skip(&mut self, e: Error) {
+        *self.skips.entry(e.kind().into()).or_default() += 1;
+        self.skipped = Some(e);
+    }
+
+    /// Applies the series of transactions (see [`Ledger::apply`]), stopping
+    /// on the first error which isn't skipped.
+    pub fn apply_all<I>(&mut self, txs: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        txs.into_iter().try_for_each(|tx| self.apply(tx))
+    }
+
+    /// Reads a CSV series of transactions from the source with the given
+    /// options and applies them (see [`Ledger::apply`]), returning errors of
+    /// skipped rows with numbers of their lines. Rows which can't be read are
+    /// skipped according to the policy too.
+    ///
+    /// Stops on the first error which isn't skipped.
+    pub fn process_reader<R: Read>(
+        &mut self,
+        source: R,
+        options: ReaderOptions,
+    ) -> Result<Vec<(u64, Error)>, Error> {
+        let mut skipped = Vec::new();
+        let mut line = 1;
+        for row in TransactionReader::new(source, options)? {
+            match row {
+                Ok((row_line, tx)) => {
+                    line = row_line;
+                    self.apply(tx)?;
+                }
+                Err(e) if self.policy.skips(&e) => {
+                    line = e.line().unwrap_or(line + 1);
+                    self.skip(e);
+                }
+                Err(e) => return Err(e),
+            }
+            if let Some(e) = self.take_skipped() {
+                skipped.push((line, e));
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Takes the error of the transaction skipped by the latest
+    /// [`Ledger::apply`], if it was skipped.
+    pub fn take_skipped(&mut self) -> Option<Error> {
+        self.skipped.take()
+    }
+
+    /// Numbers of skipped transactions, by kind of the error.
+    pub fn skips(&self) -> &Skips {
+        &self.skips
+    }
+
+    /// Returns states of accounts of all clients, ordered by client ID.
+    pub fn snapshot(&self) -> Vec<ClientView> {
+        self.engine.clients().map(ClientView::from).collect()
+    }
+
+    /// Engine applying the transactions.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Mutable engine applying the transactions, e.g. to take its trace.
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    /// Consumes the ledger, returning its engine.
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transaction::TransactionType;
+
+    #[test]
+    fn test_apply() {
+        let mut ledger = Ledger::new();
+        ledger
+            .apply_all([
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+                Transaction::new(TransactionType::Deposit, 2, 2, Some(Decimal::new(3, 0))),
+                Transaction::new(TransactionType::Withdrawal, 2, 3, Some(Decimal::new(4, 0))),
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+                Transaction::new(TransactionType::Dispute, 1, 9, None),
+                Transaction::new(TransactionType::Chargeback, 1, 1, None),
+            ])
+            .expect("Failed to apply transactions");
+        ledger
+            .apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                4,
+                Some(Decimal::new(1, 0)),
+            ))
+            .expect("Failed to apply a transaction");
+        assert_eq!(ledger.take_skipped(), Some(Error::ClientLocked(1)));
+        assert_eq!(ledger.take_skipped(), None);
+        assert_eq!(
+            ledger.skips(),
+            &Skips::from([
+                ("client_locked".into(), 1),
+                ("no_funds".into(), 1),
+                ("transaction_not_found".into(), 1)
+            ])
+        );
+        assert_eq!(
+            ledger.snapshot(),
+            vec![
+                ClientView {
+                    client: 1,
+                    available: Decimal::new(0, 0),
+                    held: Decimal::new(0, 0),
+                    total: Decimal::new(0, 0),
+                    locked: true,
+                },
+                ClientView {
+                    client: 2,
+                    available: Decimal::new(3, 0),
+                    held: Decimal::new(0, 0),
+                    total: Decimal::new(3, 0),
+                    locked: false,
+                },
+            ]
+        );
+
+        // Errors which can't be ignored stop the processing.
+        let err = ledger
+            .apply_all([
+                Transaction::new(TransactionType::Deposit, 2, 5, None),
+                Transaction::new(TransactionType::Deposit, 2, 6, Some(Decimal::new(1, 0))),
+            ])
+            .expect_err("Expected a deposit without amount to fail");
+        assert!(matches!(err, Error::WithoutAmount { tx: 5, .. }));
+        assert_eq!(ledger.snapshot()[1].total, Decimal::new(3, 0));
+    }
+
+    #[test]
+    fn test_process_reader() {
+        let data = "\
+type,       client, tx, amount
+deposit,         1,  1,    5.0
+withdrawal,      1,  2,    9.0
+deposit,    client,  3,    1.0
+dispute,         1,  1,
+";
+        let mut ledger = Ledger::new();
+        let err = ledger
+            .process_reader(data.as_bytes(), ReaderOptions::default())
+            .expect_err("Expected an invalid client ID to fail");
+        assert!(matches!(err, Error::InvalidInteger { line: 4, .. }));
+        assert_eq!(ledger.snapshot()[0].held, Decimal::new(0, 0));
+
+        let mut ledger = Ledger::new().with_skip_policy(SkipPolicy::Custom(|e| {
+            e.is_ignorable() || matches!(e, Error::InvalidInteger { .. })
+        }));
+        let skipped = ledger
+            .process_reader(data.as_bytes(), ReaderOptions::default())
+            .expect("Failed to process transactions");
+        let lines: Vec<u64> = skipped.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [3, 4]);
+        assert_eq!(
+            ledger.skips(),
+            &Skips::from([("invalid_integer".into(), 1), ("no_funds".into(), 1)])
+        );
+        assert_eq!(ledger.snapshot()[0].held, Decimal::new(50, 1));
+    }
+
+    #[test]
+    fn test_skip_policy() {
+        let txs = || {
+            [
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(5, 0))),
+                Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::new(9, 0))),
+                Transaction::new(TransactionType::Resolve, 1, 1, None),
+            ]
+        };
+
+        let mut ledger = Ledger::new().with_skip_policy(SkipPolicy::Never);
+        let err = ledger
+            .apply_all(txs())
+            .expect_err("Expected a withdrawal exceeding funds to fail");
+        assert!(matches!(err, Error::NoFunds { .. }));
+        assert!(ledger.skips().is_empty());
+
+        let mut ledger = Ledger::new()
+            .with_skip_policy(SkipPolicy::Custom(|e| matches!(e, Error::NoFunds { .. })));
+        let err = ledger
+            .apply_all(txs())
+            .expect_err("Expected a resolve of an undisputed tx to fail");
+        assert_eq!(err, Error::TxNotDisputed(1));
+        assert_eq!(ledger.skips(), &Skips::from([("no_funds".into(), 1)]));
+        assert_eq!(ledger.snapshot()[0].available, Decimal::new(5, 0));
+    }
+}
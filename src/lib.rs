@@ -0,0 +1,45 @@
+//! Engine processing series of off-chain transactions and keeping the state
+//! of clients accounts.
+//!
+//! ```
+//! use tranzaktionz::{Engine, Transaction, TransactionType};
+//!
+//! let mut engine = Engine::new();
+//! engine
+//!     .process_transaction(Transaction::new(
+//!         TransactionType::Deposit,
+//!         1,
+//!         1,
+//!         Some("1.5".parse().unwrap()),
+//!     ))
+//!     .unwrap();
+//! let client = engine.clients().next().unwrap();
+//! assert_eq!(client.available().to_string(), "1.5");
+//! ```
+
+pub mod checkpoint;
+mod client;
+mod engine;
+mod error;
+pub mod hash;
+mod ledger;
+mod metrics;
+mod parallel;
+mod reader;
+#[cfg(feature = "postgres")]
+mod sql;
+mod transaction;
+
+pub use client::{
+    round_balances, round_output, ChargebackLock, Client, ClientOptions, ClientSnapshot,
+    ScaleLimit, DEFAULT_DISPUTABLE_TYPES, OUTPUT_SCALE,
+};
+pub use engine::{Engine, SharedEngine, TraceEntry};
+pub use error::Error;
+pub use ledger::{ClientView, Ledger, SkipPolicy};
+pub use metrics::{Metrics, Skips};
+pub use parallel::ShardedEngine;
+pub use reader::{JsonLinesReader, ReaderOptions, TransactionReader};
+#[cfg(feature = "postgres")]
+pub use sql::PostgresReader;
+pub use transaction::{Transaction, TransactionType};
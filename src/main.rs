@@ -1,65 +1,2034 @@
-use std::{collections::BTreeMap, io, path::Path};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{self, BufRead, Write},
+    process::ExitCode,
+    rc::Rc,
+    thread,
+};
 
-use clap::Parser;
-use csv::{ReaderBuilder, Trim, WriterBuilder};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 
-mod client;
-mod error;
-mod transaction;
+use clap::{Parser, Subcommand, ValueEnum};
+use csv::{ReaderBuilder, Terminator, Trim, WriterBuilder};
+use flate2::read::MultiGzDecoder;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize, Serializer};
 
-use client::Client;
-use error::Error;
-use transaction::Transaction;
+#[cfg(feature = "postgres")]
+use tranzaktionz::PostgresReader;
+use tranzaktionz::{
+    checkpoint, hash, round_balances, round_output, ChargebackLock, Client, ClientOptions, Engine,
+    Error, JsonLinesReader, Ledger, Metrics, ReaderOptions, ScaleLimit, ShardedEngine, SkipPolicy,
+    Skips, TraceEntry, Transaction, TransactionReader, TransactionType, OUTPUT_SCALE,
+};
 
-#[derive(Debug, Parser)]
-#[clap(author, version, about)]
+mod diff;
+mod generate;
+mod manifest;
+mod segments;
+
+/// Query reading transactions from the database, when `--query` is not
+/// specified.
+#[cfg(feature = "postgres")]
+const DEFAULT_QUERY: &str = "SELECT type, client, tx, amount FROM transactions";
+
+/// Path of the input file meaning the standard input.
+const STDIN_PATH: &str = "-";
+/// Extension of input files which are decompressed with gzip.
+const GZIP_EXTENSION: &str = ".gz";
+
+/// Exit code of runs which completed, but skipped some transactions. Fatal
+/// errors exit with 1.
+const EXIT_SKIPPED: u8 = 2;
+
+/// Number of transactions sampled by `--auto-parallel`.
+const AUTO_PARALLEL_SAMPLE: usize = 10_000;
+/// Minimal number of distinct clients in the sample, for which `--auto-parallel`
+/// processes transactions in multiple threads.
+const AUTO_PARALLEL_MIN_CLIENTS: usize = 100;
+
+#[derive(Debug, Parser, Serialize)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// File with CSV series of transactions
-    #[clap()]
-    file: String,
+    #[clap(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
+    /// Files with CSV series of transactions, each with its own header, read
+    /// one after another as a single series; the standard input is read if
+    /// a file is `-` or none is specified
+    file: Vec<String>,
+
+    /// URL of a PostgreSQL database to read transactions from, instead of
+    /// a file
+    #[cfg(feature = "postgres")]
+    #[clap(long, value_name = "URL", conflicts_with = "file")]
+    #[serde(serialize_with = "manifest::serialize_redacted_url")]
+    source: Option<String>,
+
+    /// Query returning transactions from the database, with `type`, `client`,
+    /// `tx` and `amount` columns
+    #[cfg(feature = "postgres")]
+    #[clap(long, value_name = "SQL", requires = "source")]
+    query: Option<String>,
+
+    /// Fail if the transactions are not sorted by client ID
+    #[clap(long)]
+    assert_sorted_by_client: bool,
+
+    /// Automatically resolve disputes opened more than the given number of
+    /// seconds before the last transaction
+    #[clap(long, value_name = "SECONDS")]
+    dispute_expiry: Option<u64>,
+
+    /// Compare the output with the given file instead of printing it, fail if
+    /// they differ
+    #[clap(long, value_name = "EXPECTED")]
+    compare_to: Option<String>,
+
+    /// Previous output of tranzaktionz to compare the clients with
+    #[clap(long, value_name = "PATH", requires = "changed-only")]
+    baseline: Option<String>,
+
+    /// Output only clients which are new or changed since the baseline
+    #[clap(long, requires = "baseline")]
+    changed_only: bool,
+
+    /// Skip rows repeating the header (e.g. in concatenated files)
+    #[clap(long)]
+    skip_repeated_headers: bool,
+
+    /// CSV file assigning clients to groups (with `client` and `group`
+    /// columns)
+    #[clap(long, value_name = "PATH", requires = "subtotals")]
+    segments: Option<String>,
+
+    /// Group clients by segments and output subtotals of each group
+    #[clap(long, requires = "segments")]
+    subtotals: bool,
+
+    /// Skip transactions with ID 0, which is reserved
+    #[clap(long)]
+    reject_tx_zero: bool,
+
+    /// Abort when more than the given number of transactions is skipped
+    #[clap(long, value_name = "N")]
+    max_skips: Option<u64>,
+
+    /// Don't print the summary of processing to stderr
+    #[clap(long)]
+    quiet: bool,
+
+    /// Abort on the first transaction which can't be applied, instead of
+    /// skipping it
+    #[clap(
+        long,
+        conflicts_with_all = &["max-skips", "auto-parallel", "threads"]
+    )]
+    strict: bool,
+
+    /// Accept amounts in additional formats (with `_` digit separators or
+    /// a trailing currency code)
+    #[clap(long)]
+    lenient_amounts: bool,
+
+    /// Decimal separator of amounts in the input; with `comma`, dots separate
+    /// groups of thousands (e.g. `1.234,56`)
+    #[clap(long, value_enum, value_name = "SEPARATOR", default_value = "dot")]
+    decimal_separator: DecimalSeparator,
+
+    /// Currency code which amounts have to be in, if they specify one
+    /// (e.g. `1.50 USD`); unlike `--currency`, other codes are invalid input
+    #[clap(long, value_name = "CODE", requires = "lenient-amounts")]
+    expected_currency: Option<String>,
+
+    /// Process transactions in multiple threads if the input has enough
+    /// distinct clients
+    #[clap(long)]
+    auto_parallel: bool,
+
+    /// Process transactions in the given number of threads, each owning a
+    /// disjoint set of clients
+    #[clap(long, value_name = "N", conflicts_with = "auto-parallel")]
+    threads: Option<usize>,
+
+    /// Write counters describing the processing as JSON to the given file
+    #[clap(long, value_name = "PATH")]
+    metrics_json: Option<String>,
+
+    /// Append a `checksum` column with a hash of each client row
+    #[clap(long, conflicts_with = "subtotals")]
+    row_checksum: bool,
+
+    /// Write transactions rejected due to a locked account to the given CSV
+    /// file
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = &["auto-parallel", "threads"]
+    )]
+    report_blocked: Option<String>,
+
+    /// Write skipped transactions with the errors they were skipped due to to
+    /// the given CSV file
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = &["auto-parallel", "threads"]
+    )]
+    log_errors: Option<String>,
+
+    /// Write every operation which changed an account, with balances of the
+    /// account after it, to the given CSV file
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = &["auto-parallel", "threads"]
+    )]
+    trace: Option<String>,
+
+    /// Treat resolves of already resolved disputes as no-ops instead of
+    /// skipping them as errors
+    #[clap(long)]
+    idempotent_resolve: bool,
+
+    /// Only check that all rows are valid transactions, without applying them
+    /// to any account, and report invalid ones
+    #[clap(long)]
+    validate_only: bool,
+
+    /// Column to sort the output by, clients with equal values are sorted by
+    /// ID
+    #[clap(long, value_enum, value_name = "COLUMN", default_value = "id")]
+    sort_by: SortKey,
+
+    /// Order of sorting the output by `--sort-by` column
+    #[clap(long, value_enum, default_value = "asc")]
+    order: SortOrder,
+
+    /// Write held funds of clients with open disputes to the given CSV file
+    #[clap(long, value_name = "PATH")]
+    liabilities: Option<String>,
+
+    /// Skip deposits, withdrawals, adjustments and transfers with IDs of
+    /// already applied ones, so disputes always refer to the first one
+    #[clap(long, conflicts_with_all = &["auto-parallel", "threads"])]
+    skip_duplicate_tx_ids: bool,
+
+    /// Skip rows with a number of fields different from the header, instead
+    /// of failing
+    #[clap(long)]
+    skip_ragged_rows: bool,
+
+    /// Skip rows with `client` or `tx` fields which are not integers in range,
+    /// instead of failing
+    #[clap(long)]
+    skip_invalid_integers: bool,
+
+    /// Renumber clients in the output to 1..=N, in the order of their IDs
+    #[clap(long)]
+    normalize_client_ids: bool,
+
+    /// Write the mapping of original client IDs to normalized ones to the
+    /// given CSV file
+    #[clap(long, value_name = "PATH", requires = "normalize-client-ids")]
+    id_map: Option<String>,
+
+    /// Write the complete state of all accounts to the given file, so
+    /// processing can be resumed with `--restore`
+    #[clap(long, value_name = "PATH", alias = "checkpoint-path")]
+    checkpoint: Option<String>,
+
+    /// Also write the state of all accounts to the `--checkpoint` file every
+    /// N input rows, so an interrupted run can be continued with `--resume`
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "checkpoint",
+        conflicts_with_all = &["auto-parallel", "threads"]
+    )]
+    checkpoint_interval: Option<u64>,
+
+    /// Continue an interrupted run from the state in the `--checkpoint` file,
+    /// skipping input rows processed before it was written
+    #[clap(
+        long,
+        requires = "checkpoint",
+        conflicts_with_all = &[
+            "restore",
+            "initial-state",
+            "with-delta",
+            "log-errors",
+            "trace",
+            "report-blocked",
+        ]
+    )]
+    resume: bool,
+
+    /// Resume processing from the state of accounts written by
+    /// `--checkpoint`
+    #[clap(long, value_name = "PATH")]
+    restore: Option<String>,
+
+    /// Start from balances of clients in the given CSV file with the columns
+    /// of the output (e.g. the output of a previous run)
+    #[clap(long, value_name = "PATH", conflicts_with = "restore")]
+    initial_state: Option<String>,
+
+    /// Skip rows identical to the preceding ones (e.g. retry artifacts)
+    #[clap(long)]
+    dedup_consecutive: bool,
+
+    /// Add a `locked_at` column with the timestamp of the chargeback which
+    /// locked the account
+    #[clap(long, conflicts_with = "subtotals")]
+    with_lock_time: bool,
+
+    /// Add a `tx_count` column with the number of transactions in the history
+    /// of the client
+    #[clap(long, conflicts_with = "subtotals")]
+    with_counts: bool,
+
+    /// Omit unlocked clients with zero total and held funds and no applied
+    /// deposits or withdrawals (e.g. created only by a stray dispute)
+    #[clap(long)]
+    hide_empty: bool,
+
+    /// Skip chargebacks which are not preceded by a dispute of the same
+    /// transaction in the input
+    #[clap(long)]
+    require_dispute_before_chargeback: bool,
+
+    /// Send the output to a consumer listening on the given Unix domain
+    /// socket instead of printing it
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = &["compare-to", "output"]
+    )]
+    emit_socket: Option<String>,
+
+    /// Round balances to at most the given number of decimal places after
+    /// each transaction, trading exactness for bounded cost of arithmetic
+    #[clap(long, value_name = "N")]
+    max_internal_scale: Option<u32>,
+
+    /// Strategy of rounding balances limited by `--max-internal-scale`
+    #[clap(
+        long,
+        value_enum,
+        value_name = "STRATEGY",
+        default_value = "half-even",
+        requires = "max-internal-scale"
+    )]
+    internal_rounding: Rounding,
+
+    /// Comma-separated types of transactions which can be disputed
+    #[clap(
+        long,
+        value_enum,
+        value_name = "TYPES",
+        use_value_delimiter = true,
+        default_value = "deposit,withdrawal"
+    )]
+    disputable_types: Vec<DisputableType>,
+
+    /// Skip disputes, resolves and chargebacks of transactions which can't be
+    /// disputed, instead of aborting
+    #[clap(long)]
+    skip_undisputable: bool,
+
+    /// Allow disputes, resolves and chargebacks of transactions on locked
+    /// accounts
+    #[clap(long)]
+    disputes_ignore_lock: bool,
+
+    /// Allow disputes to specify an amount (up to the amount of the disputed
+    /// transaction), holding only that part of it
+    #[clap(long)]
+    partial_disputes: bool,
+
+    /// Reject withdrawals and outgoing transfers which would leave exactly
+    /// zero available funds
+    #[clap(long)]
+    forbid_zeroing: bool,
+
+    /// Reject deposits, incoming transfers and positive adjustments which
+    /// would make total funds of a client exceed the given amount
+    #[clap(long, value_name = "DECIMAL")]
+    max_balance: Option<Decimal>,
+
+    /// Chargebacks which lock the account of the client
+    #[clap(long, value_enum, value_name = "WHEN", default_value = "always")]
+    lock_on_chargeback: LockOnChargeback,
+
+    /// Fail if held funds of a client exceed their total funds after any
+    /// transaction
+    #[clap(long)]
+    verify_invariants: bool,
+
+    /// After processing, check that held funds of each client are the sum of
+    /// their disputed amounts, reporting clients which don't pass
+    #[clap(long)]
+    audit: bool,
+
+    /// Keep only the given number of the most recent transactions of each
+    /// client (and ones with open disputes), so older ones can't be disputed
+    #[clap(long, value_name = "N")]
+    max_history: Option<usize>,
+
+    /// Add `delta_available`, `delta_held` and `delta_total` columns with
+    /// changes of balances since the state restored with `--restore` or
+    /// `--initial-state`
+    #[clap(
+        long,
+        conflicts_with_all = &["subtotals", "normalize-client-ids"]
+    )]
+    with_delta: bool,
+
+    /// Apply only transactions in the given currency, skipping rows with
+    /// a different one in the `currency` column (rows without it are applied);
+    /// unlike `--expected-currency`, other currencies are valid input
+    #[clap(long, value_name = "CODE")]
+    currency: Option<String>,
+
+    /// Apply only disputes, resolves and chargebacks, referring to
+    /// transactions in the state restored with `--restore`, skipping other
+    /// rows
+    #[clap(
+        long,
+        requires = "restore",
+        conflicts_with_all = &["auto-parallel", "threads"]
+    )]
+    lifecycle_only: bool,
+
+    /// Line endings of the output
+    #[clap(long, value_enum, default_value = "lf")]
+    line_ending: LineEnding,
+
+    /// Number of decimal places balances are rounded to in the output
+    #[clap(long, value_name = "N", default_value_t = OUTPUT_SCALE)]
+    scale: u32,
+
+    /// Format of the input files
+    #[clap(long, value_enum, value_name = "FORMAT", default_value = "csv")]
+    input_format: InputFormat,
+
+    /// Decompress the input with gzip, which is done anyway for files with
+    /// the `.gz` extension
+    #[clap(long)]
+    gzip: bool,
+
+    /// Delimiter of fields of the input and the output (e.g. `;` or a tab)
+    #[clap(long, value_name = "CHAR", default_value = ",", value_parser = parse_delimiter)]
+    delimiter: u8,
+
+    /// Skip lines of the input starting with the given character (e.g. `#`)
+    #[clap(long, value_name = "CHAR", value_parser = parse_comment_char)]
+    comment_char: Option<u8>,
+
+    /// Warn when held funds of a client exceed the given ratio of their total
+    /// funds after a dispute
+    #[clap(
+        long,
+        value_name = "R",
+        conflicts_with_all = &["auto-parallel", "threads"]
+    )]
+    soft_lock_ratio: Option<Decimal>,
+
+    /// Write the final state of all clients as SQL `INSERT` statements to the
+    /// given file
+    #[clap(long, value_name = "PATH")]
+    sql_dump: Option<String>,
+
+    /// Name of the table in `--sql-dump` statements
+    #[clap(
+        long,
+        value_name = "TABLE",
+        default_value = "clients",
+        requires = "sql-dump",
+        value_parser = parse_table_name
+    )]
+    sql_table: String,
+
+    /// Stop applying deposits, withdrawals and adjustments after the one with
+    /// the given ID, still applying disputes, resolves and chargebacks
+    #[clap(long, value_name = "ID")]
+    as_of_tx: Option<u32>,
+
+    /// Format of the output
+    #[clap(
+        long,
+        value_enum,
+        default_value = "csv",
+        conflicts_with_all = &["subtotals", "with-lock-time", "with-delta", "row-checksum"]
+    )]
+    format: Format,
+
+    /// Write the output to the given file instead of printing it
+    #[clap(long, value_name = "PATH", conflicts_with = "compare-to")]
+    output: Option<String>,
+
+    /// Write a JSON manifest describing the run (inputs, settings, counters
+    /// and hash of the output) to the given file
+    #[clap(long, value_name = "PATH")]
+    manifest: Option<String>,
 }
 
-fn process_transactions<P: AsRef<Path>>(file: P) -> Result<(), Error> {
-    let mut clients_map: BTreeMap<u16, Client> = BTreeMap::new();
+/// Strategy of rounding.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum Rounding {
+    /// Round to the nearest value, ties to the even one (banker's rounding)
+    HalfEven,
+    /// Round to the nearest value, ties away from zero
+    HalfUp,
+    /// Round towards zero (truncate)
+    Down,
+}
 
-    let rdr = ReaderBuilder::new()
-        .delimiter(b',')
-        .trim(Trim::All)
-        .from_path(file)?;
-    for result in rdr.into_deserialize() {
-        let tx: Transaction = result?;
-
-        clients_map
-            .entry(tx.client)
-            .or_insert(Client::new(tx.client));
-
-        let client = clients_map
-            .get_mut(&tx.client)
-            .ok_or(error::Error::ClientNotFound(tx.client))?;
-
-        if let Err(e) = client.make_tx(tx) {
-            match e {
-                // Those errors can be ignored. We can proceed with next
-                // transactions.
-                Error::NoFunds { .. } | Error::TransactionNotFound(_) | Error::TxNotDisputed(_) => {
+impl From<Rounding> for RoundingStrategy {
+    fn from(rounding: Rounding) -> RoundingStrategy {
+        match rounding {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate a synthetic CSV series of valid transactions
+    Generate {
+        /// Number of clients
+        #[clap(long, default_value = "100")]
+        clients: u16,
+
+        /// Number of rows
+        #[clap(long, default_value = "10000")]
+        rows: u32,
+
+        /// Seed of the pseudo-random number generator
+        #[clap(long, default_value = "0")]
+        seed: u64,
+
+        /// Probability that a row is a dispute, resolve or chargeback
+        #[clap(long, value_name = "RATE", default_value = "0.1")]
+        dispute_rate: f64,
+
+        /// Probability that a row is a dispute referring to a non-existent
+        /// transaction
+        #[clap(long, value_name = "RATE", default_value = "0")]
+        with_errors: f64,
+    },
+}
+
+/// Type of transactions which can be disputed.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum DisputableType {
+    Deposit,
+    Withdrawal,
+    Adjustment,
+}
+
+impl From<DisputableType> for TransactionType {
+    fn from(disputable_type: DisputableType) -> TransactionType {
+        match disputable_type {
+            DisputableType::Deposit => TransactionType::Deposit,
+            DisputableType::Withdrawal => TransactionType::Withdrawal,
+            DisputableType::Adjustment => TransactionType::Adjustment,
+        }
+    }
+}
+
+/// Chargebacks which lock the account.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum LockOnChargeback {
+    /// Every chargeback
+    Always,
+    /// None, chargebacks only reverse funds
+    Never,
+    /// Chargebacks of clients which already had one
+    Repeat,
+}
+
+impl From<LockOnChargeback> for ChargebackLock {
+    fn from(lock_on_chargeback: LockOnChargeback) -> ChargebackLock {
+        match lock_on_chargeback {
+            LockOnChargeback::Always => ChargebackLock::Always,
+            LockOnChargeback::Never => ChargebackLock::Never,
+            LockOnChargeback::Repeat => ChargebackLock::Repeat,
+        }
+    }
+}
+
+/// Format of the output.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum Format {
+    Csv,
+    /// Array of objects, with decimals as strings
+    Json,
+}
+
+/// Format of the input.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum InputFormat {
+    Csv,
+    /// JSON Lines, an object per line, with amounts as strings or numbers
+    Jsonl,
+}
+
+/// Decimal separator of amounts in the input.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum DecimalSeparator {
+    /// `1234.56`
+    Dot,
+    /// `1.234,56`
+    Comma,
+}
+
+/// Line endings of the output.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum LineEnding {
+    /// `\n` (Unix)
+    Lf,
+    /// `\r\n` (Windows)
+    Crlf,
+}
+
+impl From<LineEnding> for Terminator {
+    fn from(line_ending: LineEnding) -> Terminator {
+        match line_ending {
+            LineEnding::Lf => Terminator::Any(b'\n'),
+            LineEnding::Crlf => Terminator::CRLF,
+        }
+    }
+}
+
+/// Column to sort the output by.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum SortKey {
+    Id,
+    Total,
+    Available,
+    Held,
+}
+
+impl SortKey {
+    /// Compares clients by the column in the given order, breaking ties by
+    /// ascending client ID, so the order is total and the output
+    /// deterministic. Sorting by ID itself follows the given order.
+    fn compare(&self, order: SortOrder, a: &Client, b: &Client) -> Ordering {
+        let ordering = match self {
+            SortKey::Id => a.id().cmp(&b.id()),
+            SortKey::Total => a.total().cmp(&b.total()),
+            SortKey::Available => a.available().cmp(&b.available()),
+            SortKey::Held => a.held().cmp(&b.held()),
+        };
+        let ordering = match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        ordering.then_with(|| a.id().cmp(&b.id()))
+    }
+}
+
+/// Order of sorting the output.
+#[derive(Clone, Copy, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Parses a delimiter of fields, which has to be a single ASCII character.
+fn parse_delimiter(value: &str) -> Result<u8, Error> {
+    match value.as_bytes() {
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => Err(Error::InvalidDelimiter(value.to_owned())),
+    }
+}
+
+/// Parses a character starting comment lines, which has to be a single ASCII
+/// character.
+fn parse_comment_char(value: &str) -> Result<u8, Error> {
+    match value.as_bytes() {
+        [comment] if comment.is_ascii() => Ok(*comment),
+        _ => Err(Error::InvalidCommentChar(value.to_owned())),
+    }
+}
+
+/// Parses a name of a table, which has to consist of identifiers (letters,
+/// digits and underscores, not starting with a digit) separated by dots, so
+/// it can be written to SQL statements unquoted.
+fn parse_table_name(value: &str) -> Result<String, Error> {
+    let is_identifier = |part: &str| {
+        part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if value.split('.').all(is_identifier) {
+        Ok(value.to_owned())
+    } else {
+        Err(Error::InvalidTableName(value.to_owned()))
+    }
+}
+
+/// Fails if more transactions were skipped than allowed.
+fn check_skips(args: &Args, skipped: u64) -> Result<(), Error> {
+    match args.max_skips {
+        Some(max_skips) if skipped > max_skips => Err(Error::TooManySkips(max_skips)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks whether the transaction is allowed by the given arguments.
+fn check_tx(args: &Args, tx: &Transaction) -> Result<(), Error> {
+    if args.reject_tx_zero && tx.tx == 0 {
+        return Err(Error::ReservedTxId(tx.tx));
+    }
+    Ok(())
+}
+
+/// Checks whether a chargeback is preceded by a dispute row of the same
+/// transaction, if required by arguments. Records disputes in `disputes`.
+fn check_dispute_row(
+    args: &Args,
+    disputes: &mut BTreeSet<(u16, u32)>,
+    tx: &Transaction,
+) -> Result<(), Error> {
+    if !args.require_dispute_before_chargeback {
+        return Ok(());
+    }
+    match tx.tx_type {
+        TransactionType::Dispute => {
+            disputes.insert((tx.client, tx.tx));
+        }
+        TransactionType::Chargeback if !disputes.contains(&(tx.client, tx.tx)) => {
+            return Err(Error::ChargebackWithoutDispute(tx.tx));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Options of making transactions chosen by arguments.
+fn client_options(args: &Args) -> ClientOptions {
+    ClientOptions {
+        idempotent_resolve: args.idempotent_resolve,
+        skip_duplicate_tx_ids: args.skip_duplicate_tx_ids,
+        max_scale: args.max_internal_scale.map(|scale| ScaleLimit {
+            scale,
+            rounding: args.internal_rounding.into(),
+        }),
+        disputable_types: args
+            .disputable_types
+            .iter()
+            .map(|&disputable_type| disputable_type.into())
+            .collect(),
+        disputes_ignore_lock: args.disputes_ignore_lock,
+        partial_disputes: args.partial_disputes,
+        lock_on_chargeback: args.lock_on_chargeback.into(),
+        forbid_zeroing: args.forbid_zeroing,
+        max_balance: args.max_balance,
+        verify_invariants: args.verify_invariants,
+        max_history: args.max_history,
+    }
+}
+
+/// FNV-1a hash of rows read from the input, allowing to verify that a resumed
+/// run reads the same input as the interrupted one.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+struct Fingerprint(u64);
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint(hash::FNV_OFFSET_BASIS)
+    }
+}
+
+impl Fingerprint {
+    /// Adds the row (or the error of reading it) to the hashed ones.
+    fn add(&mut self, row: &Result<(Position, Transaction), Error>) {
+        let data = match row {
+            Ok((position, tx)) => format!("{}:{:?}\n", position.line, tx),
+            Err(e) => format!("{}\n", e),
+        };
+        self.0 = hash::fnv1a_update(self.0, data.as_bytes());
+    }
+}
+
+/// State of processing the input, written to checkpoints together with the
+/// state of accounts, so an interrupted run can be resumed with `--resume`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Progress {
+    /// Hash of the rows read so far, computed only when checkpoints are
+    /// written.
+    fingerprint: Fingerprint,
+    /// Client of the previous transaction, used to check whether the input is
+    /// sorted by client.
+    prev_client: Option<u16>,
+    /// The latest timestamp seen in the input.
+    last_timestamp: Option<u64>,
+    /// Clients and IDs of transactions disputed in the input.
+    disputes: BTreeSet<(u16, u32)>,
+    /// Number of rows skipped due to `--currency`.
+    other_currency_rows: u64,
+    /// Number of rows skipped due to `--lifecycle-only`.
+    non_lifecycle_rows: u64,
+    /// If true, the transaction given with `--as-of-tx` was reached.
+    as_of_reached: bool,
+    /// Number of rows skipped due to `--as-of-tx`.
+    after_as_of_rows: u64,
+    /// Position of the latest transaction in the input.
+    seq: u64,
+    /// Counters describing the processing, including the number of rows read.
+    metrics: Metrics,
+}
+
+/// Transaction rejected due to a locked account.
+#[derive(Debug, Serialize)]
+struct BlockedRow {
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_file"
+    )]
+    file: Option<Rc<str>>,
+    line: u64,
+    tx: u32,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+}
+
+/// Transaction skipped due to an error.
+#[derive(Debug, Serialize)]
+struct SkippedRow {
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_file"
+    )]
+    file: Option<Rc<str>>,
+    line: u64,
+    tx: u32,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    kind: &'static str,
+    error: String,
+}
+
+/// Position of a row in the input.
+#[derive(Clone, Debug)]
+struct Position {
+    /// Path of the file, if transactions are read from more than one.
+    file: Option<Rc<str>>,
+    line: u64,
+}
+
+impl Position {
+    /// Adds the path of the file to the error, if it's known.
+    fn locate(&self, e: Error) -> Error {
+        in_file(self.file.as_ref(), e)
+    }
+}
+
+/// Adds the path of the file to the error, if it's given.
+fn in_file(file: Option<&Rc<str>>, e: Error) -> Error {
+    match file {
+        Some(path) => Error::InFile {
+            path: path.to_string(),
+            source: Box::new(e),
+        },
+        None => e,
+    }
+}
+
+/// Serializes the path of the file of a logged row.
+fn serialize_file<S: Serializer>(file: &Option<Rc<str>>, serializer: S) -> Result<S::Ok, S::Error> {
+    file.as_deref().serialize(serializer)
+}
+
+/// Source of transactions.
+enum Source {
+    Files(Box<Files>),
+    #[cfg(feature = "postgres")]
+    Postgres(Box<PostgresReader>),
+}
+
+impl Source {
+    /// Opens the source of transactions chosen by arguments.
+    fn open(args: &Args) -> Result<Source, Error> {
+        #[cfg(feature = "postgres")]
+        if let Some(ref url) = args.source {
+            let query = args.query.as_deref().unwrap_or(DEFAULT_QUERY);
+            return Ok(Source::Postgres(Box::new(PostgresReader::new(url, query)?)));
+        }
+
+        let paths = match args.file.as_slice() {
+            [] => vec![STDIN_PATH.to_owned()],
+            paths => paths.to_vec(),
+        };
+        let mut files = Files {
+            paths,
+            next: 0,
+            current: None,
+            format: args.input_format,
+            gzip: args.gzip,
+            options: ReaderOptions {
+                delimiter: args.delimiter,
+                comment: args.comment_char,
+                skip_repeated_headers: args.skip_repeated_headers,
+                lenient_amounts: args.lenient_amounts,
+                expected_currency: args.expected_currency.clone(),
+                decimal_comma: matches!(args.decimal_separator, DecimalSeparator::Comma),
+                dedup_consecutive: args.dedup_consecutive,
+            },
+            repeated_headers: 0,
+            deduplicated_rows: 0,
+        };
+        // Fail early if the first file can't be read.
+        files.open_next()?;
+        Ok(Source::Files(Box::new(files)))
+    }
+
+    /// Number of skipped rows repeating the header.
+    fn repeated_headers(&self) -> u64 {
+        match self {
+            Source::Files(files) => {
+                files.repeated_headers
+                    + files
+                        .current
+                        .as_ref()
+                        .map_or(0, |(reader, _)| reader.repeated_headers())
+            }
+            #[cfg(feature = "postgres")]
+            Source::Postgres(_) => 0,
+        }
+    }
+
+    /// Number of skipped rows identical to the preceding ones.
+    fn deduplicated_rows(&self) -> u64 {
+        match self {
+            Source::Files(files) => {
+                files.deduplicated_rows
+                    + files
+                        .current
+                        .as_ref()
+                        .map_or(0, |(reader, _)| reader.deduplicated_rows())
+            }
+            #[cfg(feature = "postgres")]
+            Source::Postgres(_) => 0,
+        }
+    }
+}
+
+impl Iterator for Source {
+    type Item = Result<(Position, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Source::Files(files) => files.next(),
+            #[cfg(feature = "postgres")]
+            Source::Postgres(reader) => reader
+                .next()
+                .map(|row| row.map(|(line, tx)| (Position { file: None, line }, tx))),
+        }
+    }
+}
+
+/// Reader of a single input file.
+enum FileReader {
+    Csv(TransactionReader<Box<dyn io::Read>>),
+    JsonLines(JsonLinesReader<Box<dyn BufRead>>),
+}
+
+impl FileReader {
+    /// Number of skipped rows repeating the header.
+    fn repeated_headers(&self) -> u64 {
+        match self {
+            FileReader::Csv(reader) => reader.repeated_headers(),
+            FileReader::JsonLines(_) => 0,
+        }
+    }
+
+    /// Number of skipped rows identical to the preceding ones.
+    fn deduplicated_rows(&self) -> u64 {
+        match self {
+            FileReader::Csv(reader) => reader.deduplicated_rows(),
+            FileReader::JsonLines(_) => 0,
+        }
+    }
+}
+
+impl Iterator for FileReader {
+    type Item = Result<(u64, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            FileReader::Csv(reader) => reader.next(),
+            FileReader::JsonLines(reader) => reader.next(),
+        }
+    }
+}
+
+/// Files read one after another as a single series. Each file is opened only
+/// once the preceding ones are exhausted.
+struct Files {
+    paths: Vec<String>,
+    /// Index of the next file to open.
+    next: usize,
+    /// Reader of the current file, with its path if there is more than one.
+    current: Option<(FileReader, Option<Rc<str>>)>,
+    format: InputFormat,
+    gzip: bool,
+    options: ReaderOptions,
+    /// Number of skipped rows repeating the header in exhausted files.
+    repeated_headers: u64,
+    /// Number of skipped rows identical to the preceding ones in exhausted
+    /// files.
+    deduplicated_rows: u64,
+}
+
+impl Files {
+    /// Opens the next file, if there is any left.
+    fn open_next(&mut self) -> Result<(), Error> {
+        let Some(path) = self.paths.get(self.next) else {
+            return Ok(());
+        };
+        self.next += 1;
+        let file = (self.paths.len() > 1).then(|| Rc::from(path.as_str()));
+        let reader = self.open(path).map_err(|e| in_file(file.as_ref(), e))?;
+        self.current = Some((reader, file));
+        Ok(())
+    }
+
+    /// Opens the file with the given path.
+    fn open(&self, path: &str) -> Result<FileReader, Error> {
+        let mut input: Box<dyn BufRead> = if path == STDIN_PATH {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(io::BufReader::new(File::open(path)?))
+        };
+        if self.gzip || path.ends_with(GZIP_EXTENSION) {
+            input = Box::new(io::BufReader::new(MultiGzDecoder::new(input)));
+        }
+        Ok(match self.format {
+            InputFormat::Csv => FileReader::Csv(TransactionReader::new(
+                Box::new(input) as _,
+                self.options.clone(),
+            )?),
+            InputFormat::Jsonl => {
+                FileReader::JsonLines(JsonLinesReader::new(input, self.options.clone()))
+            }
+        })
+    }
+}
+
+impl Iterator for Files {
+    type Item = Result<(Position, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some((reader, file)) = self.current.as_mut() else {
+                if self.next >= self.paths.len() {
+                    return None;
+                }
+                if let Err(e) = self.open_next() {
+                    return Some(Err(e));
                 }
-                _ => return Err(e),
+                continue;
+            };
+            match reader.next() {
+                Some(Ok((line, tx))) => {
+                    let file = file.clone();
+                    return Some(Ok((Position { file, line }, tx)));
+                }
+                Some(Err(e)) => return Some(Err(in_file(file.as_ref(), e))),
+                None => {
+                    self.repeated_headers += reader.repeated_headers();
+                    self.deduplicated_rows += reader.deduplicated_rows();
+                    self.current = None;
+                }
+            }
+        }
+    }
+}
+
+/// Processor of transactions, either in a single thread or in multiple ones.
+enum Processor {
+    Single(Box<Ledger>),
+    Sharded(ShardedEngine),
+}
+
+impl Processor {
+    /// Applies the transaction, returning the error it was skipped with.
+    /// Errors of transactions processed by multiple threads are only counted
+    /// and returned by [`Processor::finish`].
+    fn apply(&mut self, tx: Transaction) -> Result<Option<Error>, Error> {
+        match self {
+            Processor::Single(ledger) => {
+                ledger.apply(tx)?;
+                Ok(ledger.take_skipped())
             }
+            Processor::Sharded(engine) => engine.process_transaction(tx).map(|()| None),
+        }
+    }
+
+    /// Gets the given client. Accounts of clients processed by multiple
+    /// threads are not available until [`Processor::finish`].
+    fn client(&self, id: u16) -> Option<&Client> {
+        match self {
+            Processor::Single(ledger) => ledger.engine().client(id),
+            Processor::Sharded(_) => None,
+        }
+    }
+
+    /// Number of skipped transactions which weren't returned by
+    /// [`Processor::apply`] yet.
+    fn pending_skips(&self) -> u64 {
+        match self {
+            Processor::Single(_) => 0,
+            Processor::Sharded(engine) => engine.skipped(),
+        }
+    }
+
+    /// Returns operations which changed accounts since the previous call, if
+    /// they're recorded. They're never recorded in multiple threads.
+    fn take_trace(&mut self) -> Vec<TraceEntry> {
+        match self {
+            Processor::Single(ledger) => ledger.engine_mut().take_trace(),
+            Processor::Sharded(_) => Vec::new(),
+        }
+    }
+
+    /// Resolves expired disputes (see [`Engine::expire_disputes`]), returning
+    /// IDs of clients and transactions. Accounts of clients processed by
+    /// multiple threads are not available, so `None` is returned and their
+    /// disputes have to be expired after [`Processor::finish`].
+    fn expire_disputes(&mut self, now: u64, expiry: u64) -> Result<Option<Vec<(u16, u32)>>, Error> {
+        match self {
+            Processor::Single(ledger) => ledger.engine_mut().expire_disputes(now, expiry).map(Some),
+            Processor::Sharded(_) => Ok(None),
+        }
+    }
+
+    /// Continues processing in a single thread with the given options and
+    /// policy, returning numbers of transactions skipped in multiple threads
+    /// so far.
+    fn into_single(
+        self,
+        options: ClientOptions,
+        policy: SkipPolicy,
+    ) -> Result<(Processor, Skips), Error> {
+        match self {
+            Processor::Single(ledger) => Ok((Processor::Single(ledger), Skips::new())),
+            Processor::Sharded(engine) => {
+                let (clients_map, skips) = engine.finish()?;
+                let mut engine = Engine::with_options(options);
+                engine.restore(clients_map);
+                let ledger = Ledger::with_engine(engine).with_skip_policy(policy);
+                Ok((Processor::Single(Box::new(ledger)), skips))
+            }
+        }
+    }
+
+    /// Finishes processing, returning accounts of all clients and numbers of
+    /// skipped transactions which weren't returned by [`Processor::apply`].
+    fn finish(self) -> Result<(BTreeMap<u16, Client>, Skips), Error> {
+        match self {
+            Processor::Single(ledger) => Ok((ledger.into_engine().into_clients(), Skips::new())),
+            Processor::Sharded(engine) => engine.finish(),
         }
     }
+}
 
-    let mut wtr = WriterBuilder::new().from_writer(io::stdout());
-    for (_, client) in clients_map.iter() {
-        wtr.serialize(client)?;
+/// Policy of skipping failing transactions: none with `--strict`, ones
+/// failing with errors which can be ignored otherwise, and disputes of
+/// transactions which can't be disputed with `--skip-undisputable`. Missing
+/// referred transactions can't be skipped with `--lifecycle-only`.
+fn skip_policy(args: &Args) -> SkipPolicy {
+    if args.strict {
+        return SkipPolicy::Never;
+    }
+    match (args.skip_undisputable, args.lifecycle_only) {
+        (false, false) => SkipPolicy::Ignorable,
+        (true, false) => SkipPolicy::Custom(|e| e.is_ignorable() || is_undisputable(e)),
+        (false, true) => SkipPolicy::Custom(|e| e.is_ignorable() && !is_not_found(e)),
+        (true, true) => {
+            SkipPolicy::Custom(|e| (e.is_ignorable() || is_undisputable(e)) && !is_not_found(e))
+        }
     }
+}
+
+/// Whether the error is caused by a dispute of a transaction which can't be
+/// disputed.
+fn is_undisputable(e: &Error) -> bool {
+    matches!(e.inner(), Error::InvalidTxType(_))
+}
 
+/// Whether the error is caused by a reference to an unknown transaction.
+fn is_not_found(e: &Error) -> bool {
+    matches!(e.inner(), Error::TransactionNotFound(_))
+}
+
+/// Audits accounts of all clients (see [`Client::audit`]), reporting ones
+/// which don't pass.
+///
+/// Fails if there is any such client.
+fn audit_clients(clients_map: &BTreeMap<u16, Client>) -> Result<(), Error> {
+    let mut failed = 0;
+    for client in clients_map.values() {
+        if let Err(e) = client.audit() {
+            eprintln!("audit: {}", e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(Error::AuditFailed(failed));
+    }
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+/// Reads all transactions without applying them, reporting rows which can't be
+/// parsed or aren't structurally valid (see [`Transaction::validate`]).
+///
+/// Fails if there is any invalid row.
+fn validate_transactions(args: &Args) -> Result<(), Error> {
+    let (mut valid, mut invalid) = (0u64, 0u64);
+    for row in Source::open(args)? {
+        match row.and_then(|(position, tx)| {
+            tx.validate(args.partial_disputes).map_err(|e| {
+                position.locate(Error::AtRow {
+                    row: position.line,
+                    source: Box::new(e),
+                })
+            })
+        }) {
+            Ok(()) => valid += 1,
+            Err(e) => {
+                eprintln!("invalid: {}", e);
+                invalid += 1;
+            }
+        }
+    }
+    eprintln!("{} valid rows, {} invalid rows", valid, invalid);
+    if invalid > 0 {
+        return Err(Error::InvalidRows(invalid));
+    }
+    Ok(())
+}
+
+/// Number of threads to process transactions with, when the given sample of
+/// transactions is processed with `--auto-parallel`.
+///
+/// Processing is done in multiple threads only if the sample contains at
+/// least [`AUTO_PARALLEL_MIN_CLIENTS`] distinct clients (so there is enough
+/// independent work to distribute) and there is more than one CPU available.
+/// Transfers can change accounts owned by different threads, so a single one
+/// in the sample keeps processing in a single thread (and one after it
+/// switches processing to a single thread).
+fn auto_parallel_threads(sample: &[Result<(Position, Transaction), Error>]) -> usize {
+    let txs = || sample.iter().filter_map(|row| row.as_ref().ok());
+    if txs().any(|(_, tx)| tx.tx_type == TransactionType::Transfer) {
+        return 1;
+    }
+    let clients: BTreeSet<u16> = txs().map(|(_, tx)| tx.client).collect();
+    if clients.len() < AUTO_PARALLEL_MIN_CLIENTS {
+        return 1;
+    }
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Processes transactions on accounts of the given clients (e.g. restored from
+/// a checkpoint) and new ones, continuing the given progress. Input rows
+/// processed before the checkpoint the run is resumed from are only verified
+/// to be the same as then.
+fn process_transactions(
+    args: &Args,
+    clients_map: BTreeMap<u16, Client>,
+    mut progress: Progress,
+) -> Result<(BTreeMap<u16, Client>, Progress), Error> {
+    let mut reader = Source::open(args)?;
+    let resumed_rows = progress.metrics.rows_read;
+    if resumed_rows > 0 {
+        let mut fingerprint = Fingerprint::default();
+        for row in reader.by_ref().take(resumed_rows as usize) {
+            fingerprint.add(&row);
+        }
+        if fingerprint != progress.fingerprint {
+            return Err(Error::CheckpointInputMismatch { rows: resumed_rows });
+        }
+        eprintln!(
+            "skipped {} rows processed before the checkpoint",
+            resumed_rows
+        );
+    }
 
-    process_transactions(args.file)?;
+    let sample: Vec<_> = if args.auto_parallel {
+        reader.by_ref().take(AUTO_PARALLEL_SAMPLE).collect()
+    } else {
+        Vec::new()
+    };
+    let threads = match args.threads {
+        Some(threads) => threads,
+        None if args.auto_parallel => auto_parallel_threads(&sample),
+        None => 1,
+    };
+    let policy = skip_policy(args);
+    let mut processor = if threads > 1 {
+        eprintln!("processing transactions in {} threads", threads);
+        Processor::Sharded(ShardedEngine::with_skip_policy(
+            threads,
+            client_options(args),
+            clients_map,
+            policy,
+        ))
+    } else {
+        let mut engine = Engine::with_options(client_options(args));
+        if args.trace.is_some() {
+            engine = engine.with_trace();
+        }
+        engine.restore(clients_map);
+        Processor::Single(Box::new(
+            Ledger::with_engine(engine).with_skip_policy(policy),
+        ))
+    };
+
+    let mut blocked_wtr = match args.report_blocked {
+        Some(ref path) => Some(WriterBuilder::new().from_path(path)?),
+        None => None,
+    };
+    let mut skipped_wtr = match args.log_errors {
+        Some(ref path) => Some(WriterBuilder::new().from_path(path)?),
+        None => None,
+    };
+    let mut trace_wtr = match args.trace {
+        Some(ref path) => Some(WriterBuilder::new().from_path(path)?),
+        None => None,
+    };
+
+    for row in sample.into_iter().chain(reader.by_ref()) {
+        if let (Some(interval), Some(path), Processor::Single(ledger)) =
+            (args.checkpoint_interval, &args.checkpoint, &processor)
+        {
+            let rows = progress.metrics.rows_read;
+            if rows > resumed_rows && rows.is_multiple_of(interval) {
+                checkpoint::write_checkpoint_with(
+                    path,
+                    ledger.engine().clients(),
+                    Some(&progress),
+                )?;
+            }
+        }
+        if args.checkpoint.is_some() {
+            progress.fingerprint.add(&row);
+        }
+        progress.metrics.rows_read += 1;
+        let (position, tx) = match row {
+            Ok(row) => row,
+            Err(e)
+                if args.skip_ragged_rows
+                    && matches!(e.inner(), Error::FieldCountMismatch { .. }) =>
+            {
+                eprintln!("skipped: {}", e);
+                progress.metrics.skip(&e);
+                check_skips(args, progress.metrics.skipped_total())?;
+                continue;
+            }
+            Err(e)
+                if args.skip_invalid_integers
+                    && matches!(e.inner(), Error::InvalidInteger { .. }) =>
+            {
+                eprintln!("skipped: {}", e);
+                progress.metrics.skip(&e);
+                check_skips(args, progress.metrics.skipped_total())?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        progress.seq += 1;
+        let tx = tx.with_seq(progress.seq);
+        progress.metrics.read(&tx.tx_type);
+
+        if args.assert_sorted_by_client {
+            // The input is sorted so far, so the previous client is the
+            // greatest of the completed ones.
+            if let Some(prev) = progress.prev_client {
+                if tx.client < prev {
+                    return Err(position.locate(Error::NotSortedByClient {
+                        line: position.line,
+                    }));
+                }
+            }
+            progress.prev_client = Some(tx.client);
+        }
+
+        if let Some(timestamp) = tx.timestamp {
+            progress.last_timestamp = progress.last_timestamp.max(Some(timestamp));
+        }
+
+        if let (Some(expected), Some(currency)) = (&args.currency, &tx.currency) {
+            if currency != expected {
+                progress.other_currency_rows += 1;
+                progress.metrics.filter();
+                continue;
+            }
+        }
+        if args.lifecycle_only && !tx.tx_type.refers_tx() {
+            progress.non_lifecycle_rows += 1;
+            progress.metrics.filter();
+            continue;
+        }
+        if !tx.tx_type.refers_tx() {
+            if progress.as_of_reached {
+                progress.after_as_of_rows += 1;
+                progress.metrics.filter();
+                continue;
+            }
+            progress.as_of_reached = args.as_of_tx == Some(tx.tx);
+        }
+
+        // Transfers can change accounts owned by different threads, so the
+        // remaining transactions are processed in a single thread.
+        if let (TransactionType::Transfer, Processor::Sharded(_)) = (&tx.tx_type, &processor) {
+            eprintln!(
+                "processing transactions in a single thread from transfer tx {}",
+                tx.tx
+            );
+            let (single, skips) = processor.into_single(client_options(args), policy)?;
+            processor = single;
+            progress.metrics.add_skips(skips);
+        }
+
+        let blocked = BlockedRow {
+            file: position.file.clone(),
+            line: position.line,
+            tx: tx.tx,
+            client: tx.client,
+            tx_type: tx.tx_type.clone(),
+        };
+        // Some errors can be ignored. We can proceed with next transactions.
+        let skipped = match check_tx(args, &tx)
+            .and_then(|()| check_dispute_row(args, &mut progress.disputes, &tx))
+        {
+            Ok(()) => processor.apply(tx),
+            Err(e) if policy.skips(&e) => Ok(Some(e)),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| {
+            let e = match e {
+                // Referred transactions are expected to be restored, so
+                // a missing one means a wrong checkpoint or input.
+                Error::TransactionNotFound(tx_id) if args.lifecycle_only => {
+                    Error::TxNotRestored(tx_id)
+                }
+                e => e,
+            };
+            if args.strict {
+                position.locate(Error::AtRow {
+                    row: position.line,
+                    source: Box::new(e),
+                })
+            } else {
+                e
+            }
+        })?;
+        if let Some(e) = skipped {
+            if let Some(wtr) = skipped_wtr.as_mut() {
+                wtr.serialize(SkippedRow {
+                    file: blocked.file.clone(),
+                    line: blocked.line,
+                    tx: blocked.tx,
+                    client: blocked.client,
+                    tx_type: blocked.tx_type.clone(),
+                    kind: e.kind(),
+                    error: e.to_string(),
+                })?;
+            }
+            if let (Error::ClientLocked(_), Some(wtr)) = (&e, blocked_wtr.as_mut()) {
+                wtr.serialize(blocked)?;
+            }
+            progress.metrics.skip(&e);
+            check_skips(args, progress.metrics.skipped_total())?;
+        } else if let (Some(ratio), TransactionType::Dispute) =
+            (args.soft_lock_ratio, &blocked.tx_type)
+        {
+            if let Some(client) = processor
+                .client(blocked.client)
+                .filter(|client| client.held_exceeds_ratio(ratio))
+            {
+                eprintln!(
+                    "warning: held funds ({}) of client {} exceed {} of total funds ({}) after dispute of tx {}",
+                    client.held(),
+                    blocked.client,
+                    ratio,
+                    client.total(),
+                    blocked.tx,
+                );
+            }
+        }
+
+        // Transactions skipped in multiple threads are only counted there.
+        let pending_skips = processor.pending_skips();
+        if pending_skips > 0 {
+            check_skips(args, progress.metrics.skipped_total() + pending_skips)?;
+        }
+
+        if let Some(wtr) = trace_wtr.as_mut() {
+            for entry in processor.take_trace() {
+                wtr.serialize(entry)?;
+            }
+        }
+    }
+
+    // Expire disputes before the trace is written, so the resolves are
+    // recorded in it.
+    let expiry = args.dispute_expiry.zip(progress.last_timestamp);
+    let expired = match expiry {
+        Some((expiry, now)) => processor.expire_disputes(now, expiry)?,
+        None => Some(Vec::new()),
+    };
+    if let Some(wtr) = trace_wtr.as_mut() {
+        for entry in processor.take_trace() {
+            wtr.serialize(entry)?;
+        }
+    }
+
+    if let Some(mut wtr) = blocked_wtr {
+        wtr.flush()?;
+    }
+    if let Some(mut wtr) = skipped_wtr {
+        wtr.flush()?;
+    }
+    if let Some(mut wtr) = trace_wtr {
+        wtr.flush()?;
+    }
+
+    progress.metrics.repeated_headers = reader.repeated_headers();
+    if progress.metrics.repeated_headers > 0 {
+        eprintln!(
+            "skipped {} repeated header rows",
+            progress.metrics.repeated_headers
+        );
+    }
+    progress.metrics.deduplicated_rows = reader.deduplicated_rows();
+    if progress.metrics.deduplicated_rows > 0 {
+        eprintln!(
+            "skipped {} rows identical to the preceding ones",
+            progress.metrics.deduplicated_rows
+        );
+    }
+
+    if let (Some(currency), true) = (&args.currency, progress.other_currency_rows > 0) {
+        eprintln!(
+            "skipped {} rows in currencies other than {}",
+            progress.other_currency_rows, currency
+        );
+    }
+
+    if progress.non_lifecycle_rows > 0 {
+        eprintln!(
+            "skipped {} rows other than dispute lifecycle ones",
+            progress.non_lifecycle_rows
+        );
+    }
+
+    if let Some(as_of_tx) = args.as_of_tx {
+        // Otherwise all transactions were applied, which isn't the requested
+        // state.
+        if !progress.as_of_reached {
+            return Err(Error::AsOfTxNotFound(as_of_tx));
+        }
+        if progress.after_as_of_rows > 0 {
+            eprintln!(
+                "skipped {} transactions after tx {}",
+                progress.after_as_of_rows, as_of_tx
+            );
+        }
+    }
+
+    let (mut clients_map, skips) = processor.finish()?;
+    progress.metrics.add_skips(skips);
+    check_skips(args, progress.metrics.skipped_total())?;
+
+    let expired = match (expired, expiry) {
+        (Some(expired), _) => expired,
+        (None, Some((expiry, now))) => {
+            let mut expired = Vec::new();
+            for (id, client) in clients_map.iter_mut() {
+                for tx_id in client.expire_disputes(now, expiry)? {
+                    expired.push((*id, tx_id));
+                }
+            }
+            expired
+        }
+        (None, None) => Vec::new(),
+    };
+    for (id, tx_id) in expired {
+        eprintln!(
+            "auto-resolved expired dispute of tx {} (client {})",
+            tx_id, id
+        );
+    }
+
+    progress.metrics.count_clients(&clients_map);
+
+    Ok((clients_map, progress))
+}
+
+/// Reads balances of clients from a CSV file with the columns of the report.
+/// The clients have empty histories of transactions.
+fn read_report(path: &str, delimiter: u8) -> Result<BTreeMap<u16, Client>, Error> {
+    let mut clients_map = BTreeMap::new();
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(Trim::All)
+        .from_path(path)?;
+    let headers = rdr.headers()?.clone();
+    for result in rdr.records() {
+        let client = Client::from_report_row(&result?, &headers)?;
+        clients_map.insert(client.id(), client);
+    }
+    Ok(clients_map)
+}
+
+/// Removes clients whose balances didn't change since the baseline.
+fn retain_changed(
+    clients_map: &mut BTreeMap<u16, Client>,
+    baseline_path: &str,
+    delimiter: u8,
+) -> Result<(), Error> {
+    let baseline = read_report(baseline_path, delimiter)?;
+
+    clients_map.retain(|id, client| match baseline.get(id) {
+        Some(previous) => !client.same_balance(previous),
+        None => true,
+    });
+
+    Ok(())
+}
+
+/// Removes clients without funds and without any applied transactions.
+/// Locked accounts are always kept.
+fn retain_nonempty(clients_map: &mut BTreeMap<u16, Client>) {
+    clients_map.retain(|_, client| {
+        client.locked()
+            || !(client.total().is_zero() && client.held().is_zero() && client.tx_count() == 0)
+    });
+}
+
+/// Writes the report of clients, with additional columns chosen by
+/// arguments.
+fn write_clients<W: Write>(
+    args: &Args,
+    clients_map: &BTreeMap<u16, Client>,
+    seeded: &BTreeMap<u16, Client>,
+    writer: W,
+) -> Result<(), Error> {
+    let mut clients: Vec<&Client> = clients_map.values().collect();
+    clients.sort_by(|a, b| args.sort_by.compare(args.order, a, b));
+
+    let mut headers = vec!["client", "available", "held", "total", "locked"];
+    if args.with_lock_time {
+        headers.push("locked_at");
+    }
+    if args.with_counts {
+        headers.push("tx_count");
+    }
+    if args.with_delta {
+        headers.extend(["delta_available", "delta_held", "delta_total"]);
+    }
+    if args.row_checksum {
+        headers.push("checksum");
+    }
+
+    let mut wtr = WriterBuilder::new()
+        .delimiter(args.delimiter)
+        .terminator(args.line_ending.into())
+        .from_writer(writer);
+    // Like serialized records, write the header only together with rows.
+    if !clients.is_empty() {
+        wtr.write_record(&headers)?;
+    }
+    for client in clients {
+        let (available, held, total) =
+            round_balances(client.available(), client.held(), args.scale);
+        let mut record = vec![
+            client.id().to_string(),
+            available.to_string(),
+            held.to_string(),
+            total.to_string(),
+            client.locked().to_string(),
+        ];
+        if args.with_lock_time {
+            record.push(
+                client
+                    .locked_at()
+                    .map_or_else(String::new, |t| t.to_string()),
+            );
+        }
+        if args.with_counts {
+            record.push(client.tx_count().to_string());
+        }
+        if args.with_delta {
+            let seeded = seeded.get(&client.id());
+            let delta = |balance: Decimal, seeded_balance: Option<Decimal>| {
+                balance
+                    .checked_sub(seeded_balance.unwrap_or_default())
+                    .ok_or(Error::BalanceOverflow {
+                        client: client.id(),
+                    })
+            };
+            let (available, held, total) = round_balances(
+                delta(client.available(), seeded.map(Client::available))?,
+                delta(client.held(), seeded.map(Client::held))?,
+                args.scale,
+            );
+            record.extend([available, held, total].map(|delta| delta.to_string()));
+        }
+        if args.row_checksum {
+            record.push(client.checksum(args.scale));
+        }
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
 
     Ok(())
 }
+
+/// Mapping of an original client ID to the normalized one.
+#[derive(Debug, Serialize)]
+struct IdMapRow {
+    original: u16,
+    client: u16,
+}
+
+/// Renumbers clients to 1..=N in the order of their IDs, optionally writing
+/// the mapping to the given CSV file.
+fn normalize_client_ids(
+    clients_map: BTreeMap<u16, Client>,
+    id_map_path: Option<&str>,
+) -> Result<BTreeMap<u16, Client>, Error> {
+    let mut wtr = match id_map_path {
+        Some(path) => Some(WriterBuilder::new().from_path(path)?),
+        None => None,
+    };
+
+    // IDs start from 1, so if all the possible IDs are used, one client
+    // can't be renumbered.
+    if clients_map.len() > u16::MAX as usize {
+        return Err(Error::TooManyClients(clients_map.len()));
+    }
+
+    let mut normalized = BTreeMap::new();
+    for (client, (original, c)) in (1..=u16::MAX).zip(clients_map) {
+        if let Some(wtr) = wtr.as_mut() {
+            wtr.serialize(IdMapRow { original, client })?;
+        }
+        normalized.insert(client, c.with_id(client));
+    }
+    if let Some(mut wtr) = wtr {
+        wtr.flush()?;
+    }
+
+    Ok(normalized)
+}
+
+/// Funds of a client held pending dispute resolution.
+#[derive(Debug, Serialize)]
+struct LiabilityRow {
+    client: u16,
+    held: Decimal,
+}
+
+/// Writes held funds of all clients which have any to the given CSV file,
+/// rounded to the given scale.
+fn write_liabilities(
+    clients_map: &BTreeMap<u16, Client>,
+    scale: u32,
+    path: &str,
+) -> Result<(), Error> {
+    let mut wtr = WriterBuilder::new().from_path(path)?;
+    for client in clients_map.values() {
+        if !client.held().is_zero() {
+            wtr.serialize(LiabilityRow {
+                client: client.id(),
+                held: round_output(client.held(), scale),
+            })?;
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Writes the state of all clients as SQL `INSERT` statements into the given
+/// table to the given file. Decimals are rounded to the given scale and
+/// written as exact numeric literals.
+fn write_sql_dump(
+    clients_map: &BTreeMap<u16, Client>,
+    scale: u32,
+    table: &str,
+    path: &str,
+) -> Result<(), Error> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    for client in clients_map.values() {
+        let (available, held, total) = round_balances(client.available(), client.held(), scale);
+        writeln!(
+            file,
+            "INSERT INTO {} (client, available, held, total, locked) VALUES ({}, {}, {}, {}, {});",
+            table,
+            client.id(),
+            available,
+            held,
+            total,
+            if client.locked() { "TRUE" } else { "FALSE" },
+        )?;
+    }
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Writes the output in the format chosen by arguments.
+fn write_output<W: Write>(
+    args: &Args,
+    clients_map: &BTreeMap<u16, Client>,
+    seeded: &BTreeMap<u16, Client>,
+    writer: W,
+) -> Result<(), Error> {
+    match (args.format, &args.segments) {
+        (Format::Json, _) => write_clients_json(args, clients_map, writer),
+        (Format::Csv, Some(segments_path)) => {
+            let segments = segments::read_segments(segments_path)?;
+            segments::write_subtotals(
+                clients_map,
+                &segments,
+                args.scale,
+                args.delimiter,
+                args.line_ending.into(),
+                writer,
+            )
+        }
+        (Format::Csv, None) => write_clients(args, clients_map, seeded, writer),
+    }
+}
+
+/// Account balance of a client in the JSON output.
+#[derive(Debug, Serialize)]
+struct JsonRow {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Writes clients as a JSON array of objects with the same fields as CSV
+/// columns. Decimals are serialized as strings, so they're not rounded by
+/// conversion to floats.
+fn write_clients_json<W: Write>(
+    args: &Args,
+    clients_map: &BTreeMap<u16, Client>,
+    mut writer: W,
+) -> Result<(), Error> {
+    let mut clients: Vec<&Client> = clients_map.values().collect();
+    clients.sort_by(|a, b| args.sort_by.compare(args.order, a, b));
+    let rows: Vec<JsonRow> = clients
+        .into_iter()
+        .map(|client| {
+            let (available, held, total) =
+                round_balances(client.available(), client.held(), args.scale);
+            JsonRow {
+                client: client.id(),
+                available,
+                held,
+                total,
+                locked: client.locked(),
+            }
+        })
+        .collect();
+
+    serde_json::to_writer(&mut writer, &rows)?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Compares the output with the expected one stored in the given file.
+fn compare_clients(
+    args: &Args,
+    clients_map: &BTreeMap<u16, Client>,
+    seeded: &BTreeMap<u16, Client>,
+    expected_path: &str,
+) -> Result<(), Error> {
+    let mut output = Vec::new();
+    write_output(args, clients_map, seeded, &mut output)?;
+    let actual = String::from_utf8_lossy(&output);
+    let expected = fs::read_to_string(expected_path)?;
+
+    match diff::unified_diff(&expected, &actual, expected_path, "output") {
+        Some(diff) => Err(Error::OutputMismatch {
+            expected: expected_path.to_owned(),
+            diff,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+
+    if let Some(Command::Generate {
+        clients,
+        rows,
+        seed,
+        dispute_rate,
+        with_errors,
+    }) = args.command
+    {
+        let options = generate::GeneratorOptions {
+            clients,
+            rows,
+            seed,
+            dispute_rate,
+            error_rate: with_errors,
+        };
+        generate::generate(&options, io::stdout().lock())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.validate_only {
+        validate_transactions(&args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let (restored, progress) = match (&args.restore, &args.initial_state, &args.checkpoint) {
+        (_, _, Some(path)) if args.resume => {
+            let (restored, progress) = checkpoint::read_checkpoint_with(path)?;
+            (restored, progress.ok_or(Error::NoCheckpointProgress)?)
+        }
+        (Some(path), _, _) => {
+            // Positions of transactions continue the ones of the restored run.
+            let (restored, progress) = checkpoint::read_checkpoint_with::<_, Progress>(path)?;
+            let progress = Progress {
+                seq: progress.map_or(0, |progress| progress.seq),
+                ..Default::default()
+            };
+            (restored, progress)
+        }
+        (None, Some(path), _) => (read_report(path, args.delimiter)?, Progress::default()),
+        _ => (BTreeMap::new(), Progress::default()),
+    };
+    // Balances the deltas are computed against.
+    let seeded = if args.with_delta {
+        restored.clone()
+    } else {
+        BTreeMap::new()
+    };
+    let (mut clients_map, progress) = process_transactions(&args, restored, progress)?;
+    let metrics = &progress.metrics;
+    if !args.quiet {
+        eprintln!("{}", metrics);
+    }
+    if args.audit {
+        audit_clients(&clients_map)?;
+    }
+    let exit_code = if metrics.skipped_total() > 0 {
+        ExitCode::from(EXIT_SKIPPED)
+    } else {
+        ExitCode::SUCCESS
+    };
+
+    if let Some(ref metrics_path) = args.metrics_json {
+        metrics.write_json(metrics_path)?;
+    }
+
+    if let Some(ref checkpoint_path) = args.checkpoint {
+        checkpoint::write_checkpoint_with(checkpoint_path, clients_map.values(), Some(&progress))?;
+    }
+
+    if let Some(ref liabilities_path) = args.liabilities {
+        write_liabilities(&clients_map, args.scale, liabilities_path)?;
+    }
+
+    if let Some(ref sql_dump_path) = args.sql_dump {
+        write_sql_dump(&clients_map, args.scale, &args.sql_table, sql_dump_path)?;
+    }
+
+    if let Some(ref baseline_path) = args.baseline {
+        retain_changed(&mut clients_map, baseline_path, args.delimiter)?;
+    }
+
+    if args.hide_empty {
+        retain_nonempty(&mut clients_map);
+    }
+
+    if args.normalize_client_ids {
+        clients_map = normalize_client_ids(clients_map, args.id_map.as_deref())?;
+    }
+
+    if let Some(ref manifest_path) = args.manifest {
+        let mut output = Vec::new();
+        write_output(&args, &clients_map, &seeded, &mut output)?;
+        let inputs: Vec<&str> = [
+            &args.restore,
+            &args.initial_state,
+            &args.baseline,
+            &args.segments,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(&args.file)
+        .map(|path| path.as_str())
+        .filter(|path| *path != STDIN_PATH)
+        .collect();
+        manifest::write_manifest(manifest_path, &inputs, &args, metrics, hash::fnv1a(&output))?;
+    }
+
+    #[cfg(unix)]
+    if let Some(ref socket_path) = args.emit_socket {
+        let stream =
+            UnixStream::connect(socket_path).map_err(|source| Error::SocketUnavailable {
+                path: socket_path.clone(),
+                source,
+            })?;
+        write_output(&args, &clients_map, &seeded, stream)?;
+        return Ok(exit_code);
+    }
+
+    match (&args.compare_to, &args.output) {
+        (Some(expected_path), _) => compare_clients(&args, &clients_map, &seeded, expected_path)?,
+        (None, Some(output_path)) => {
+            let file = File::create(output_path).map_err(|source| Error::OutputUnavailable {
+                path: output_path.clone(),
+                source,
+            })?;
+            write_output(&args, &clients_map, &seeded, io::BufWriter::new(file))?
+        }
+        (None, None) => write_output(&args, &clients_map, &seeded, io::stdout())?,
+    }
+
+    Ok(exit_code)
+}
@@ -0,0 +1,130 @@
+use std::{borrow::Cow, collections::BTreeMap, fmt, fs::File, io::BufWriter, path::Path};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{round_output, Client, OUTPUT_SCALE},
+    error::Error,
+    transaction::TransactionType,
+};
+
+/// Numbers of skipped transactions by kinds of errors which caused skipping.
+///
+/// Kinds are borrowed from [`Error::kind`], unless the numbers were
+/// deserialized (e.g. from a checkpoint).
+pub type Skips = BTreeMap<Cow<'static, str>, u64>;
+
+/// Counters describing the processing.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Metrics {
+    /// Number of transactions read from the input.
+    pub rows_read: u64,
+    /// Number of transactions read from the input by types.
+    pub transactions: BTreeMap<TransactionType, u64>,
+    /// Number of applied transactions.
+    pub applied: u64,
+    /// Number of skipped transactions by kinds of errors.
+    pub skipped: Skips,
+    /// Number of transactions filtered out by options (e.g. in other
+    /// currencies), which are neither applied nor skipped.
+    #[serde(default)]
+    pub filtered: u64,
+    /// Number of skipped rows repeating the header.
+    pub repeated_headers: u64,
+    /// Number of skipped rows identical to the preceding ones.
+    pub deduplicated_rows: u64,
+    /// Number of clients.
+    pub clients: u64,
+    /// Number of open (not resolved or charged back) disputes.
+    pub open_disputes: u64,
+    /// Number of locked accounts.
+    pub locked: u64,
+    /// Sum of held funds of all clients, unless it overflows.
+    pub held: Option<Decimal>,
+}
+
+impl Metrics {
+    /// Counts a transaction of the given type read from the input.
+    pub fn read(&mut self, tx_type: &TransactionType) {
+        *self.transactions.entry(tx_type.clone()).or_default() += 1;
+    }
+
+    /// Counts a transaction skipped due to the given error.
+    pub fn skip(&mut self, e: &Error) {
+        *self.skipped.entry(Cow::Borrowed(e.kind())).or_default() += 1;
+    }
+
+    /// Counts a transaction filtered out by options.
+    pub fn filter(&mut self) {
+        self.filtered += 1;
+    }
+
+    /// Adds the given numbers of skipped transactions.
+    pub fn add_skips(&mut self, skips: Skips) {
+        for (kind, count) in skips {
+            *self.skipped.entry(kind).or_default() += count;
+        }
+    }
+
+    /// Total number of skipped transactions.
+    pub fn skipped_total(&self) -> u64 {
+        self.skipped.values().sum()
+    }
+
+    /// Fills in the counters describing the final state of accounts.
+    pub fn count_clients(&mut self, clients_map: &BTreeMap<u16, Client>) {
+        self.applied = self.rows_read - self.skipped_total() - self.filtered;
+        self.clients = clients_map.len() as u64;
+        self.open_disputes = clients_map.values().map(|c| c.open_disputes() as u64).sum();
+        self.locked = clients_map.values().filter(|c| c.locked()).count() as u64;
+        self.held = clients_map
+            .values()
+            .try_fold(Decimal::ZERO, |sum, c| sum.checked_add(c.held()));
+    }
+
+    /// Writes the metrics as JSON to the given file.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// Writes the counts in a `(key: count, ...)` list, if there are any.
+fn write_counts<K: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    counts: &BTreeMap<K, u64>,
+) -> fmt::Result {
+    if counts.is_empty() {
+        return Ok(());
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(key, count)| format!("{}: {}", key, count))
+        .collect();
+    write!(f, " ({})", counts.join(", "))
+}
+
+/// One-line summary of the processing.
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read {} transactions", self.rows_read)?;
+        write_counts(f, &self.transactions)?;
+        write!(
+            f,
+            ", applied {}, skipped {}",
+            self.applied,
+            self.skipped_total()
+        )?;
+        write_counts(f, &self.skipped)?;
+        if self.filtered > 0 {
+            write!(f, ", filtered {}", self.filtered)?;
+        }
+        write!(f, ", {} clients, {} locked, ", self.clients, self.locked)?;
+        match self.held {
+            Some(held) => write!(f, "{} held", round_output(held, OUTPUT_SCALE)),
+            None => write!(f, "held funds overflowing"),
+        }
+    }
+}
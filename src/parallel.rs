@@ -0,0 +1,270 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    client::{Client, ClientOptions},
+    engine::Engine,
+    error::Error,
+    ledger::{Ledger, SkipPolicy},
+    metrics::Skips,
+    transaction::{Transaction, TransactionType},
+};
+
+/// Number of transactions which can wait for each worker thread.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Accounts processed by a worker thread.
+struct Shard {
+    clients: BTreeMap<u16, Client>,
+    /// Numbers of skipped transactions.
+    skips: Skips,
+}
+
+/// Engine processing transactions in multiple worker threads.
+///
+/// Each worker owns a disjoint set of clients (shard), determined by the
+/// client ID modulo the number of workers, and applies transactions of its
+/// clients in the order they were sent. Since transactions of different
+/// clients are independent, the result is the same as of processing all
+/// transactions in a single thread.
+pub struct ShardedEngine {
+    senders: Vec<SyncSender<Transaction>>,
+    workers: Vec<Option<JoinHandle<Result<Shard, Error>>>>,
+    /// Number of transactions skipped by all workers so far.
+    skipped: Arc<AtomicU64>,
+}
+
+impl ShardedEngine {
+    /// Create a new engine with the given number of worker threads, making
+    /// transactions with the given options on accounts of the given clients
+    /// (e.g. restored from a checkpoint) and new ones.
+    ///
+    /// Transactions failing with errors which can be ignored are skipped.
+    pub fn new(
+        threads: usize,
+        options: ClientOptions,
+        clients: BTreeMap<u16, Client>,
+    ) -> ShardedEngine {
+        ShardedEngine::with_skip_policy(threads, options, clients, SkipPolicy::default())
+    }
+
+    /// Create a new engine like [`ShardedEngine::new`], skipping transactions
+    /// according to the given policy.
+    pub fn with_skip_policy(
+        threads: usize,
+        options: ClientOptions,
+        clients: BTreeMap<u16, Client>,
+        policy: SkipPolicy,
+    ) -> ShardedEngine {
+        let threads = threads.max(1);
+        let mut shards: Vec<BTreeMap<u16, Client>> = vec![BTreeMap::new(); threads];
+        for (id, client) in clients {
+            shards[id as usize % threads].insert(id, client);
+        }
+
+        let mut senders = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+        let skipped = Arc::new(AtomicU64::new(0));
+        for clients in shards {
+            let (sender, receiver) = mpsc::sync_channel::<Transaction>(CHANNEL_CAPACITY);
+            senders.push(sender);
+            let options = options.clone();
+            let skipped = skipped.clone();
+            workers.push(Some(thread::spawn(move || {
+                let mut engine = Engine::with_options(options);
+                engine.restore(clients);
+                let mut ledger = Ledger::with_engine(engine).with_skip_policy(policy);
+                for tx in receiver {
+                    ledger.apply(tx)?;
+                    if ledger.take_skipped().is_some() {
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Ok(Shard {
+                    skips: ledger.skips().clone(),
+                    clients: ledger.into_engine().into_clients(),
+                })
+            })));
+        }
+
+        ShardedEngine {
+            senders,
+            workers,
+            skipped,
+        }
+    }
+
+    /// Sends the transaction to the worker owning its client.
+    ///
+    /// All transactions of a client are processed by the same worker, in the
+    /// order of sending them.
+    ///
+    /// Errors of transactions which are skipped are only counted and
+    /// returned by [`ShardedEngine::finish`]. Other errors stop the worker and
+    /// are returned by the first call after that.
+    ///
+    /// Transfers are not supported, since they can change accounts owned by
+    /// different workers.
+    pub fn process_transaction(&mut self, tx: Transaction) -> Result<(), Error> {
+        if tx.tx_type == TransactionType::Transfer {
+            return Err(Error::UnsupportedTransfer(tx.tx));
+        }
+        let shard = tx.client as usize % self.senders.len();
+        if self.senders[shard].send(tx).is_err() {
+            // The worker stopped due to an error.
+            return Err(match self.workers[shard].take() {
+                Some(worker) => join(worker).err().unwrap_or(Error::WorkerFailed),
+                None => Error::WorkerFailed,
+            });
+        }
+        Ok(())
+    }
+
+    /// Number of transactions skipped by all workers so far. Workers apply
+    /// transactions asynchronously, so it doesn't include the ones which are
+    /// still waiting for them.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Waits for all workers to finish, returning accounts of all clients and
+    /// numbers of skipped transactions.
+    pub fn finish(self) -> Result<(BTreeMap<u16, Client>, Skips), Error> {
+        // Close the channels, so workers know there are no more transactions.
+        drop(self.senders);
+
+        let mut clients_map = BTreeMap::new();
+        let mut skips = Skips::new();
+        for worker in self.workers {
+            let shard = join(worker.ok_or(Error::WorkerFailed)?)?;
+            clients_map.extend(shard.clients);
+            for (kind, count) in shard.skips {
+                *skips.entry(kind).or_default() += count;
+            }
+        }
+
+        Ok((clients_map, skips))
+    }
+}
+
+/// Waits for the worker to finish, returning its result.
+fn join(worker: JoinHandle<Result<Shard, Error>>) -> Result<Shard, Error> {
+    worker.join().map_err(|_| Error::WorkerFailed)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+
+    fn transactions() -> Vec<Transaction> {
+        let mut txs = Vec::new();
+        for i in 0..1000u32 {
+            let client = (i % 37) as u16;
+            txs.push(Transaction::new(
+                TransactionType::Deposit,
+                client,
+                i * 3,
+                Some(Decimal::new(i as i64 + 10, 1)),
+            ));
+            txs.push(Transaction::new(
+                TransactionType::Withdrawal,
+                client,
+                i * 3 + 1,
+                Some(Decimal::new(25, 1)),
+            ));
+            if i % 5 == 0 {
+                txs.push(Transaction::new(
+                    TransactionType::Dispute,
+                    client,
+                    i * 3,
+                    None,
+                ));
+            }
+            if i % 10 == 0 {
+                txs.push(Transaction::new(
+                    TransactionType::Resolve,
+                    client,
+                    i * 3,
+                    None,
+                ));
+            }
+        }
+        txs
+    }
+
+    #[test]
+    fn test_sharded_engine() {
+        let mut ledger = Ledger::new();
+        ledger
+            .apply_all(transactions())
+            .expect("Failed to apply transactions");
+        let skips = ledger.skips().clone();
+        let expected = ledger.into_engine().into_clients();
+
+        for threads in [1, 2, 4, 7] {
+            let mut sharded =
+                ShardedEngine::new(threads, ClientOptions::default(), BTreeMap::new());
+            for tx in transactions() {
+                sharded
+                    .process_transaction(tx)
+                    .expect("Failed to apply a transaction");
+            }
+            let skipped = sharded.skipped.clone();
+            let (clients_map, sharded_skips) =
+                sharded.finish().expect("Failed to finish processing");
+
+            assert_eq!(clients_map, expected);
+            assert_eq!(sharded_skips, skips);
+            assert_eq!(skipped.load(Ordering::Relaxed), skips.values().sum::<u64>());
+        }
+    }
+
+    #[test]
+    fn test_sharded_engine_order() {
+        // Every withdrawal takes all funds of the preceding deposit, so it
+        // fails if any of them is applied out of order (which also fails
+        // the assertion of positions in debug builds).
+        let mut txs = Vec::new();
+        for i in 0..5000u32 {
+            let client = (i % 53) as u16;
+            txs.push(Transaction::new(
+                TransactionType::Deposit,
+                client,
+                i * 2,
+                Some(Decimal::new(i as i64 + 1, 2)),
+            ));
+            txs.push(Transaction::new(
+                TransactionType::Withdrawal,
+                client,
+                i * 2 + 1,
+                Some(Decimal::new(i as i64 + 1, 2)),
+            ));
+        }
+
+        for threads in [2, 4, 7] {
+            let mut sharded =
+                ShardedEngine::new(threads, ClientOptions::default(), BTreeMap::new());
+            for (seq, tx) in txs.iter().cloned().enumerate() {
+                sharded
+                    .process_transaction(tx.with_seq(seq as u64 + 1))
+                    .expect("Failed to apply a transaction");
+            }
+            let (clients_map, skips) = sharded.finish().expect("Failed to finish processing");
+
+            assert!(skips.is_empty());
+            assert_eq!(clients_map.len(), 53);
+            assert!(clients_map
+                .values()
+                .all(|client| client.total() == Decimal::new(0, 0)));
+        }
+    }
+}
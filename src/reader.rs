@@ -0,0 +1,505 @@
+use std::{
+    io::{BufRead, Lines, Read},
+    str::FromStr,
+};
+
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::{
+    error::Error,
+    transaction::{self, Transaction, TransactionType},
+};
+
+/// Columns which the header has to contain.
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+/// Columns which the header can contain.
+const OPTIONAL_COLUMNS: [&str; 3] = ["timestamp", "target", "currency"];
+
+/// Options of reading transactions.
+#[derive(Clone, Debug)]
+pub struct ReaderOptions {
+    /// Delimiter of fields.
+    pub delimiter: u8,
+    /// Character starting comment lines, which are skipped.
+    pub comment: Option<u8>,
+    /// Skip rows repeating the header.
+    pub skip_repeated_headers: bool,
+    /// Accept amounts in formats normalized by
+    /// [`transaction::normalize_amount`].
+    pub lenient_amounts: bool,
+    /// Currency code which amounts have to be in, if they specify one.
+    pub expected_currency: Option<String>,
+    /// Amounts are written with a comma as the decimal separator and dots
+    /// separating groups of thousands (e.g. `1.234,56`).
+    pub decimal_comma: bool,
+    /// Skip rows identical to the preceding ones.
+    pub dedup_consecutive: bool,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        ReaderOptions {
+            delimiter: b',',
+            comment: None,
+            skip_repeated_headers: false,
+            lenient_amounts: false,
+            expected_currency: None,
+            decimal_comma: false,
+            dedup_consecutive: false,
+        }
+    }
+}
+
+/// Reader of CSV series of transactions, yielding each transaction together
+/// with the number of the line it starts at.
+pub struct TransactionReader<R: Read> {
+    rdr: Reader<R>,
+    headers: StringRecord,
+    /// Index of the amount column.
+    amount_idx: Option<usize>,
+    record: StringRecord,
+    /// The previous row, used for skipping identical consecutive rows.
+    prev_record: StringRecord,
+    options: ReaderOptions,
+    /// Number of skipped rows repeating the header.
+    repeated_headers: u64,
+    /// Number of skipped rows identical to the preceding ones.
+    deduplicated_rows: u64,
+}
+
+impl<R: Read> TransactionReader<R> {
+    /// Create a new transaction reader, reading the header from the given
+    /// source.
+    pub fn new(source: R, options: ReaderOptions) -> Result<TransactionReader<R>, Error> {
+        // Number of fields is validated by the reader itself, so it can point
+        // to the offending row. A leading UTF-8 BOM (e.g. in files exported
+        // from Excel) is stripped by the reader as well.
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .comment(options.comment)
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(source);
+        let headers = rdr.headers()?.clone();
+        check_headers(&headers)?;
+        let amount_idx = headers.iter().position(|h| h == "amount");
+
+        Ok(TransactionReader {
+            rdr,
+            headers,
+            amount_idx,
+            record: StringRecord::new(),
+            prev_record: StringRecord::new(),
+            options,
+            repeated_headers: 0,
+            deduplicated_rows: 0,
+        })
+    }
+
+    /// Number of skipped rows repeating the header.
+    pub fn repeated_headers(&self) -> u64 {
+        self.repeated_headers
+    }
+
+    /// Number of skipped rows identical to the preceding ones.
+    pub fn deduplicated_rows(&self) -> u64 {
+        self.deduplicated_rows
+    }
+
+    /// Reads the next transaction, if there is any.
+    fn read_tx(&mut self) -> Result<Option<(u64, Transaction)>, Error> {
+        loop {
+            if !self.rdr.read_record(&mut self.record)? {
+                return Ok(None);
+            }
+            // Empty lines are skipped by the reader itself, but lines with
+            // only whitespace are read as a single empty field.
+            if self.record.len() == 1 && self.record[0].is_empty() {
+                continue;
+            }
+            if self.options.skip_repeated_headers && self.record == self.headers {
+                self.repeated_headers += 1;
+                continue;
+            }
+            if self.options.dedup_consecutive {
+                if self.record == self.prev_record {
+                    self.deduplicated_rows += 1;
+                    continue;
+                }
+                self.prev_record.clone_from(&self.record);
+            }
+            break;
+        }
+
+        let line = self.record.position().map_or(0, |p| p.line());
+        if self.record.len() != self.headers.len() {
+            return Err(Error::FieldCountMismatch {
+                line,
+                expected: self.headers.len(),
+                found: self.record.len(),
+            });
+        }
+        check_integer_fields(&self.record, &self.headers, line)?;
+        if let (true, Some(idx)) = (
+            self.options.lenient_amounts || self.options.decimal_comma,
+            self.amount_idx,
+        ) {
+            self.record = normalize_amount_field(&self.record, idx, &self.options)?;
+        }
+        let tx: Transaction = self.record.deserialize(Some(&self.headers))?;
+
+        Ok(Some((line, tx)))
+    }
+}
+
+/// Fails if the header lacks any of the required columns, contains unknown
+/// ones or repeats any, so reading a wrong file fails with a list of columns
+/// found instead of an error of the first row.
+fn check_headers(headers: &StringRecord) -> Result<(), Error> {
+    let valid = REQUIRED_COLUMNS
+        .iter()
+        .all(|column| headers.iter().filter(|h| h == column).count() == 1)
+        && headers.iter().enumerate().all(|(i, h)| {
+            (REQUIRED_COLUMNS.contains(&h) || OPTIONAL_COLUMNS.contains(&h))
+                && !headers.iter().take(i).any(|prev| prev == h)
+        });
+    if !valid {
+        return Err(Error::InvalidHeader {
+            found: headers.iter().map(String::from).collect(),
+        });
+    }
+    Ok(())
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<(u64, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_tx().transpose()
+    }
+}
+
+/// Ensures that the `client` and `tx` fields of the record contain integers
+/// in range of their types, so the row is rejected with a clear error instead
+/// of a generic deserialization one.
+pub(crate) fn check_integer_fields(
+    record: &StringRecord,
+    headers: &StringRecord,
+    line: u64,
+) -> Result<(), Error> {
+    for (field, max) in [("client", u16::MAX as u64), ("tx", u32::MAX as u64)] {
+        let value = match headers.iter().position(|h| h == field) {
+            Some(idx) => &record[idx],
+            None => continue,
+        };
+        if !value.parse::<u64>().is_ok_and(|value| value <= max) {
+            return Err(Error::InvalidInteger {
+                field,
+                line,
+                value: value.to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Normalizes the amount to a format accepted by `Decimal`, as required by the
+/// options.
+fn normalize_amount(amount: &str, options: &ReaderOptions) -> Result<String, Error> {
+    let mut amount = amount.to_owned();
+    if options.lenient_amounts {
+        amount = transaction::normalize_amount(&amount, options.expected_currency.as_deref())?;
+    }
+    if options.decimal_comma {
+        amount = transaction::normalize_decimal_comma(&amount)?;
+    }
+    Ok(amount)
+}
+
+/// Returns the record with its amount field normalized to a format accepted by
+/// `Decimal`.
+fn normalize_amount_field(
+    record: &StringRecord,
+    idx: usize,
+    options: &ReaderOptions,
+) -> Result<StringRecord, Error> {
+    let mut normalized = StringRecord::new();
+    for (i, field) in record.iter().enumerate() {
+        if i == idx {
+            normalized.push_field(&normalize_amount(field, options)?);
+        } else {
+            normalized.push_field(field);
+        }
+    }
+    normalized.set_position(record.position().cloned());
+    Ok(normalized)
+}
+
+/// Transaction as an object of JSON Lines input, with the amount kept as
+/// written, so it's parsed exactly from either a string or a number.
+#[derive(Deserialize)]
+struct JsonTransaction<'a> {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default, borrow)]
+    amount: Option<&'a RawValue>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    target: Option<u16>,
+    #[serde(default)]
+    currency: Option<String>,
+}
+
+/// Reader of JSON Lines series of transactions (an object per line), yielding
+/// each transaction together with the number of its line.
+///
+/// Amounts can be written either as strings or numbers. Of the
+/// [`ReaderOptions`], only `lenient_amounts`, `expected_currency` and
+/// `decimal_comma` apply, to amounts written as strings.
+pub struct JsonLinesReader<R: BufRead> {
+    lines: Lines<R>,
+    /// Number of the latest read line.
+    line: u64,
+    options: ReaderOptions,
+}
+
+impl<R: BufRead> JsonLinesReader<R> {
+    /// Create a new transaction reader, reading from the given source.
+    pub fn new(source: R, options: ReaderOptions) -> JsonLinesReader<R> {
+        JsonLinesReader {
+            lines: source.lines(),
+            line: 0,
+            options,
+        }
+    }
+
+    /// Parses a transaction from the line.
+    fn parse_tx(&self, line: &str) -> Result<Transaction, Error> {
+        let json: JsonTransaction = serde_json::from_str(line)?;
+        let amount = match json.amount {
+            Some(raw) if raw.get().starts_with('"') => Some(normalize_amount(
+                &serde_json::from_str::<String>(raw.get())?,
+                &self.options,
+            )?),
+            Some(raw) if raw.get() != "null" => Some(raw.get().to_owned()),
+            _ => None,
+        };
+        // Only empty amounts are missing, like in CSV.
+        let amount = match amount {
+            Some(amount) if !amount.trim().is_empty() => {
+                Some(Decimal::from_str(amount.trim()).map_err(|_| Error::InvalidAmount(amount))?)
+            }
+            _ => None,
+        };
+
+        let mut tx = Transaction::new(json.tx_type, json.client, json.tx, amount);
+        tx.timestamp = json.timestamp;
+        tx.target = json.target;
+        tx.currency = json.currency;
+        Ok(tx)
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesReader<R> {
+    type Item = Result<(u64, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(self.parse_tx(&line).map(|tx| (self.line, tx)).map_err(|e| {
+                Error::AtRow {
+                    row: self.line,
+                    source: Box::new(e),
+                }
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads all transactions from the data.
+    fn read_all(data: &[u8]) -> Vec<(u64, Transaction)> {
+        TransactionReader::new(data, ReaderOptions::default())
+            .expect("Failed to create a reader")
+            .map(|row| row.expect("Failed to read a transaction"))
+            .collect()
+    }
+
+    #[test]
+    fn test_bom() {
+        let data = "\
+type,       client, tx, amount
+deposit,         1,  1,    1.0
+withdrawal,      1,  2,    0.5
+dispute,         1,  1,
+";
+        let txs = read_all(data.as_bytes());
+        assert_eq!(txs.len(), 3);
+
+        let bom_data = format!("\u{feff}{}", data);
+        assert_eq!(read_all(bom_data.as_bytes()), txs);
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let data = "\
+# exported by the upstream tool
+type,       client, tx, amount
+deposit,         1,  1,    1.0
+
+# withdrawals
+withdrawal,      1,  2,    0.5
+   \t
+dispute,         1,  1,
+";
+        let txs: Vec<_> = TransactionReader::new(
+            data.as_bytes(),
+            ReaderOptions {
+                comment: Some(b'#'),
+                ..Default::default()
+            },
+        )
+        .expect("Failed to create a reader")
+        .map(|row| row.expect("Failed to read a transaction"))
+        .collect();
+        assert_eq!(
+            txs.iter().map(|(_, tx)| tx.tx).collect::<Vec<_>>(),
+            vec![1, 2, 1]
+        );
+
+        // Without the comment character, comments are malformed rows.
+        let uncommented = data.lines().skip(1).collect::<Vec<_>>().join("\n");
+        let mut rdr = TransactionReader::new(uncommented.as_bytes(), ReaderOptions::default())
+            .expect("Failed to create a reader");
+        rdr.next()
+            .expect("Expected a row")
+            .expect("Failed to read a transaction");
+        rdr.next()
+            .expect("Expected a row")
+            .expect_err("Expected a comment to fail");
+    }
+
+    #[test]
+    fn test_headers() {
+        // Order of columns doesn't matter.
+        let txs = read_all(b"amount,tx,client,type,timestamp\n1.0,1,1,deposit,100\n");
+        assert_eq!(txs.len(), 1);
+
+        for data in [
+            "type,client,tx\ndeposit,1,1\n",
+            "type,client,tx,amount,memo\ndeposit,1,1,1.0,x\n",
+            "type,client,tx,amount,tx\ndeposit,1,1,1.0,2\n",
+            "client,available,held,total,locked\n1,1.0,0,1.0,false\n",
+        ] {
+            let err = TransactionReader::new(data.as_bytes(), ReaderOptions::default())
+                .err()
+                .expect("Expected an invalid header to fail");
+            let expected: Vec<String> = data
+                .lines()
+                .next()
+                .expect("Failed to get the header")
+                .split(',')
+                .map(String::from)
+                .collect();
+            assert!(matches!(err, Error::InvalidHeader { found } if found == expected));
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let data = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.50"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 0.1, "timestamp": 5}
+
+{"type": "dispute", "client": 1, "tx": 1, "amount": null}
+{"type": "resolve", "client": 1, "tx": 1}
+{"type": "transfer", "client": 1, "tx": 3, "amount": 1, "target": 2, "currency": "USD"}
+"#;
+        let txs: Vec<_> = JsonLinesReader::new(data.as_bytes(), ReaderOptions::default())
+            .map(|row| row.expect("Failed to read a transaction"))
+            .collect();
+        let lines: Vec<_> = txs.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 2, 4, 5, 6]);
+        let amounts: Vec<_> = txs
+            .iter()
+            .map(|(_, tx)| tx.amount.map(|amount| amount.to_string()))
+            .collect();
+        assert_eq!(
+            amounts,
+            [
+                Some("1.50".to_owned()),
+                Some("0.1".to_owned()),
+                None,
+                None,
+                Some("1".to_owned())
+            ]
+        );
+        assert_eq!(txs[1].1.timestamp, Some(5));
+        assert_eq!(txs[4].1.target, Some(2));
+        assert_eq!(txs[4].1.currency.as_deref(), Some("USD"));
+
+        let data = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1}\n{\"type\": \"deposit\"}\n";
+        let err = JsonLinesReader::new(data.as_bytes(), ReaderOptions::default())
+            .nth(1)
+            .expect("Expected a second row")
+            .expect_err("Expected an incomplete transaction to fail");
+        assert!(matches!(err, Error::AtRow { row: 2, .. }));
+    }
+
+    #[test]
+    fn test_decimal_comma() {
+        let data = "type,client,tx,amount\ndeposit,1,1,\"1.234,56\"\ndispute,1,1,\n";
+        let options = ReaderOptions {
+            decimal_comma: true,
+            ..Default::default()
+        };
+        let amounts: Vec<_> = TransactionReader::new(data.as_bytes(), options.clone())
+            .expect("Failed to create a reader")
+            .map(|row| row.expect("Failed to read a transaction").1.amount)
+            .collect();
+        assert_eq!(amounts, [Some(Decimal::new(123456, 2)), None]);
+
+        let data = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.234,56"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 1.5}
+"#;
+        let amounts: Vec<_> = JsonLinesReader::new(data.as_bytes(), options)
+            .map(|row| row.expect("Failed to read a transaction").1.amount)
+            .collect();
+        assert_eq!(
+            amounts,
+            [Some(Decimal::new(123456, 2)), Some(Decimal::new(15, 1))]
+        );
+    }
+
+    #[test]
+    fn test_currency() {
+        let txs = read_all(
+            b"\
+type,       client, tx, amount, currency
+deposit,         1,  1,    1.0,      USD
+deposit,         1,  2,    2.0,
+",
+        );
+        let currencies: Vec<_> = txs.iter().map(|(_, tx)| tx.currency.as_deref()).collect();
+        assert_eq!(currencies, [Some("USD"), None]);
+
+        // The column is optional.
+        let txs = read_all(b"type,client,tx,amount\ndeposit,1,1,1.0\n");
+        assert_eq!(txs[0].1.currency, None);
+    }
+}
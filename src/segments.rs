@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+use csv::{ReaderBuilder, Terminator, Trim, WriterBuilder};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use tranzaktionz::{round_balances, Client, Error};
+
+/// Assignment of a client to a group.
+#[derive(Debug, Deserialize)]
+struct Segment {
+    client: u16,
+    group: String,
+}
+
+/// Reads the mapping of clients to groups from a CSV file with `client` and
+/// `group` columns.
+pub(crate) fn read_segments<P: AsRef<Path>>(path: P) -> Result<BTreeMap<u16, String>, Error> {
+    let rdr = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+    let mut segments = BTreeMap::new();
+    for result in rdr.into_deserialize() {
+        let segment: Segment = result?;
+        segments.insert(segment.client, segment.group);
+    }
+    Ok(segments)
+}
+
+/// Row of the report with subtotals. Either account balance of a client,
+/// subtotal of a group (with `subtotal` in the `client` column) or the grand
+/// total (with `total` in the `client` column).
+#[derive(Debug, Serialize)]
+struct SubtotalRow<'a> {
+    group: &'a str,
+    client: String,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: Option<bool>,
+}
+
+/// Sums of balances of multiple clients.
+#[derive(Debug, Default)]
+struct Sums {
+    available: Decimal,
+    held: Decimal,
+}
+
+impl Sums {
+    /// Adds balances of the client, failing if any of the sums (including the
+    /// total) overflows.
+    fn add(&mut self, client: &Client) -> Result<(), Error> {
+        let overflow = || Error::BalanceOverflow {
+            client: client.id(),
+        };
+        let available = self
+            .available
+            .checked_add(client.available())
+            .ok_or_else(overflow)?;
+        let held = self.held.checked_add(client.held()).ok_or_else(overflow)?;
+        available.checked_add(held).ok_or_else(overflow)?;
+
+        self.available = available;
+        self.held = held;
+        Ok(())
+    }
+
+    fn row<'a>(&self, group: &'a str, client: &str, scale: u32) -> SubtotalRow<'a> {
+        let (available, held, total) = round_balances(self.available, self.held, scale);
+        SubtotalRow {
+            group,
+            client: client.to_owned(),
+            available,
+            held,
+            total,
+            locked: None,
+        }
+    }
+}
+
+/// Writes clients grouped by segments, each group followed by a subtotal row,
+/// with the grand total at the end, rounding balances to the given scale,
+/// separating fields with the given delimiter and terminating lines with the
+/// given terminator.
+///
+/// Groups are sorted by name. Clients without a group are gathered in a group
+/// with an empty name.
+pub(crate) fn write_subtotals<W: Write>(
+    clients_map: &BTreeMap<u16, Client>,
+    segments: &BTreeMap<u16, String>,
+    scale: u32,
+    delimiter: u8,
+    terminator: Terminator,
+    writer: W,
+) -> Result<(), Error> {
+    let mut groups: BTreeMap<&str, Vec<&Client>> = BTreeMap::new();
+    for (id, client) in clients_map.iter() {
+        let group = segments.get(id).map_or("", |g| g.as_str());
+        groups.entry(group).or_default().push(client);
+    }
+
+    let mut wtr = WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(terminator)
+        .from_writer(writer);
+    let mut grand_total = Sums::default();
+    for (group, clients) in groups.iter() {
+        let mut subtotal = Sums::default();
+        for client in clients.iter() {
+            let (available, held, total) = round_balances(client.available(), client.held(), scale);
+            wtr.serialize(SubtotalRow {
+                group,
+                client: client.id().to_string(),
+                available,
+                held,
+                total,
+                locked: Some(client.locked()),
+            })?;
+            subtotal.add(client)?;
+            grand_total.add(client)?;
+        }
+        wtr.serialize(subtotal.row(group, "subtotal", scale))?;
+    }
+    wtr.serialize(grand_total.row("", "total", scale))?;
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+
+use csv::StringRecord;
+use postgres::{Client, NoTls};
+use rust_decimal::Decimal;
+
+use crate::{error::Error, reader, transaction::Transaction};
+
+/// Name of the cursor transactions are fetched through.
+const CURSOR: &str = "tranzaktionz_transactions";
+/// Number of rows fetched from the cursor at once.
+const FETCH_SIZE: usize = 1024;
+
+/// Values of a row returned by the query.
+#[derive(Debug)]
+struct SqlRow {
+    tx_type: String,
+    client: i32,
+    tx: i64,
+    amount: Option<Decimal>,
+}
+
+/// Source of rows returned by the query, fetched in batches.
+trait Cursor {
+    /// Fetches the next batch of rows, which is empty if there are no more.
+    fn fetch(&mut self) -> Result<Vec<SqlRow>, Error>;
+}
+
+/// Cursor of the query on a PostgreSQL database.
+struct PostgresCursor {
+    client: Client,
+    /// If true, all rows were fetched from the cursor.
+    done: bool,
+}
+
+impl PostgresCursor {
+    /// Connects to the database and opens a cursor for the given query.
+    fn new(url: &str, query: &str) -> Result<PostgresCursor, Error> {
+        let mut client = Client::connect(url, NoTls)?;
+        // Cursors exist only inside transactions.
+        client.batch_execute(&format!(
+            "BEGIN READ ONLY; DECLARE {} NO SCROLL CURSOR FOR {}",
+            CURSOR, query
+        ))?;
+        Ok(PostgresCursor {
+            client,
+            done: false,
+        })
+    }
+}
+
+impl Cursor for PostgresCursor {
+    fn fetch(&mut self) -> Result<Vec<SqlRow>, Error> {
+        if self.done {
+            return Ok(Vec::new());
+        }
+        let rows = self.client.query(
+            format!("FETCH FORWARD {} FROM {}", FETCH_SIZE, CURSOR).as_str(),
+            &[],
+        )?;
+        self.done = rows.len() < FETCH_SIZE;
+        rows.iter()
+            .map(|row| {
+                Ok(SqlRow {
+                    tx_type: row.try_get("type")?,
+                    client: row.try_get("client")?,
+                    tx: row.try_get("tx")?,
+                    amount: row.try_get("amount")?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reader of transactions from rows fetched through a cursor, yielding each
+/// transaction together with the number of its row.
+struct RowReader<C: Cursor> {
+    cursor: C,
+    headers: StringRecord,
+    rows: VecDeque<SqlRow>,
+    /// If true, all rows were fetched from the cursor.
+    done: bool,
+    /// Number of the last read row.
+    row: u64,
+}
+
+/// Headers of the CSV records rows are converted to.
+fn headers() -> StringRecord {
+    StringRecord::from(vec!["type", "client", "tx", "amount"])
+}
+
+impl<C: Cursor> RowReader<C> {
+    fn new(cursor: C) -> RowReader<C> {
+        RowReader {
+            cursor,
+            headers: headers(),
+            rows: VecDeque::new(),
+            done: false,
+            row: 0,
+        }
+    }
+
+    /// Reads the next transaction, if there is any.
+    fn read_tx(&mut self) -> Result<Option<(u64, Transaction)>, Error> {
+        if self.rows.is_empty() && !self.done {
+            let rows = self.cursor.fetch()?;
+            self.done = rows.is_empty();
+            self.rows.extend(rows);
+        }
+        let row = match self.rows.pop_front() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        self.row += 1;
+
+        let tx = to_transaction(
+            &self.headers,
+            self.row,
+            row.tx_type,
+            row.client,
+            row.tx,
+            row.amount,
+        )?;
+
+        Ok(Some((self.row, tx)))
+    }
+}
+
+impl<C: Cursor> Iterator for RowReader<C> {
+    type Item = Result<(u64, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_tx().transpose()
+    }
+}
+
+/// Reader of transactions returned by a query on a PostgreSQL database,
+/// yielding each transaction together with the number of its row.
+///
+/// The query has to return `type` (text), `client` (integer), `tx` (bigint)
+/// and `amount` (numeric, nullable) columns. Rows are fetched through a cursor
+/// in batches, so the whole result doesn't have to fit in memory.
+pub struct PostgresReader {
+    reader: RowReader<PostgresCursor>,
+}
+
+impl PostgresReader {
+    /// Connects to the database and opens a cursor for the given query.
+    pub fn new(url: &str, query: &str) -> Result<PostgresReader, Error> {
+        Ok(PostgresReader {
+            reader: RowReader::new(PostgresCursor::new(url, query)?),
+        })
+    }
+}
+
+/// Converts values of the row with the given number to a transaction.
+///
+/// The values are converted to a CSV record with the given headers first, so
+/// they're validated the same way as the CSV input.
+fn to_transaction(
+    headers: &StringRecord,
+    row: u64,
+    tx_type: String,
+    client: i32,
+    tx: i64,
+    amount: Option<Decimal>,
+) -> Result<Transaction, Error> {
+    let record = StringRecord::from(vec![
+        tx_type,
+        client.to_string(),
+        tx.to_string(),
+        amount.map_or_else(String::new, |amount| amount.to_string()),
+    ]);
+    reader::check_integer_fields(&record, headers, row)?;
+    Ok(record.deserialize(Some(headers))?)
+}
+
+impl Iterator for PostgresReader {
+    type Item = Result<(u64, Transaction), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, str::FromStr};
+
+    use crate::{ledger::Ledger, reader::ReaderOptions, transaction::TransactionType};
+
+    /// Cursor returning the given rows in batches of the given size, like
+    /// the one of a database.
+    struct FakeCursor {
+        rows: VecDeque<SqlRow>,
+        batch: usize,
+    }
+
+    impl Cursor for FakeCursor {
+        fn fetch(&mut self) -> Result<Vec<SqlRow>, Error> {
+            let batch = self.batch.min(self.rows.len());
+            Ok(self.rows.drain(..batch).collect())
+        }
+    }
+
+    /// Rows a table with the transactions of the given CSV file returns.
+    fn rows(path: &str) -> VecDeque<SqlRow> {
+        let data = std::fs::read_to_string(path).expect("Failed to read the input file");
+        data.lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+                SqlRow {
+                    tx_type: fields[0].to_owned(),
+                    client: fields[1].parse().expect("Failed to parse client"),
+                    tx: fields[2].parse().expect("Failed to parse tx"),
+                    amount: (!fields[3].is_empty())
+                        .then(|| Decimal::from_str(fields[3]).expect("Failed to parse amount")),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_row_reader() {
+        for path in ["tests/example1.csv", "tests/example2.csv"] {
+            let mut expected = Ledger::new();
+            let file = File::open(path).expect("Failed to open the input file");
+            expected
+                .process_reader(file, ReaderOptions::default())
+                .expect("Failed to process transactions");
+
+            for batch in [1, 2, FETCH_SIZE] {
+                let mut ledger = Ledger::new();
+                let reader = RowReader::new(FakeCursor {
+                    rows: rows(path),
+                    batch,
+                });
+                for (i, row) in reader.enumerate() {
+                    let (line, tx) = row.expect("Failed to read a row");
+                    assert_eq!(line, i as u64 + 1);
+                    ledger.apply(tx).expect("Failed to apply a transaction");
+                }
+                assert_eq!(ledger.snapshot(), expected.snapshot());
+                assert_eq!(ledger.skips(), expected.skips());
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_transaction() {
+        let headers = headers();
+
+        let tx = to_transaction(
+            &headers,
+            1,
+            "deposit".to_owned(),
+            1,
+            2,
+            Some(Decimal::new(15, 1)),
+        )
+        .expect("Failed to convert a row");
+        assert_eq!(
+            tx,
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::new(15, 1)))
+        );
+
+        let tx = to_transaction(&headers, 2, "dispute".to_owned(), 1, 2, None)
+            .expect("Failed to convert a row");
+        assert_eq!(tx, Transaction::new(TransactionType::Dispute, 1, 2, None));
+
+        // Values which don't fit in the types of the CSV input are rejected.
+        let err = to_transaction(&headers, 3, "deposit".to_owned(), 70_000, 2, None)
+            .expect_err("Expected a client ID out of range to fail");
+        assert!(matches!(
+            err,
+            Error::InvalidInteger {
+                field: "client",
+                line: 3,
+                ..
+            }
+        ));
+        let err = to_transaction(&headers, 4, "deposit".to_owned(), 1, -1, None)
+            .expect_err("Expected a negative transaction ID to fail");
+        assert!(matches!(
+            err,
+            Error::InvalidInteger {
+                field: "tx",
+                line: 4,
+                ..
+            }
+        ));
+
+        let err = to_transaction(&headers, 5, "refund".to_owned(), 1, 2, None)
+            .expect_err("Expected an unknown type to fail");
+        assert!(matches!(err, Error::CSV(_)));
+    }
+}
@@ -1,12 +1,14 @@
+use std::fmt;
+
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::error::Error;
 
 /// Type of transaction.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 #[serde(rename_all = "lowercase")]
-pub(crate) enum TransactionType {
+pub enum TransactionType {
     /// Credit to the client's account.
     Deposit,
     /// Debit to the client's account.
@@ -17,9 +19,41 @@ pub(crate) enum TransactionType {
     Resolve,
     /// Final state of a dispute, client reversing a transaction.
     Chargeback,
+    /// Manual correction of the client's account, crediting a positive amount
+    /// or debiting a negative one. Cannot be disputed.
+    Adjustment,
+    /// Move of funds from the client's account to the account of the target
+    /// client. Cannot be disputed.
+    Transfer,
+}
+
+impl TransactionType {
+    /// Returns true for types of transactions referring to other ones
+    /// (dispute, resolve, chargeback) - steps of a dispute lifecycle.
+    pub fn refers_tx(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Transfer => "transfer",
+        };
+        write!(f, "{}", name)
+    }
 }
 
-/// Deserialize Decimals from strings in CSV.
+/// (De)serialize Decimals from/to strings in CSV.
 ///
 /// rust_decimal comes with a serde module, available through serde-with-str
 /// feature, but it supports only fields of type `Decimal`, not
@@ -29,7 +63,17 @@ mod rust_decimal_serde_str_option {
     use super::*;
 
     use rust_decimal::prelude::*;
-    use serde::Deserializer;
+    use serde::{de, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match value {
+            Some(d) => serializer.serialize_some(&d.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
 
     pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
     where
@@ -41,58 +85,267 @@ mod rust_decimal_serde_str_option {
             return Ok(None);
         }
 
-        match Decimal::from_str(&s) {
-            Ok(d) => Ok(Some(d)),
-            Err(_) => Ok(None),
+        // Only empty amounts are missing, malformed ones are errors.
+        Decimal::from_str(s.trim())
+            .map(Some)
+            .map_err(|e| de::Error::custom(format!("invalid amount `{}`: {}", s, e)))
+    }
+}
+
+/// Normalizes an amount written in a format which `Decimal` doesn't accept:
+///
+/// * removes underscores used as digit group separators (e.g. `1_000.50`),
+///   which are allowed only between digits
+/// * removes a trailing currency code (e.g. `1.50 USD`), which has to match
+///   the expected one, if given
+pub(crate) fn normalize_amount(
+    amount: &str,
+    expected_currency: Option<&str>,
+) -> Result<String, Error> {
+    let number = match amount.trim().rsplit_once(char::is_whitespace) {
+        Some((number, currency)) if currency.chars().all(|c| c.is_ascii_alphabetic()) => {
+            if let Some(expected) = expected_currency {
+                if currency != expected {
+                    return Err(Error::CurrencyMismatch {
+                        expected: expected.to_owned(),
+                        found: currency.to_owned(),
+                    });
+                }
+            }
+            number.trim_end()
+        }
+        _ => amount,
+    };
+
+    let chars: Vec<char> = number.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_' {
+            let between_digits = i > 0
+                && chars[i - 1].is_ascii_digit()
+                && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit());
+            if !between_digits {
+                return Err(Error::InvalidAmount(amount.to_owned()));
+            }
         }
     }
+    Ok(number.replace('_', ""))
+}
+
+/// Normalizes an amount written with a comma as the decimal separator and
+/// dots separating groups of thousands (e.g. `1.234,56`) to the format which
+/// `Decimal` accepts (e.g. `1234.56`).
+pub(crate) fn normalize_decimal_comma(amount: &str) -> Result<String, Error> {
+    let invalid = || Error::InvalidAmount(amount.to_owned());
+    let (integer, fraction) = match amount.split_once(',') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (amount, None),
+    };
+    if fraction.is_some_and(|fraction| fraction.contains([',', '.'])) {
+        return Err(invalid());
+    }
+    let mut groups = integer.split('.');
+    let first = groups.next().unwrap_or_default();
+    for group in groups {
+        if group.len() != 3 || !group.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if !first.ends_with(|c: char| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+    }
+
+    let integer = integer.replace('.', "");
+    Ok(match fraction {
+        Some(fraction) => format!("{}.{}", integer, fraction),
+        None => integer,
+    })
 }
 
 /// Off-chain transaction.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
-pub(crate) struct Transaction {
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Transaction {
     #[serde(rename = "type")]
-    pub(crate) tx_type: TransactionType,
-    pub(crate) client: u16,
-    pub(crate) tx: u32,
+    pub tx_type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
     #[serde(with = "rust_decimal_serde_str_option")]
-    pub(crate) amount: Option<Decimal>,
+    pub amount: Option<Decimal>,
+    /// Optional time of the transaction (in seconds).
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Client receiving the funds of a transfer.
+    #[serde(default)]
+    pub target: Option<u16>,
+    /// Optional currency code of the amount (e.g. `USD`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Position of the transaction in the input, assigned when it's read and
+    /// increasing from 1. Zero if it's not known.
+    #[serde(skip)]
+    pub seq: u64,
     #[serde(skip)]
     disputed: bool,
+    /// If true, the transaction was disputed and the dispute was resolved.
+    #[serde(skip)]
+    resolved: bool,
+    /// If true, the transaction was charged back, which is its final state.
+    #[serde(skip)]
+    charged_back: bool,
+    /// Part of the amount held by the dispute, if only that part is disputed.
+    #[serde(skip)]
+    disputed_part: Option<Decimal>,
 }
 
 impl Transaction {
     /// Create a new transaction.
-    #[cfg(test)]
-    pub(crate) fn new(
+    pub fn new(
         tx_type: TransactionType,
         client: u16,
         tx: u32,
         amount: Option<Decimal>,
     ) -> Transaction {
         Transaction {
-            tx_type: tx_type,
-            client: client,
-            tx: tx,
-            amount: amount,
+            tx_type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            target: None,
+            currency: None,
+            seq: 0,
             disputed: false,
+            resolved: false,
+            charged_back: false,
+            disputed_part: None,
         }
     }
 
+    /// Returns the transaction with the given target client (of a transfer).
+    pub fn with_target(self, target: u16) -> Transaction {
+        Transaction {
+            target: Some(target),
+            ..self
+        }
+    }
+
+    /// Returns the transaction with the given position in the input.
+    pub fn with_seq(self, seq: u64) -> Transaction {
+        Transaction { seq, ..self }
+    }
+
     /// Claim that the transaction was erroneus and should be reversed.
     pub(crate) fn dispute(&mut self) {
         self.disputed = true;
     }
 
-    pub(crate) fn is_disputed(&self) -> bool {
-        return self.disputed;
+    /// Dispute only the given part of the amount.
+    pub(crate) fn dispute_part(&mut self, part: Decimal) {
+        self.disputed = true;
+        self.disputed_part = Some(part);
+    }
+
+    /// Release the transaction from a resolved dispute, so it can be disputed
+    /// again.
+    pub(crate) fn undispute(&mut self) {
+        self.disputed = false;
+        self.resolved = true;
+        self.disputed_part = None;
+    }
+
+    /// Reverse the transaction, ending its dispute for good.
+    pub(crate) fn charge_back(&mut self) {
+        self.disputed = false;
+        self.charged_back = true;
+    }
+
+    pub fn is_disputed(&self) -> bool {
+        self.disputed
+    }
+
+    /// Returns true if the transaction was disputed and the dispute was
+    /// resolved (even if it's disputed again).
+    pub fn is_resolved(&self) -> bool {
+        self.resolved
+    }
+
+    /// Returns true if the transaction was charged back.
+    pub fn is_charged_back(&self) -> bool {
+        self.charged_back
+    }
+
+    /// Returns the disputed part of the amount, if only part of it is
+    /// disputed.
+    pub fn disputed_part(&self) -> Option<Decimal> {
+        self.disputed_part
     }
 
     /// Gets an amount of the given transactionn or returns an error.
     pub(crate) fn get_amount_or_err(&self) -> Result<Decimal, Error> {
-        let amount = self.amount.ok_or(Error::WithoutAmount)?;
+        let amount = self.amount.ok_or(Error::WithoutAmount {
+            tx: self.tx,
+            tx_type: self.tx_type.clone(),
+        })?;
         Ok(amount)
     }
+
+    /// Gets a positive amount of the given transaction or returns an error.
+    pub(crate) fn get_positive_amount_or_err(&self) -> Result<Decimal, Error> {
+        let amount = self.get_amount_or_err()?;
+        if amount <= Decimal::new(0, 0) {
+            return Err(Error::NonPositiveAmount {
+                tx: self.tx,
+                amount,
+            });
+        }
+        Ok(amount)
+    }
+
+    /// Gets the target client of a transfer, which has to be other than the
+    /// source one, or returns an error.
+    pub(crate) fn get_target_or_err(&self) -> Result<u16, Error> {
+        match self.target {
+            Some(target) if target != self.client => Ok(target),
+            _ => Err(Error::InvalidTarget { tx: self.tx }),
+        }
+    }
+
+    /// Checks whether the transaction is structurally valid, i.e. has an
+    /// amount (positive for deposits, withdrawals and transfers) and a target
+    /// only if its type requires them, without applying it to any account.
+    /// Disputes can specify a positive amount if `partial_disputes` is set.
+    pub fn validate(&self, partial_disputes: bool) -> Result<(), Error> {
+        match self.tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                self.get_positive_amount_or_err()?;
+            }
+            TransactionType::Adjustment => {
+                self.get_amount_or_err()?;
+            }
+            TransactionType::Transfer => {
+                self.get_target_or_err()?;
+                self.get_positive_amount_or_err()?;
+            }
+            TransactionType::Dispute if partial_disputes && self.amount.is_some() => {
+                self.get_positive_amount_or_err()?;
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.ensure_no_amount()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the transaction specifies an amount.
+    pub(crate) fn ensure_no_amount(&self) -> Result<(), Error> {
+        match self.amount {
+            Some(amount) => Err(Error::WithAmount {
+                tx: self.tx,
+                tx_type: self.tx_type.clone(),
+                amount,
+            }),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -110,13 +363,15 @@ deposit
 resolve
 chargeback
 dispute
+adjustment
 ";
-        let expected = vec![
+        let expected = [
             TransactionType::Withdrawal,
             TransactionType::Deposit,
             TransactionType::Resolve,
             TransactionType::Chargeback,
             TransactionType::Dispute,
+            TransactionType::Adjustment,
         ];
 
         let rdr = ReaderBuilder::new()
@@ -146,7 +401,7 @@ resolve,         1,  4,
 dispute,         2,  5,
 chargeback,      2,  5,
 ";
-        let expected = vec![
+        let expected = [
             Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0))),
             Transaction::new(TransactionType::Deposit, 2, 2, Some(Decimal::new(2, 0))),
             Transaction::new(TransactionType::Deposit, 1, 3, Some(Decimal::new(2, 0))),
@@ -170,4 +425,151 @@ chargeback,      2,  5,
             assert_eq!(record, *exp_record);
         }
     }
+
+    #[test]
+    fn deserialize_amount() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,abc
+deposit,1,2,
+deposit,1,3,\"  \"
+deposit,1,4, 1.5
+";
+        let results: Vec<Result<Transaction, _>> = ReaderBuilder::new()
+            .from_reader(data.as_bytes())
+            .into_deserialize()
+            .collect();
+        assert_eq!(results.len(), 4);
+
+        let err = results[0]
+            .as_ref()
+            .expect_err("Expected a malformed amount to fail");
+        assert!(err.to_string().contains("invalid amount `abc`"));
+        for (result, expected) in results[1..]
+            .iter()
+            .zip([None, None, Some(Decimal::new(15, 1))])
+        {
+            let tx = result
+                .as_ref()
+                .expect("Failed to deserialize a transaction");
+            assert_eq!(tx.amount, expected);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        for tx in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(1, 0))),
+            Transaction::new(TransactionType::Adjustment, 1, 2, Some(Decimal::new(-1, 0))),
+            Transaction::new(TransactionType::Transfer, 1, 3, Some(Decimal::new(1, 0)))
+                .with_target(2),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ] {
+            tx.validate(false).expect("Expected a valid transaction");
+        }
+
+        let err = Transaction::new(TransactionType::Withdrawal, 1, 4, None)
+            .validate(false)
+            .expect_err("Expected a withdrawal without amount to fail");
+        assert!(matches!(err, Error::WithoutAmount { tx: 4, .. }));
+        let err = Transaction::new(TransactionType::Deposit, 1, 5, Some(Decimal::new(0, 0)))
+            .validate(false)
+            .expect_err("Expected a zero deposit to fail");
+        assert!(matches!(err, Error::NonPositiveAmount { tx: 5, .. }));
+        let err = Transaction::new(TransactionType::Transfer, 1, 6, Some(Decimal::new(1, 0)))
+            .validate(false)
+            .expect_err("Expected a transfer without target to fail");
+        assert!(matches!(err, Error::InvalidTarget { tx: 6 }));
+        let err = Transaction::new(TransactionType::Resolve, 1, 1, Some(Decimal::new(1, 0)))
+            .validate(false)
+            .expect_err("Expected a resolve with amount to fail");
+        assert!(matches!(err, Error::WithAmount { tx: 1, .. }));
+
+        let tx = Transaction::new(TransactionType::Dispute, 1, 1, Some(Decimal::new(1, 0)));
+        tx.validate(true)
+            .expect("Expected a partial dispute to be valid");
+        let err = tx
+            .validate(false)
+            .expect_err("Expected a dispute with amount to fail");
+        assert!(matches!(err, Error::WithAmount { tx: 1, .. }));
+        let err = Transaction::new(TransactionType::Dispute, 1, 1, Some(Decimal::new(-1, 0)))
+            .validate(true)
+            .expect_err("Expected a dispute of negative amount to fail");
+        assert!(matches!(err, Error::NonPositiveAmount { tx: 1, .. }));
+    }
+
+    #[test]
+    fn test_normalize_amount() {
+        let amount = normalize_amount("1_000.50", None).expect("Failed to normalize amount");
+        assert_eq!(amount, "1000.50");
+        assert_eq!(
+            Decimal::from_str_exact(&amount).unwrap(),
+            Decimal::new(100050, 2)
+        );
+        assert_eq!(
+            normalize_amount("1_000_000", None).expect("Failed to normalize amount"),
+            "1000000"
+        );
+        assert_eq!(
+            normalize_amount("2.5", None).expect("Failed to normalize amount"),
+            "2.5"
+        );
+
+        normalize_amount("1__000", None).expect_err("Expected repeated underscores to fail");
+        normalize_amount("_1000", None).expect_err("Expected leading underscore to fail");
+        normalize_amount("1000_", None).expect_err("Expected trailing underscore to fail");
+        normalize_amount("1_.5", None).expect_err("Expected underscore before a dot to fail");
+    }
+
+    #[test]
+    fn test_normalize_decimal_comma() {
+        for (amount, expected) in [
+            ("1.234,56", "1234.56"),
+            ("1.234.567,8", "1234567.8"),
+            ("-1.000", "-1000"),
+            ("0,5", "0.5"),
+            ("42", "42"),
+            ("", ""),
+        ] {
+            let normalized = normalize_decimal_comma(amount).expect("Failed to normalize amount");
+            assert_eq!(normalized, expected);
+        }
+        let normalized = normalize_decimal_comma("1.234,56").expect("Failed to normalize amount");
+        assert_eq!(
+            Decimal::from_str_exact(&normalized).expect("Failed to parse normalized amount"),
+            Decimal::new(123456, 2)
+        );
+
+        for amount in ["1,2,3", "1,234.5", "1.23,4", "1.2345", ".123", "1..234"] {
+            let err =
+                normalize_decimal_comma(amount).expect_err("Expected a malformed amount to fail");
+            assert_eq!(err, Error::InvalidAmount(amount.to_owned()));
+        }
+    }
+
+    #[test]
+    fn test_normalize_amount_currency() {
+        assert_eq!(
+            normalize_amount("1.50 USD", None).expect("Failed to normalize amount"),
+            "1.50"
+        );
+        assert_eq!(
+            normalize_amount("1.50 USD", Some("USD")).expect("Failed to normalize amount"),
+            "1.50"
+        );
+        assert_eq!(
+            normalize_amount("1_000 EUR", Some("EUR")).expect("Failed to normalize amount"),
+            "1000"
+        );
+        // Amount without the code is accepted even if the currency is
+        // expected.
+        assert_eq!(
+            normalize_amount("1.50", Some("USD")).expect("Failed to normalize amount"),
+            "1.50"
+        );
+
+        let err = normalize_amount("1.50 EUR", Some("USD"))
+            .expect_err("Expected mismatched currency to fail");
+        assert_eq!(err.to_string(), "expected currency `USD`, found `EUR`");
+    }
 }
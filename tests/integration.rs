@@ -1,15 +1,20 @@
 use std::{
     ffi::OsStr,
-    process::{Command, Output},
+    io::Write,
+    process::{Command, Output, Stdio},
 };
 
-fn cli_output_for<P: AsRef<OsStr>>(file: P) -> Output {
+fn cli_output_with<S: AsRef<OsStr>>(args: &[S]) -> Output {
     #[cfg(debug_assertions)]
     let mut cmd = Command::new("target/debug/tranzaktionz");
     #[cfg(not(debug_assertions))]
     let mut cmd = Command::new("target/release/tranzaktionz");
 
-    cmd.arg(file).output().expect("Failed to execute CLI")
+    cmd.args(args).output().expect("Failed to execute CLI")
+}
+
+fn cli_output_for<P: AsRef<OsStr>>(file: P) -> Output {
+    cli_output_with(&[file])
 }
 
 #[test]
@@ -34,3 +39,1976 @@ client,available,held,total,locked
 "
     );
 }
+
+#[test]
+fn test_cli_assert_sorted_by_client() {
+    // Without the flag, the order of clients doesn't matter.
+    let output = cli_output_for("tests/unsorted.csv");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,2.0,0,2.0,false
+"
+    );
+
+    let output = cli_output_with(&["tests/unsorted.csv", "--assert-sorted-by-client"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("transactions are not sorted by client (line 5)"));
+}
+
+#[test]
+fn test_cli_dispute_expiry() {
+    // Without the option, both disputes stay open.
+    let output = cli_output_for("tests/dispute_expiry.csv");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,3.0,1.0,4.0,false
+2,0.0,2.0,2.0,false
+"
+    );
+
+    // Dispute of tx 1 was opened 900 seconds before the last transaction,
+    // dispute of tx 2 only 200 seconds before.
+    let output = cli_output_with(&["tests/dispute_expiry.csv", "--dispute-expiry", "600"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,4.0,0.0,4.0,false
+2,0.0,2.0,2.0,false
+"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("auto-resolved expired dispute of tx 1 (client 1)"));
+    assert!(!stderr.contains("tx 2"));
+}
+
+#[test]
+fn test_cli_compare_to() {
+    let output = cli_output_with(&[
+        "tests/example1.csv",
+        "--compare-to",
+        "tests/example1_expected.csv",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let output = cli_output_with(&[
+        "tests/example1.csv",
+        "--compare-to",
+        "tests/example2_expected.csv",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "\
+--- tests/example2_expected.csv
++++ output
+@@ -1,3 +1,3 @@
+ client,available,held,total,locked
+-1,1.5,0.0,1.5,false
+-2,0.0,0.0,0.0,true
++1,1.5,0,1.5,false
++2,2.0,0,2.0,false
+"
+    ));
+}
+
+#[test]
+fn test_cli_changed_only() {
+    // Balances of client 1 are equal to the baseline, balances of client 2
+    // changed.
+    let output = cli_output_with(&[
+        "tests/example1.csv",
+        "--baseline",
+        "tests/example1_baseline.csv",
+        "--changed-only",
+    ]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+2,2.0,0,2.0,false
+"
+    );
+
+    // Clients missing in the baseline are new, therefore they're included.
+    let output = cli_output_with(&[
+        "tests/example1.csv",
+        "--baseline",
+        "tests/example1_baseline_partial.csv",
+        "--changed-only",
+    ]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+2,2.0,0,2.0,false
+"
+    );
+}
+
+#[test]
+fn test_cli_skip_repeated_headers() {
+    let output = cli_output_for("tests/repeated_header.csv");
+    assert!(!output.status.success());
+
+    let output = cli_output_with(&["tests/repeated_header.csv", "--skip-repeated-headers"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,2.0,0,2.0,false
+"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped 1 repeated header rows"));
+}
+
+#[test]
+fn test_cli_subtotals() {
+    let output = cli_output_with(&[
+        "tests/subtotals.csv",
+        "--segments",
+        "tests/segments.csv",
+        "--subtotals",
+    ]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+group,client,available,held,total,locked
+retail,1,1.25,0,1.25,false
+retail,3,0.0,3.5,3.5,false
+retail,subtotal,1.25,3.5,4.75,
+vip,2,2.0,0,2.0,false
+vip,subtotal,2.0,0,2.0,
+,total,3.25,3.5,6.75,
+"
+    );
+}
+
+#[test]
+fn test_cli_subtotals_overflow() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("subtotals_overflow.csv");
+    std::fs::write(
+        &path,
+        "\
+type,client,tx,amount
+deposit,1,1,40000000000000000000000000000
+deposit,2,2,40000000000000000000000000000
+deposit,3,3,40000000000000000000000000000
+",
+    )
+    .expect("Failed to write the input file");
+
+    // Clients 1 and 3 are both in the retail group.
+    let output = cli_output_with(&[
+        path.as_os_str(),
+        OsStr::new("--segments"),
+        OsStr::new("tests/segments.csv"),
+        OsStr::new("--subtotals"),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("balance of client `3` would overflow")
+    );
+}
+
+#[test]
+fn test_cli_reject_tx_zero() {
+    let output = cli_output_for("tests/tx_zero.csv");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,3.0,0,3.0,false
+"
+    );
+
+    let output = cli_output_with(&["tests/tx_zero.csv", "--reject-tx-zero"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,2.0,0,2.0,false
+"
+    );
+}
+
+#[test]
+fn test_cli_max_skips() {
+    let output = cli_output_with(&["tests/skips.csv", "--max-skips", "3"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.5,0,0.5,false
+"
+    );
+
+    for args in [&[][..], &["--threads", "2"][..]] {
+        let output = cli_output_with(&[&["tests/skips.csv", "--max-skips", "2"], args].concat());
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("more than 2 transactions skipped, input is likely malformed"));
+    }
+}
+
+#[test]
+fn test_cli_lenient_amounts() {
+    let output = cli_output_with(&["tests/lenient_amounts.csv", "--lenient-amounts"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1000.00,0,1000.00,false
+"
+    );
+
+    let output = cli_output_with(&[
+        "tests/lenient_amounts.csv",
+        "--lenient-amounts",
+        "--expected-currency",
+        "EUR",
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("expected currency `EUR`, found `USD`")
+    );
+}
+
+#[test]
+fn test_cli_auto_parallel() {
+    // Medium file with enough clients for processing in multiple threads.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("auto_parallel.csv");
+    let mut data = String::from("type,client,tx,amount\n");
+    for i in 0..5000u32 {
+        let client = i % 500;
+        data.push_str(&format!("deposit,{},{},{}.5\n", client, i * 2, i % 7));
+        data.push_str(&format!("withdrawal,{},{},2.25\n", client, i * 2 + 1));
+        if i % 3 == 0 {
+            data.push_str(&format!("dispute,{},{},\n", client, i * 2));
+        }
+        if i % 9 == 0 {
+            data.push_str(&format!("resolve,{},{},\n", client, i * 2));
+        }
+    }
+    std::fs::write(&path, data).expect("Failed to write the input file");
+
+    let output = cli_output_for(&path);
+    assert_eq!(output.status.code(), Some(2));
+    let output_parallel = cli_output_with(&[path.as_os_str(), OsStr::new("--auto-parallel")]);
+    assert_eq!(output_parallel.status.code(), Some(2));
+    assert_eq!(output.stdout, output_parallel.stdout);
+
+    // Multiple threads are used as long as there is more than one CPU.
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let stderr = String::from_utf8_lossy(&output_parallel.stderr);
+    if threads > 1 {
+        assert!(stderr.contains(&format!("processing transactions in {} threads", threads)));
+    } else {
+        assert!(!stderr.contains("threads"));
+    }
+}
+
+#[test]
+fn test_cli_auto_parallel_transfer() {
+    // Enough clients for processing in multiple threads, but transfers can
+    // change accounts owned by different threads.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("auto_parallel_transfer.csv");
+    let mut data = String::from("type,client,tx,amount,target\n");
+    for i in 0..500u32 {
+        data.push_str(&format!("deposit,{},{},5.0,\n", i, i * 2));
+        data.push_str(&format!(
+            "transfer,{},{},1.0,{}\n",
+            i,
+            i * 2 + 1,
+            (i + 1) % 500
+        ));
+    }
+    std::fs::write(&path, data).expect("Failed to write the input file");
+
+    let output = cli_output_for(&path);
+    assert!(output.status.success());
+    let output_parallel = cli_output_with(&[path.as_os_str(), OsStr::new("--auto-parallel")]);
+    assert!(output_parallel.status.success());
+    assert_eq!(output.stdout, output_parallel.stdout);
+    assert!(!String::from_utf8_lossy(&output_parallel.stderr).contains("threads"));
+}
+
+#[test]
+fn test_cli_threads_transfer() {
+    // A transfer after the sample of `--auto-parallel`, when processing in
+    // multiple threads.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("threads_transfer.csv");
+    let mut data = String::from("type,client,tx,amount,target\n");
+    for i in 0..10_000u32 {
+        data.push_str(&format!("deposit,{},{},1.0,\n", i % 100, i + 1));
+    }
+    data.push_str("transfer,1,10001,2.5,2\n");
+    data.push_str("withdrawal,2,10002,1.0,\n");
+    data.push_str("withdrawal,3,10003,500.0,\n");
+    std::fs::write(&path, data).expect("Failed to write the input file");
+
+    let output = cli_output_for(&path);
+    assert_eq!(output.status.code(), Some(2));
+    let output_auto = cli_output_with(&[path.as_os_str(), OsStr::new("--auto-parallel")]);
+    let output_threads =
+        cli_output_with(&[path.as_os_str(), OsStr::new("--threads"), OsStr::new("2")]);
+    for output_parallel in [&output_auto, &output_threads] {
+        assert_eq!(output_parallel.status.code(), Some(2));
+        assert_eq!(output.stdout, output_parallel.stdout);
+    }
+    assert!(String::from_utf8_lossy(&output_threads.stderr)
+        .contains("processing transactions in a single thread from transfer tx 10001"));
+}
+
+#[test]
+fn test_cli_threads() {
+    let output = cli_output_for("tests/example2.csv");
+    assert_eq!(output.status.code(), Some(2));
+    for threads in ["1", "2", "3"] {
+        let output_threads = cli_output_with(&["tests/example2.csv", "--threads", threads]);
+        assert_eq!(output_threads.status.code(), Some(2));
+        assert_eq!(output.stdout, output_threads.stdout);
+    }
+}
+
+#[test]
+fn test_cli_metrics_json() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("metrics.json");
+    let output = cli_output_with(&[
+        OsStr::new("tests/metrics.csv"),
+        OsStr::new("--metrics-json"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let metrics = std::fs::read_to_string(&path).expect("Failed to read metrics");
+    assert_eq!(
+        metrics,
+        r#"{
+  "rows_read": 7,
+  "transactions": {
+    "deposit": 3,
+    "withdrawal": 1,
+    "dispute": 2,
+    "chargeback": 1
+  },
+  "applied": 6,
+  "skipped": {
+    "no_funds": 1
+  },
+  "filtered": 0,
+  "repeated_headers": 0,
+  "deduplicated_rows": 0,
+  "clients": 2,
+  "open_disputes": 1,
+  "locked": 1,
+  "held": "2.0"
+}"#
+    );
+}
+
+#[test]
+fn test_cli_row_checksum() {
+    let output1 = cli_output_with(&["tests/example1.csv", "--row-checksum"]);
+    assert_eq!(output1.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output1.stdout),
+        "\
+client,available,held,total,locked,checksum
+1,1.5,0,1.5,false,0816c87bfd25f2e7
+2,2.0,0,2.0,false,2545f57c57b056c6
+"
+    );
+
+    let output2 = cli_output_with(&["tests/example1.csv", "--row-checksum"]);
+    assert_eq!(output1.stdout, output2.stdout);
+}
+
+#[test]
+fn test_cli_report_blocked() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("blocked.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/blocked.csv"),
+        OsStr::new("--report-blocked"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.0,0.0,0.0,true
+2,2.0,0,2.0,false
+"
+    );
+
+    let blocked = std::fs::read_to_string(&path).expect("Failed to read the blocked report");
+    assert_eq!(
+        blocked,
+        "\
+line,tx,client,type
+6,3,1,deposit
+7,4,1,withdrawal
+9,3,1,dispute
+"
+    );
+}
+
+#[test]
+fn test_cli_log_errors() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("errors.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/log_errors.csv"),
+        OsStr::new("--log-errors"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,5.0,0,5.0,false
+2,1.0,0,1.0,false
+"
+    );
+
+    let errors = std::fs::read_to_string(&path).expect("Failed to read the error log");
+    assert_eq!(
+        errors,
+        "\
+line,tx,client,type,kind,error
+3,2,1,withdrawal,no_funds,no funds available (requested 10.0 from client 1 with 5.0 available)
+4,3,1,dispute,transaction_not_found,transaction not found
+5,1,1,resolve,tx_not_disputed,\"transaction is not dissputed, cannot resolve/chargeback\"
+"
+    );
+}
+
+#[test]
+fn test_cli_idempotent_resolve() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("idempotent_resolve.json");
+    for (args, skipped) in [
+        (vec![], "\"tx_not_disputed\": 2"),
+        (vec!["--idempotent-resolve"], "\"tx_not_disputed\": 1"),
+    ] {
+        let mut args: Vec<&OsStr> = args.into_iter().map(OsStr::new).collect();
+        args.extend([
+            OsStr::new("tests/idempotent_resolve.csv"),
+            OsStr::new("--metrics-json"),
+            path.as_os_str(),
+        ]);
+        let output = cli_output_with(&args);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "\
+client,available,held,total,locked
+1,3.5,0.0,3.5,false
+"
+        );
+        let metrics = std::fs::read_to_string(&path).expect("Failed to read metrics");
+        assert!(metrics.contains(skipped));
+    }
+}
+
+#[test]
+fn test_cli_sort_by() {
+    // Clients 1, 3 and 4 have the same total, so they're sorted by ID.
+    let output = cli_output_with(&["tests/sort_by.csv", "--sort-by", "total"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+5,1.0,0,1.0,false
+1,2.0,0,2.0,false
+3,0.0,2.0,2.0,false
+4,2.0,0,2.0,false
+2,3.0,0,3.0,false
+"
+    );
+
+    let output = cli_output_with(&["tests/sort_by.csv", "--sort-by", "held"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,2.0,0,2.0,false
+2,3.0,0,3.0,false
+4,2.0,0,2.0,false
+5,1.0,0,1.0,false
+3,0.0,2.0,2.0,false
+"
+    );
+
+    // Ties are still sorted by ascending ID.
+    let output = cli_output_with(&["tests/sort_by.csv", "--sort-by", "total", "--order", "desc"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+2,3.0,0,3.0,false
+1,2.0,0,2.0,false
+3,0.0,2.0,2.0,false
+4,2.0,0,2.0,false
+5,1.0,0,1.0,false
+"
+    );
+
+    let output = cli_output_with(&["tests/sort_by.csv", "--order", "desc"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+5,1.0,0,1.0,false
+4,2.0,0,2.0,false
+3,0.0,2.0,2.0,false
+2,3.0,0,3.0,false
+1,2.0,0,2.0,false
+"
+    );
+}
+
+#[test]
+fn test_cli_liabilities() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("liabilities.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/liabilities.csv"),
+        OsStr::new("--liabilities"),
+        path.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.00,2.25,2.25,false
+2,0.0,3.5,3.5,false
+3,1.0,0.0,1.0,false
+"
+    );
+
+    // Client 3 has no open disputes, so it has no liabilities.
+    let liabilities = std::fs::read_to_string(&path).expect("Failed to read liabilities");
+    assert_eq!(
+        liabilities,
+        "\
+client,held
+1,2.25
+2,3.5
+"
+    );
+}
+
+#[test]
+fn test_cli_skip_ragged_rows() {
+    let output = cli_output_for("tests/ragged.csv");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("row has 3 fields instead of 4 (line 3)")
+    );
+
+    let output = cli_output_with(&["tests/ragged.csv", "--skip-ragged-rows"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,2.0,0,2.0,false
+2,1.0,0,1.0,false
+"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("row has 3 fields instead of 4 (line 3)"));
+    assert!(stderr.contains("row has 5 fields instead of 4 (line 5)"));
+}
+
+#[test]
+fn test_cli_normalize_client_ids() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("id_map.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/sparse_ids.csv"),
+        OsStr::new("--normalize-client-ids"),
+        OsStr::new("--id-map"),
+        path.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,1.5,0,1.5,false
+"
+    );
+
+    let id_map = std::fs::read_to_string(&path).expect("Failed to read the ID map");
+    assert_eq!(
+        id_map,
+        "\
+original,client
+7,1
+9000,2
+"
+    );
+}
+
+#[test]
+fn test_cli_checkpoint_restore() {
+    let tmp_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let checkpoint = tmp_dir.join("checkpoint.json");
+    let blocked = tmp_dir.join("checkpoint_blocked.csv");
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/checkpoint1.csv"),
+        OsStr::new("--checkpoint"),
+        checkpoint.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.0,5.0,5.0,true
+2,2.0,0,2.0,false
+"
+    );
+
+    // Client 1, locked before the checkpoint, can't withdraw after restoring.
+    let output = cli_output_with(&[
+        OsStr::new("tests/checkpoint2.csv"),
+        OsStr::new("--restore"),
+        checkpoint.as_os_str(),
+        OsStr::new("--report-blocked"),
+        blocked.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.0,5.0,5.0,true
+2,1.5,0,1.5,false
+"
+    );
+    let blocked = std::fs::read_to_string(&blocked).expect("Failed to read the blocked report");
+    assert_eq!(
+        blocked,
+        "\
+line,tx,client,type
+2,4,1,withdrawal
+"
+    );
+}
+
+#[test]
+fn test_cli_dedup_consecutive() {
+    let output = cli_output_for("tests/dedup.csv");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,5.0,0,5.0,false
+2,2.0,0,2.0,false
+"
+    );
+
+    // Only the repeated deposit is skipped, not the one with a different ID.
+    let output = cli_output_with(&["tests/dedup.csv", "--dedup-consecutive"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,3.0,0,3.0,false
+2,2.0,0,2.0,false
+"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("skipped 1 rows identical to the preceding ones"));
+}
+
+#[test]
+fn test_cli_with_lock_time() {
+    let output = cli_output_with(&["tests/lock_time.csv", "--with-lock-time"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked,locked_at
+1,0.0,0.0,0.0,true,350
+2,3.0,0,3.0,false,
+"
+    );
+}
+
+#[test]
+fn test_cli_require_dispute_before_chargeback() {
+    let tmp_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let checkpoint = tmp_dir.join("dispute_rows.json");
+    let metrics = tmp_dir.join("dispute_rows_metrics.json");
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/dispute_rows1.csv"),
+        OsStr::new("--checkpoint"),
+        checkpoint.as_os_str(),
+    ]);
+    assert!(output.status.success());
+
+    // Transaction 1 is disputed after restoring the checkpoint, so its
+    // chargeback is accepted by default, locking the account before the
+    // chargeback of transaction 2.
+    let output = cli_output_with(&[
+        OsStr::new("tests/dispute_rows2.csv"),
+        OsStr::new("--restore"),
+        checkpoint.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.0,1.0,1.0,true
+"
+    );
+
+    // The chargeback of transaction 1 is not preceded by a dispute row, so
+    // it's skipped.
+    let output = cli_output_with(&[
+        OsStr::new("tests/dispute_rows2.csv"),
+        OsStr::new("--restore"),
+        checkpoint.as_os_str(),
+        OsStr::new("--require-dispute-before-chargeback"),
+        OsStr::new("--metrics-json"),
+        metrics.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.0,2.0,2.0,true
+"
+    );
+    let metrics = std::fs::read_to_string(&metrics).expect("Failed to read metrics");
+    assert!(metrics.contains("\"chargeback_without_dispute\": 1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_emit_socket() {
+    use std::{io::Read, os::unix::net::UnixListener, thread};
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).expect("Failed to bind the socket");
+    let consumer = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Failed to accept a connection");
+        let mut report = String::new();
+        stream
+            .read_to_string(&mut report)
+            .expect("Failed to read the report");
+        report
+    });
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/example1.csv"),
+        OsStr::new("--emit-socket"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        consumer.join().expect("Consumer thread failed"),
+        "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,2.0,0,2.0,false
+"
+    );
+
+    // Nobody is listening anymore.
+    std::fs::remove_file(&path).expect("Failed to remove the socket");
+    let output = cli_output_with(&[
+        OsStr::new("tests/example1.csv"),
+        OsStr::new("--emit-socket"),
+        path.as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is the consumer listening?"));
+}
+
+#[test]
+fn test_cli_manifest() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("manifest.json");
+    let output = cli_output_with(&[
+        OsStr::new("tests/example1.csv"),
+        OsStr::new("--manifest"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let manifest = std::fs::read_to_string(&path).expect("Failed to read manifest");
+    assert!(manifest.contains(r#""path": "tests/example1.csv""#));
+    assert!(manifest.contains(r#""rows_read": 5"#));
+}
+
+#[test]
+fn test_cli_with_delta() {
+    let checkpoint =
+        std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("with_delta_checkpoint.json");
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/checkpoint1.csv"),
+        OsStr::new("--checkpoint"),
+        checkpoint.as_os_str(),
+        OsStr::new("--with-delta"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked,delta_available,delta_held,delta_total
+1,0.0,5.0,5.0,true,0,5.0,5.0
+2,2.0,0,2.0,false,2.0,0,2.0
+"
+    );
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/checkpoint2.csv"),
+        OsStr::new("--restore"),
+        checkpoint.as_os_str(),
+        OsStr::new("--with-delta"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked,delta_available,delta_held,delta_total
+1,0.0,5.0,5.0,true,0.0,0.0,0.0
+2,1.5,0,1.5,false,-0.5,0,-0.5
+"
+    );
+}
+
+#[test]
+fn test_cli_lifecycle_only() {
+    let checkpoint =
+        std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("lifecycle_checkpoint.json");
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/lifecycle1.csv"),
+        OsStr::new("--checkpoint"),
+        checkpoint.as_os_str(),
+    ]);
+    assert!(output.status.success());
+
+    let output = cli_output_with(&[
+        OsStr::new("tests/lifecycle2.csv"),
+        OsStr::new("--restore"),
+        checkpoint.as_os_str(),
+        OsStr::new("--lifecycle-only"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,5.0,3.0,8.0,false
+2,0.0,0.0,0.0,true
+"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("skipped 2 rows other than dispute lifecycle ones"));
+    assert!(stderr.contains("applied 5, skipped 0, filtered 2"));
+
+    // Transaction 9 is not in the checkpoint.
+    let output = cli_output_with(&[
+        OsStr::new("tests/lifecycle3.csv"),
+        OsStr::new("--restore"),
+        checkpoint.as_os_str(),
+        OsStr::new("--lifecycle-only"),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "transaction `9` referred by a dispute lifecycle row is not in the restored state"
+    ));
+}
+
+#[test]
+fn test_cli_line_ending() {
+    let output = cli_output_with(&["tests/example1.csv", "--line-ending", "crlf"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        output.stdout,
+        b"client,available,held,total,locked\r\n1,1.5,0,1.5,false\r\n2,2.0,0,2.0,false\r\n"
+    );
+}
+
+#[test]
+fn test_cli_invalid_client() {
+    let output = cli_output_for("tests/invalid_client.csv");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("invalid client `abc`, expected a non-negative integer in range (line 3)"));
+
+    let output = cli_output_with(&["tests/invalid_client.csv", "--skip-invalid-integers"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,2.0,0,2.0,false
+2,1.5,0,1.5,false
+"
+    );
+}
+
+#[test]
+fn test_cli_invalid_tx() {
+    let output = cli_output_for("tests/invalid_tx.csv");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("invalid tx `-2`, expected a non-negative integer in range (line 3)"));
+}
+
+#[test]
+fn test_cli_soft_lock_ratio() {
+    let output = cli_output_with(&["tests/soft_lock.csv", "--soft-lock-ratio", "0.4"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,100.0,10.0,110.0,false
+2,100.0,80.0,180.0,false
+"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(
+        "warning: held funds (80.0) of client 2 exceed 0.4 of total funds (180.0) after dispute of tx 4"
+    ));
+    assert!(!stderr.contains("client 1"));
+}
+
+#[test]
+fn test_cli_sql_dump() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("dump.sql");
+    let output = cli_output_with(&[
+        OsStr::new("tests/checkpoint1.csv"),
+        OsStr::new("--sql-dump"),
+        path.as_os_str(),
+        OsStr::new("--sql-table"),
+        OsStr::new("accounts"),
+    ]);
+    assert!(output.status.success());
+
+    let dump = std::fs::read_to_string(&path).expect("Failed to read the SQL dump");
+    assert_eq!(
+        dump,
+        "\
+INSERT INTO accounts (client, available, held, total, locked) VALUES (1, 0.0, 5.0, 5.0, TRUE);
+INSERT INTO accounts (client, available, held, total, locked) VALUES (2, 2.0, 0, 2.0, FALSE);
+"
+    );
+
+    // Names with a schema are accepted, other than identifiers are not.
+    for (table, valid) in [
+        ("public.accounts", true),
+        ("_accounts2", true),
+        ("accounts; DROP TABLE accounts", false),
+        ("my accounts", false),
+        ("2accounts", false),
+        ("public.", false),
+        ("\"accounts\"", false),
+    ] {
+        let output = cli_output_with(&[
+            OsStr::new("tests/checkpoint1.csv"),
+            OsStr::new("--sql-dump"),
+            path.as_os_str(),
+            OsStr::new("--sql-table"),
+            OsStr::new(table),
+        ]);
+        assert_eq!(output.status.success(), valid);
+        if !valid {
+            assert!(String::from_utf8_lossy(&output.stderr)
+                .contains(&format!("invalid table name `{}`", table)));
+        }
+    }
+}
+
+#[test]
+fn test_cli_as_of_tx() {
+    let output = cli_output_with(&["tests/as_of.csv", "--as-of-tx", "3"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("skipped 2 transactions after tx 3"));
+    // The dispute of the ignored deposit is skipped.
+    assert!(stderr.contains("applied 3, skipped 1 (transaction_not_found: 1), filtered 2"));
+
+    let prefix_output = cli_output_for("tests/as_of_prefix.csv");
+    assert!(prefix_output.status.success());
+    assert_eq!(output.stdout, prefix_output.stdout);
+
+    // The transaction has to be in the input.
+    let output = cli_output_with(&["tests/as_of.csv", "--as-of-tx", "9"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("transaction 9 to reconstruct balances as of not found"));
+}
+
+#[test]
+fn test_cli_format_json() {
+    let output = cli_output_with(&["tests/example1.csv", "--format", "json"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        r#"[{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false},{"client":2,"available":"2.0","held":"0","total":"2.0","locked":false}]
+"#
+    );
+}
+
+#[test]
+fn test_cli_rounding() {
+    let output = cli_output_for("tests/rounding.csv");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.0000,0,1.0000,false
+2,3.0002,0,3.0002,false
+"
+    );
+}
+
+#[test]
+fn test_cli_rounding_consistent_total() {
+    let tmp_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = tmp_dir.join("rounding_total.csv");
+    let report = tmp_dir.join("rounding_total_report.csv");
+    std::fs::write(
+        &input,
+        "\
+type,client,tx,amount
+deposit,1,1,0.00005
+deposit,1,2,0.00005
+dispute,1,1,
+",
+    )
+    .expect("Failed to write the input file");
+
+    // Rounded separately, the total would be 0.0001.
+    let output = cli_output_with(&[
+        input.as_os_str(),
+        OsStr::new("--output"),
+        report.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(&report).expect("Failed to read the output"),
+        "\
+client,available,held,total,locked
+1,0.0000,0.0000,0.0000,false
+"
+    );
+
+    // The output can be read back.
+    let empty = tmp_dir.join("rounding_total_empty.csv");
+    std::fs::write(&empty, "type,client,tx,amount\n").expect("Failed to write the input file");
+    let output = cli_output_with(&[
+        empty.as_os_str(),
+        OsStr::new("--initial-state"),
+        report.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0,0,0,false
+"
+    );
+}
+
+#[test]
+fn test_cli_no_clients() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_clients.csv");
+    std::fs::write(&path, "type,client,tx,amount\n").expect("Failed to write the input file");
+    let output = cli_output_for(&path);
+    assert!(output.status.success());
+    // Without any clients, not even the header is written.
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_cli_output() {
+    let tmp_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let path = tmp_dir.join("output.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/example1.csv"),
+        OsStr::new("--output"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&path).expect("Failed to read the output"),
+        "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,2.0,0,2.0,false
+"
+    );
+
+    let path = tmp_dir.join("nonexistent").join("output.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/example1.csv"),
+        OsStr::new("--output"),
+        path.as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot create output file"));
+}
+
+#[test]
+fn test_cli_max_history() {
+    let output = cli_output_with(&["tests/max_history.csv", "--max-history", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,3.0,3.0,6.0,false
+"
+    );
+}
+
+#[test]
+fn test_cli_initial_state() {
+    let output = cli_output_with(&[
+        "tests/initial_state.csv",
+        "--initial-state",
+        "tests/example1_expected.csv",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    // The dispute refers to a transaction from before the initial state.
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,2.5,0,2.5,false
+2,0.5,0,0.5,false
+"
+    );
+}
+
+#[test]
+fn test_cli_strict() {
+    let output = cli_output_with(&["tests/log_errors.csv", "--strict"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("no funds available (requested 10.0 from client 1 with 5.0 available) (row 3)"));
+}
+
+#[test]
+fn test_cli_delimiter() {
+    let output = cli_output_with(&["tests/example1.tsv", "--delimiter", "\t"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client\tavailable\theld\ttotal\tlocked
+1\t1.5\t0\t1.5\tfalse
+2\t2.0\t0\t2.0\tfalse
+"
+    );
+
+    let output = cli_output_with(&["tests/example1.tsv", "--delimiter", "ab"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("invalid delimiter `ab`, expected a single ASCII character"));
+}
+
+#[test]
+fn test_cli_summary() {
+    let output = cli_output_for("tests/metrics.csv");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "read 7 transactions (deposit: 3, withdrawal: 1, dispute: 2, chargeback: 1), \
+         applied 6, skipped 1 (no_funds: 1), 2 clients, 1 locked, 2.0 held"
+    ));
+
+    let output = cli_output_with(&["tests/metrics.csv", "--quiet"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("read 7 transactions"));
+}
+
+#[test]
+fn test_cli_scale() {
+    let output = cli_output_with(&["tests/rounding.csv", "--scale", "2"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.00,0,1.00,false
+2,3.00,0,3.00,false
+"
+    );
+
+    let output = cli_output_with(&["tests/rounding.csv", "--scale", "8", "--format", "json"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        r#"[{"client":1,"available":"1.00005","held":"0","total":"1.00005","locked":false},{"client":2,"available":"3.00015","held":"0","total":"3.00015","locked":false}]
+"#
+    );
+}
+
+#[test]
+fn test_cli_transfer() {
+    let output = cli_output_for("tests/transfer.csv");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.5,0,1.5,false
+2,3.0,0,3.0,false
+3,1.5,0,1.5,false
+"
+    );
+
+    // The remaining transactions are processed in a single thread.
+    let output_threads = cli_output_with(&["tests/transfer.csv", "--threads", "2"]);
+    assert_eq!(output_threads.status.code(), Some(2));
+    assert_eq!(output_threads.stdout, output.stdout);
+}
+
+#[test]
+fn test_cli_stdin() {
+    let input = std::fs::read("tests/example1.csv").expect("Failed to read the input");
+    let expected = cli_output_for("tests/example1.csv");
+    assert_eq!(expected.status.code(), Some(2));
+
+    for args in [&[][..], &["-"][..]] {
+        #[cfg(debug_assertions)]
+        let mut cmd = Command::new("target/debug/tranzaktionz");
+        #[cfg(not(debug_assertions))]
+        let mut cmd = Command::new("target/release/tranzaktionz");
+
+        let mut child = cmd
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute CLI");
+        child
+            .stdin
+            .take()
+            .expect("Failed to open stdin")
+            .write_all(&input)
+            .expect("Failed to write to stdin");
+        let output = child.wait_with_output().expect("Failed to wait for CLI");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, expected.stdout);
+    }
+}
+
+#[test]
+fn test_cli_missing_amount() {
+    let output = cli_output_for("tests/missing_amount.csv");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains(r#"invalid header ["type", "client", "tx"]"#));
+}
+
+#[test]
+fn test_cli_validate_only() {
+    let output = cli_output_with(&["tests/validate_only.csv", "--validate-only"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid: withdrawal tx 3 has to specify amount (row 4)\n"));
+    assert!(stderr.contains("3 valid rows, 1 invalid rows\n"));
+
+    let output = cli_output_with(&["tests/example1.csv", "--validate-only"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("5 valid rows, 0 invalid rows\n"));
+}
+
+#[test]
+fn test_cli_partial_disputes() {
+    let output = cli_output_with(&["tests/partial_disputes.csv", "--partial-disputes"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,7.5,2.5,10.0,false
+2,5.0,0,5.0,false
+"
+    );
+
+    // Disputes with amounts are malformed without the option.
+    let output = cli_output_for("tests/partial_disputes.csv");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("dispute tx 1 must not specify amount (got 4.0)"));
+}
+
+#[test]
+fn test_cli_comment_char() {
+    let output = cli_output_with(&["tests/comments.csv", "--comment-char", "#"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0.5,0,0.5,false
+2,2.0,0,2.0,false
+"
+    );
+
+    let output = cli_output_for("tests/comments.csv");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_trace() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("trace.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/example1.csv"),
+        OsStr::new("--trace"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+
+    // The failed withdrawal of tx 5 is not included.
+    let trace = std::fs::read_to_string(&path).expect("Failed to read the trace");
+    assert_eq!(
+        trace,
+        "\
+client,type,tx,amount,available,held,total,locked
+1,deposit,1,1.0,1.0,0,1.0,false
+2,deposit,2,2.0,2.0,0,2.0,false
+1,deposit,3,2.0,3.0,0,3.0,false
+1,withdrawal,4,1.5,1.5,0,1.5,false
+"
+    );
+}
+
+#[test]
+fn test_cli_trace_dispute_expiry() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("trace_expiry.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/dispute_expiry.csv"),
+        OsStr::new("--dispute-expiry"),
+        OsStr::new("600"),
+        OsStr::new("--trace"),
+        path.as_os_str(),
+    ]);
+    assert!(output.status.success());
+
+    // The auto-resolve of tx 1 is recorded after all rows.
+    let trace = std::fs::read_to_string(&path).expect("Failed to read the trace");
+    assert_eq!(
+        trace,
+        "\
+client,type,tx,amount,available,held,total,locked
+1,deposit,1,1.0,1.0,0,1.0,false
+2,deposit,2,2.0,2.0,0,2.0,false
+1,dispute,1,,0.0,1.0,1.0,false
+2,dispute,2,,0.0,2.0,2.0,false
+1,deposit,3,3.0,3.0,1.0,4.0,false
+1,resolve,1,,4.0,0.0,4.0,false
+"
+    );
+}
+
+#[test]
+fn test_cli_multiple_files() {
+    let output = cli_output_with(&["tests/multiple_files1.csv", "tests/multiple_files2.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.0,5.0,6.0,false
+2,0.0,0.0,0.0,true
+"
+    );
+
+    // Disputes refer only to the transactions read before them. Skipped rows
+    // are logged with their files.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("multiple_files_errors.csv");
+    let output = cli_output_with(&[
+        OsStr::new("tests/multiple_files2.csv"),
+        OsStr::new("tests/multiple_files1.csv"),
+        OsStr::new("--log-errors"),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,6.0,0,6.0,false
+2,3.0,0,3.0,false
+"
+    );
+    let errors = std::fs::read_to_string(&path).expect("Failed to read the log of errors");
+    assert_eq!(
+        errors,
+        "\
+file,line,tx,client,type,kind,error
+tests/multiple_files2.csv,3,2,2,dispute,transaction_not_found,transaction not found
+tests/multiple_files2.csv,4,2,2,chargeback,transaction_not_found,transaction not found
+tests/multiple_files2.csv,5,1,1,dispute,transaction_not_found,transaction not found
+"
+    );
+
+    // Files are opened only once they are reached, and errors point to them.
+    let output = cli_output_with(&[
+        "tests/multiple_files1.csv",
+        "tests/multiple_files2.csv",
+        "tests/missing.csv",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tests/missing.csv: No such file"));
+    let output = cli_output_with(&[
+        "tests/multiple_files1.csv",
+        "tests/multiple_files2.csv",
+        "--assert-sorted-by-client",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("tests/multiple_files1.csv: transactions are not sorted by client (line 4)"));
+}
+
+#[test]
+fn test_cli_lock_on_chargeback() {
+    // Transactions on locked accounts are skipped.
+    for (args, expected, code) in [
+        (&[][..], "1,5.0,0.0,5.0,true\n", 2),
+        (
+            &["--lock-on-chargeback", "always"][..],
+            "1,5.0,0.0,5.0,true\n",
+            2,
+        ),
+        (
+            &["--lock-on-chargeback", "never"][..],
+            "1,2.0,0.0,2.0,false\n",
+            0,
+        ),
+        (
+            &["--lock-on-chargeback", "repeat"][..],
+            "1,1.0,0.0,1.0,true\n",
+            2,
+        ),
+    ] {
+        let output = cli_output_with(&[&["tests/lock_on_chargeback.csv"][..], args].concat());
+        assert_eq!(output.status.code(), Some(code));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("client,available,held,total,locked\n{}", expected)
+        );
+    }
+}
+
+#[test]
+fn test_cli_exit_code() {
+    // A clean run.
+    let output = cli_output_for("tests/as_of.csv");
+    assert_eq!(output.status.code(), Some(0));
+
+    // The withdrawal of tx 5 is skipped due to insufficient funds.
+    let output = cli_output_for("tests/example1.csv");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped 1 (no_funds: 1)"));
+
+    // Fatal errors.
+    let output = cli_output_with(&["tests/example1.csv", "--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = cli_output_for("tests/nonexistent.csv");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_cli_currency() {
+    let output = cli_output_with(&["tests/currency.csv", "--currency", "USD"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,4.0,0,4.0,false
+2,2.0,0,2.0,false
+"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("skipped 3 rows in currencies other than USD"));
+    // Rows in other currencies are not applied.
+    assert!(stderr.contains("applied 3, skipped 0, filtered 3"));
+
+    // Without the filter, all rows are applied.
+    let output = cli_output_for("tests/currency.csv");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,4.0,7.0,11.0,false
+2,0.5,0,0.5,false
+"
+    );
+}
+
+#[test]
+fn test_cli_audit() {
+    let output = cli_output_with(&[
+        "tests/partial_disputes.csv",
+        "--partial-disputes",
+        "--audit",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("audit"));
+
+    // Held funds of the initial state don't come from any dispute.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("audit_initial_state.csv");
+    std::fs::write(
+        &path,
+        "client,available,held,total,locked\n1,1.0,0,1.0,false\n3,1.0,2.5,3.5,false\n",
+    )
+    .expect("Failed to write the initial state");
+    let output = cli_output_with(&[
+        OsStr::new("tests/multiple_files1.csv"),
+        OsStr::new("--initial-state"),
+        path.as_os_str(),
+        OsStr::new("--audit"),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("audit: held funds (2.5) of client `3` don't match disputed amounts (0)")
+    );
+    assert!(stderr.contains("1 clients failed the audit"));
+}
+
+#[test]
+fn test_cli_input_format_jsonl() {
+    let expected = cli_output_for("tests/example2.csv");
+    let output = cli_output_with(&["tests/example2.jsonl", "--input-format", "jsonl"]);
+    assert_eq!(output.status.code(), expected.status.code());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&expected.stdout)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.5,0.0,1.5,false
+2,0.0,0.0,0.0,true
+"
+    );
+}
+
+#[test]
+fn test_cli_forbid_zeroing() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("forbid_zeroing.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,2.0\n",
+    )
+    .expect("Failed to write the input file");
+
+    let output = cli_output_for(&path);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,0.0,0,0.0,false\n"
+    );
+
+    let output = cli_output_with(&[path.as_os_str(), OsStr::new("--forbid-zeroing")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,2.0,0,2.0,false\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("zero_balance_forbidden: 1"));
+}
+
+#[test]
+fn test_cli_with_counts() {
+    let output = cli_output_with(&["tests/example2.csv", "--with-counts", "--with-lock-time"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked,locked_at,tx_count
+1,1.5,0.0,1.5,false,,3
+2,0.0,0.0,0.0,true,,1
+"
+    );
+}
+
+#[test]
+fn test_cli_malformed_amount() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("malformed_amount.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,abc\n",
+    )
+    .expect("Failed to write the input file");
+    let output = cli_output_for(&path);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("(line: 3, byte: 38): invalid amount `abc`"));
+}
+
+#[test]
+fn test_cli_max_balance() {
+    // The deposit of tx 3 would make total funds of client 1 exceed 2.5.
+    let output = cli_output_with(&["tests/example1.csv", "--max-balance", "2.5"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.0,0,1.0,false
+2,2.0,0,2.0,false
+"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("balance_cap_exceeded: 1"));
+}
+
+#[test]
+fn test_cli_gzip() {
+    let expected = cli_output_for("tests/example2.csv");
+    let output = cli_output_for("tests/example2.csv.gz");
+    assert_eq!(output.status.code(), expected.status.code());
+    assert_eq!(output.stdout, expected.stdout);
+
+    // Without the extension, decompression has to be requested.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("example2.csv.gzipped");
+    std::fs::copy("tests/example2.csv.gz", &path).expect("Failed to copy the input file");
+    let output = cli_output_for(&path);
+    assert_eq!(output.status.code(), Some(1));
+    let output = cli_output_with(&[path.as_os_str(), OsStr::new("--gzip")]);
+    assert_eq!(output.status.code(), expected.status.code());
+    assert_eq!(output.stdout, expected.stdout);
+}
+
+#[test]
+fn test_cli_gzip_multiple_members() {
+    // Concatenation of two gzip files, the second one with the rows after the
+    // 3rd one.
+    let expected = cli_output_for("tests/example2.csv");
+    let output = cli_output_for("tests/example2_members.csv.gz");
+    assert_eq!(output.status.code(), expected.status.code());
+    assert_eq!(output.stdout, expected.stdout);
+}
+
+#[test]
+fn test_cli_decimal_separator() {
+    let output = cli_output_with(&[
+        "tests/decimal_comma.csv",
+        "--delimiter",
+        ";",
+        "--decimal-separator",
+        "comma",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client;available;held;total;locked
+1;1234.00;0;1234.00;false
+2;2.5;0;2.5;false
+"
+    );
+
+    let output = cli_output_with(&["tests/decimal_comma.csv", "--delimiter", ";"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_cli_hide_empty() {
+    // The stray dispute creates client 3, client 2 withdraws all of its funds.
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("hide_empty.csv");
+    std::fs::write(
+        &path,
+        "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+withdrawal,2,3,2.0
+dispute,3,4,
+",
+    )
+    .expect("Failed to write the input file");
+    let path = path.to_str().expect("Invalid path");
+
+    let output = cli_output_with(&[path]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.0,0,1.0,false
+2,0.0,0,0.0,false
+3,0,0,0,false
+"
+    );
+
+    let output = cli_output_with(&[path, "--hide-empty"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.0,0,1.0,false
+2,0.0,0,0.0,false
+"
+    );
+
+    // Locked accounts are kept, even if they're empty.
+    let state = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("hide_empty_state.csv");
+    std::fs::write(&state, "client,available,held,total,locked\n4,0,0,0,true\n")
+        .expect("Failed to write the initial state");
+    let output = cli_output_with(&[
+        path,
+        "--hide-empty",
+        "--initial-state",
+        state.to_str().expect("Invalid path"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,1.0,0,1.0,false
+2,0.0,0,0.0,false
+4,0,0,0,true
+"
+    );
+}
+
+#[test]
+fn test_cli_checkpoint_interval_resume() {
+    let tmp_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = tmp_dir.join("checkpoint_interval.csv");
+    let checkpoint = tmp_dir.join("checkpoint_interval.json");
+    let _ = std::fs::remove_file(&checkpoint);
+    let args = [
+        input.as_os_str(),
+        OsStr::new("--checkpoint-path"),
+        checkpoint.as_os_str(),
+        OsStr::new("--checkpoint-interval"),
+        OsStr::new("2"),
+    ];
+
+    // The malformed amount in the 5th row interrupts processing after the
+    // state was written following the 4th one.
+    let rows = "\
+type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,2,2,5.0
+deposit,1,3,2.0
+dispute,1,1,
+deposit,2,4,{amount}
+resolve,1,1,
+";
+    std::fs::write(&input, rows.replace("{amount}", "abc"))
+        .expect("Failed to write the input file");
+    let output = cli_output_with(&args);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let state = std::fs::read_to_string(&checkpoint).expect("Failed to read the checkpoint");
+    assert!(state.contains("\"rows_read\":4"));
+
+    // Rows processed before the checkpoint have to be the same.
+    std::fs::write(
+        &input,
+        rows.replace("{amount}", "0.5")
+            .replace("withdrawal,2,2,5.0", "withdrawal,2,2,0.5"),
+    )
+    .expect("Failed to write the input file");
+    let output = cli_output_with(&[&args[..], &[OsStr::new("--resume")]].concat());
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("input differs from the one of the checkpoint in the first 4 rows"));
+
+    // The dispute opened before the checkpoint is resolved after resuming and
+    // the withdrawal skipped before it is still reported by the exit code.
+    std::fs::write(&input, rows.replace("{amount}", "0.5"))
+        .expect("Failed to write the input file");
+    let output = cli_output_with(&[&args[..], &[OsStr::new("--resume")]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("skipped 4 rows processed before the checkpoint"));
+    assert!(stderr.contains("read 6 transactions"));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,3.0,0.0,3.0,false
+2,0.5,0,0.5,false
+"
+    );
+    let state = std::fs::read_to_string(&checkpoint).expect("Failed to read the checkpoint");
+    assert!(state.contains("\"rows_read\":6"));
+
+    // The logs of skipped transactions can't be continued.
+    let output = cli_output_with(
+        &[
+            &args[..],
+            &[
+                OsStr::new("--resume"),
+                OsStr::new("--log-errors"),
+                OsStr::new("errors.csv"),
+            ],
+        ]
+        .concat(),
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("'--resume' cannot be used with '--log-errors <PATH>'"));
+}
+
+#[test]
+fn test_cli_held_sum_overflow() {
+    let tmp_dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = tmp_dir.join("held_sum_overflow.csv");
+    let metrics = tmp_dir.join("held_sum_overflow.json");
+    std::fs::write(
+        &input,
+        "\
+type,client,tx,amount
+deposit,1,1,40000000000000000000000000000
+deposit,2,2,40000000000000000000000000000
+dispute,1,1,
+dispute,2,2,
+",
+    )
+    .expect("Failed to write the input file");
+
+    // Held funds of each client are valid, only their sum overflows.
+    let output = cli_output_with(&[
+        input.as_os_str(),
+        OsStr::new("--metrics-json"),
+        metrics.as_os_str(),
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("held funds overflowing"));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,0,40000000000000000000000000000,40000000000000000000000000000,false
+2,0,40000000000000000000000000000,40000000000000000000000000000,false
+"
+    );
+    let metrics = std::fs::read_to_string(&metrics).expect("Failed to read the metrics");
+    assert!(metrics.contains("\"held\": null"));
+}
+
+#[test]
+fn test_cli_skip_undisputable() {
+    // Disputes of adjustments abort the processing by default.
+    let output = cli_output_for("tests/undisputable.csv");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("transactions of type `adjustment` cannot be disputed"));
+
+    let output = cli_output_with(&["tests/undisputable.csv", "--skip-undisputable"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+client,available,held,total,locked
+1,4.5,0,4.5,false
+"
+    );
+}
+
+/// Compares the output for transactions read from a PostgreSQL database with
+/// the output for the same transactions read from a CSV file. Requires the URL
+/// of a database, where a test table can be created, in the
+/// `TRANZAKTIONZ_POSTGRES_URL` environment variable.
+#[cfg(feature = "postgres")]
+#[test]
+#[ignore = "requires a PostgreSQL database"]
+fn test_cli_source_postgres() {
+    let url = std::env::var("TRANZAKTIONZ_POSTGRES_URL")
+        .expect("Failed to get the database URL from TRANZAKTIONZ_POSTGRES_URL");
+    let mut db = postgres::Client::connect(&url, postgres::NoTls)
+        .expect("Failed to connect to the database");
+    db.batch_execute(
+        "DROP TABLE IF EXISTS tranzaktionz_test;
+        CREATE TABLE tranzaktionz_test (
+            id serial PRIMARY KEY,
+            type text NOT NULL,
+            client integer NOT NULL,
+            tx bigint NOT NULL,
+            amount numeric
+        );",
+    )
+    .expect("Failed to create the test table");
+
+    for file in ["tests/example1.csv", "tests/example2.csv"] {
+        db.batch_execute("TRUNCATE tranzaktionz_test")
+            .expect("Failed to truncate the test table");
+        let data = std::fs::read_to_string(file).expect("Failed to read the input file");
+        for line in data.lines().skip(1) {
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            let client: i32 = fields[1].parse().expect("Failed to parse client");
+            let tx: i64 = fields[2].parse().expect("Failed to parse tx");
+            let amount = (!fields[3].is_empty()).then(|| fields[3]);
+            db.execute(
+                "INSERT INTO tranzaktionz_test (type, client, tx, amount)
+                VALUES ($1, $2, $3, $4::text::numeric)",
+                &[&fields[0], &client, &tx, &amount],
+            )
+            .expect("Failed to insert a transaction");
+        }
+
+        let output = cli_output_with(&[
+            "--source",
+            &url,
+            "--query",
+            "SELECT type, client, tx, amount FROM tranzaktionz_test ORDER BY id",
+        ]);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, cli_output_for(file).stdout);
+    }
+
+    db.batch_execute("DROP TABLE tranzaktionz_test")
+        .expect("Failed to drop the test table");
+}